@@ -0,0 +1,3209 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration; 
+use std::time::Instant;
+
+use crate::cli;
+use crate::control;
+use crate::cooling;
+use crate::gpio;
+use crate::ini;
+use crate::logger;
+use crate::pwm;
+use crate::replay;
+use crate::sensor;
+use crate::signal;
+use crate::simulate;
+use crate::unit;
+#[cfg(feature = "metrics")]
+use crate::metrics;
+#[cfg(feature = "dbus")]
+use crate::dbus;
+#[cfg(feature = "toml")]
+use crate::toml_file;
+
+use crate::event::FanEvent;
+use logger::LogFormat;
+use gpio::GpioFanDevice;
+use cooling::CoolingDeviceOutput;
+use cli::CommandLine;
+use control::Control;
+use control::ControlKind;
+use control::ControlOutput;
+use control::Curve;
+use control::DutyBand;
+use control::CurvePoints;
+use control::Function;
+use control::Pid;
+use control::Regulator;
+use control::Rule;
+use ini::FieldParseError;
+use ini::FieldParseErrorKind;
+use ini::Ini;
+use crate::pidfile::PidFile;
+use pwm::FanOutput;
+use pwm::NullPWMDevice;
+use pwm::PWMDevice;
+use pwm::Polarity;
+use replay::Replay;
+use signal::Signal;
+use sensor::CommandSensor;
+use sensor::CoolingDevice;
+use sensor::SensorDevice;
+use sensor::SensorSource;
+use crate::state::StateFile;
+use simulate::Summary;
+use crate::status::Status;
+use crate::status::StatusServer;
+use unit::Temperature;
+use unit::TemperatureDelta;
+use unit::TemperatureUnit;
+use crate::watchdog::Watchdog;
+use crate::watchdog::WatchdogOutput;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CriticalAction {
+    /// keep the fan at maximum speed
+    MaxFan,
+    /// power off after `critical_time_cycle` critical cycles
+    Poweroff,
+}
+
+impl std::str::FromStr for CriticalAction {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "maxfan" => Ok(CriticalAction::MaxFan),
+            "poweroff" => Ok(CriticalAction::Poweroff),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown critical action: {}", s))),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StuckAction {
+    /// only log the stuck sensor
+    Warn,
+    /// keep the fan at maximum speed until the reading changes
+    MaxFan,
+}
+
+impl std::str::FromStr for StuckAction {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "warn" => Ok(StuckAction::Warn),
+            "maxfan" => Ok(StuckAction::MaxFan),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown stuck sensor action: {}", s))),
+        }
+    }
+}
+
+
+/// `cpu_affinity`: CPU indices like `4`, a list with ranges like `4-7,0`, or a hexadecimal mask like `0xf0`
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CpuAffinity {
+    cpus: Vec<usize>,
+}
+
+impl std::str::FromStr for CpuAffinity {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("invalid cpu affinity: {}", s));
+        let mut cpus = Vec::new();
+        if let Some(mask) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            let mask = u128::from_str_radix(mask, 16).map_err(|_e| invalid())?;
+            cpus.extend((0..128).filter(|cpu| mask & (1 << cpu) != 0));
+        } else {
+            for item in s.split(',') {
+                let (first, last): (usize, usize) = match item.split_once('-') {
+                    Some((first, last)) => (first.trim().parse().map_err(|_e| invalid())?, last.trim().parse().map_err(|_e| invalid())?),
+                    None => {
+                        let cpu = item.trim().parse().map_err(|_e| invalid())?;
+                        (cpu, cpu)
+                    }
+                };
+                if first > last {
+                    return Err(invalid());
+                }
+                cpus.extend(first..=last);
+            }
+        }
+        cpus.sort_unstable();
+        cpus.dedup();
+        if cpus.is_empty() || cpus.iter().any(|&cpu| cpu >= libc::CPU_SETSIZE as usize) {
+            return Err(invalid());
+        }
+        Ok(Self { cpus })
+    }
+}
+
+impl std::fmt::Display for CpuAffinity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let cpus: Vec<String> = self.cpus.iter().map(usize::to_string).collect();
+        write!(f, "{}", cpus.join(","))
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TerminateAction {
+    /// disable the pwm
+    Stop,
+    /// leave the fan at maximum speed, e.g. while the rest of the system shuts down
+    MaxFan,
+    /// exit without touching the pwm
+    Leave,
+}
+
+impl std::str::FromStr for TerminateAction {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stop" => Ok(TerminateAction::Stop),
+            "maxfan" => Ok(TerminateAction::MaxFan),
+            "leave" => Ok(TerminateAction::Leave),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown terminate action: {}", s))),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FanKind {
+    /// a pwm channel of `execute`
+    Pwm,
+    /// a fan switched on and off by the gpio line `gpio`
+    Gpio,
+    /// the `cur_state` of the thermal cooling device `cooling_device`
+    CoolingDevice,
+}
+
+impl std::str::FromStr for FanKind {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pwm" => Ok(FanKind::Pwm),
+            "gpio" => Ok(FanKind::Gpio),
+            "cooling_device" => Ok(FanKind::CoolingDevice),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown fan kind: {}", s))),
+        }
+    }
+}
+
+
+/// curve parameters, shared by the top-level section and `[quiet]`
+#[derive(Debug, Clone)]
+struct CurveArgs {
+    stop_temperature: f32,
+    start_temperature: f32,
+    high_temperature: f32,
+    min_duty_cycle: f32,
+    max_duty_cycle: f32,
+    curve: Option<CurvePoints>,
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SignalAction {
+    /// only log the signal
+    Status,
+    /// swap between the primary curve and the `[quiet]` curve
+    ToggleQuiet,
+    /// end a SIGUSR2 max speed early
+    ClearBoost,
+}
+
+impl std::str::FromStr for SignalAction {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "status" => Ok(SignalAction::Status),
+            "toggle_quiet" => Ok(SignalAction::ToggleQuiet),
+            "clear_boost" => Ok(SignalAction::ClearBoost),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown signal action: {}", s))),
+        }
+    }
+}
+
+
+/// what a received signal asks for, dispatched from the main loop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignalCommand {
+    Terminate,
+    Boost,
+    Reload,
+    ClearBoost,
+    /// `sigusr1_action`
+    Sigusr1,
+    /// logged only
+    Ignore,
+}
+
+/// a signal key: a signal, or `none`
+fn parse_signal(s: Option<&str>, field: &'static str) -> Result<Option<Signal>, FieldParseError> {
+    let s = FieldParseError::parse(s, field)?.trim();
+    if s == "none" {
+        return Ok(None);
+    }
+    s.parse().map(Some).map_err(|e: io::Error| match e.kind() {
+        io::ErrorKind::InvalidInput => FieldParseError::out_of_range(field, e.to_string()),
+        _ => FieldParseError::new(field, FieldParseErrorKind::Unparsable),
+    })
+}
+
+
+#[derive(Debug, Clone)]
+pub struct Args {
+
+    /// Path to the sensor device; like "/sys/class/thermal/thermal_zone0"
+    watch: PathBuf,
+
+    /// Path to the pwm device; like "/sys/devices/platform/fd8b0010.pwm/pwm/pwmchip1"
+    execute: PathBuf,
+
+    /// Interval between temperature checks, like `5s` or `500ms`; a bare number is milliseconds
+    interval: Duration,
+
+    /// Time before the pwm change when temperature drop, in times of interval
+    max_speed_time_cycle: usize,
+
+    /// Time before the pwm change when temperature drop, in times of interval
+    lag_time_cycle: usize,
+
+    /// Temperature to stop the pwm, in degrees Celsius
+    stop_temperature: f32,
+
+    /// Temperature to start the pwm, in degrees Celsius
+    start_temperature: f32,
+
+    /// Temperature when pwm should reach maximum, in degrees Celsius
+    high_temperature: f32,
+
+    /// Minimum duty cycle, in (0, 1)
+    min_duty_cycle: f32,
+
+    /// Maximum duty cycle, in (0, 1)
+    max_duty_cycle: f32,
+
+    /// PWM frequency, in Hz
+    pwm_frequency: u32,
+
+    /// Path to the pid file; locked while running to prevent duplicate instances
+    pidfile: Option<PathBuf>,
+
+    /// Window after a max speed engage during which repeated engages log at debug, in times of interval; 0 to disable
+    max_speed_log_window_cycle: usize,
+
+    /// Log the intended pwm writes instead of touching sysfs
+    dry_run: bool,
+
+    /// Control policy; `hysteresis` (curve with lag state machine), `stateless` (curve only) or `pid` (closed loop to setpoint)
+    control: ControlKind,
+
+    /// Target temperature for `control = pid`, in degrees Celsius
+    setpoint: f32,
+
+    /// Proportional gain for `control = pid`, in duty per degree
+    kp: f32,
+
+    /// Integral gain for `control = pid`, in duty per degree-second
+    ki: f32,
+
+    /// Derivative gain for `control = pid`, in duty per degree/second
+    kd: f32,
+
+    /// Read back period and polarity after initialization and report remediation on mismatch; off by default
+    verify_pwm: bool,
+
+    /// Read back enable after each start, writing period, duty cycle and enable once more before failing the start
+    /// if it did not take; on by default
+    verify_enable: bool,
+
+    /// Piecewise-linear fan curve as `temperature:duty_cycle` points, replacing start/high temperature and min/max duty cycle
+    curve: Option<CurvePoints>,
+
+    /// Minimum time the fan stays on once started, in times of interval
+    min_on_cycles: usize,
+
+    /// Action on SIGUSR1; `status` (log only), `toggle_quiet` (swap to the `[quiet]` curve and back) or `clear_boost` (end a SIGUSR2 max speed)
+    sigusr1_action: SignalAction,
+
+    /// Degrees below stop_temperature the temperature must fall before the fan stops
+    stop_hysteresis: f32,
+
+    /// Lower bound of the duty cycle written to the pwm, in nanoseconds
+    min_duty_ns: Option<u32>,
+
+    /// Upper bound of the duty cycle written to the pwm, in nanoseconds
+    max_duty_ns: Option<u32>,
+
+    /// Temperature above which the fan is forced to max duty every cycle, in degrees Celsius
+    critical_temperature: Option<f32>,
+
+    /// Action above critical_temperature; `maxfan` or `poweroff`
+    critical_action: CriticalAction,
+
+    /// Critical readings before `critical_action = poweroff` powers off, in times of interval
+    critical_time_cycle: usize,
+
+    /// Path to a unix socket answering each connection with a one-line JSON status
+    status_socket: Option<PathBuf>,
+
+    /// Nice value applied at startup, from -20 (highest priority) to 19 (lowest)
+    process_nice: Option<i32>,
+
+    /// Address serving `GET /metrics` in Prometheus text format; needs the `metrics` feature
+    metrics_address: Option<String>,
+
+    /// Forbidden duty cycle bands of the `[avoid]` section, as repeated `band = low-high`
+    avoid: Vec<DutyBand>,
+
+    /// Cooling device whose nonzero `cur_state` suppresses fan increases for up to defer_time_cycle
+    defer_cooling_device: Option<PathBuf>,
+
+    /// Longest time fan increases are deferred to an active defer_cooling_device, in times of interval
+    defer_time_cycle: usize,
+
+    /// Cycles over which the duty cycle steps down to min_duty_cycle before the fan stops; 0 stops at once
+    rampdown_cycles: usize,
+
+    /// Duty cycle of the kick given to a stopped fan before the computed duty cycle
+    spinup_duty: f32,
+
+    /// Length of the spin-up kick in times of interval; 0 disables it
+    spinup_cycles: usize,
+
+    /// Divisor turning the raw sensor reading into degrees Celsius
+    sensor_scale: f32,
+
+    /// Identical consecutive readings while the fan is on before the sensor is reported stuck; 0 disables the check
+    stuck_sensor_cycles: usize,
+
+    /// Action on a stuck sensor; `warn` or `maxfan`
+    stuck_sensor_action: StuckAction,
+
+    /// Weight of the current temperature when the kept temperature follows a falling one, in (0, 1]
+    keep_decay: f32,
+
+    /// Write `1 - duty_cycle` of the period, for fans where a higher duty means less airflow
+    invert_duty: bool,
+
+    /// Force every pwm to its maximum when the control loop has not completed a cycle for this many intervals; 0 disables
+    watchdog_cycles: usize,
+
+    /// Shell command printing the temperature, read instead of `watch` each cycle
+    sensor_command: Option<String>,
+
+    /// Time after which `sensor_command` is killed, in milliseconds
+    sensor_command_timeout: u64,
+
+    /// File every log line is also appended to
+    log_file: Option<PathBuf>,
+
+    /// Size after which `log_file` is rotated to `log_file.1`; 0 never rotates
+    log_max_bytes: u64,
+
+    /// `text`, or `json` for one JSON object per line with the control loop's `temp`, `duty` and `state` as fields
+    log_format: LogFormat,
+
+    /// Interval used while every fan is idle, like `interval`
+    idle_interval: Option<Duration>,
+
+    /// Consecutive readings within `idle_delta` with the fan off before the fan counts as idle
+    idle_cycles: usize,
+
+    /// Largest temperature change still counted as stable for `idle_cycles`
+    idle_delta: f32,
+
+    /// Time to keep retrying a pwm chip that is not ready at startup, in milliseconds
+    startup_delay_ms: u64,
+
+    /// Duty cycle at startup instead of the curve at the current temperature
+    initial_duty: Option<f32>,
+
+    /// Calibration written to the zone `offset` file at startup, in raw sensor units
+    sensor_offset: Option<i32>,
+
+    /// Control state of every fan, saved on exit and by `--once`, resumed at startup
+    state_file: Option<PathBuf>,
+
+    /// Smallest duty cycle change written while the temperature rises
+    duty_deadband: f32,
+
+    /// Actuator: a pwm channel or an on/off gpio line
+    fan_kind: FanKind,
+
+    /// Global sysfs number of the gpio line with `fan_kind = gpio`
+    gpio: Option<u32>,
+
+    /// Cycles between summary lines at info level, 0 for none
+    summary_every: usize,
+
+    /// What SIGINT/SIGTERM leave the pwm at
+    terminate_action: TerminateAction,
+
+    /// Thermal cooling device driven with `fan_kind = cooling_device`
+    cooling_device: Option<PathBuf>,
+
+    /// Largest change of the applied duty cycle per cycle while running, as a fraction; 0 for no limit
+    max_duty_slew: f32,
+
+    /// Lowest duty cycle a stopped fan is started with
+    min_start_duty: Option<f32>,
+
+    /// Lowest duty cycle of a running fan
+    min_run_duty: Option<f32>,
+
+    /// CPUs the daemon runs on, applied at startup
+    cpu_affinity: Option<CpuAffinity>,
+
+    /// Signal that runs the fans at maximum speed; `none` to ignore it
+    signal_boost: Option<Signal>,
+
+    /// Signal that reloads the curve parameters; `none` to ignore it
+    signal_reload: Option<Signal>,
+
+    /// Signal that ends a maximum speed early; `none` to ignore it
+    signal_clear_boost: Option<Signal>,
+
+    /// Degrees below the zone's `critical` trip point used as critical_temperature when that is not set
+    critical_trip_margin: Option<f32>,
+
+    /// Unit of the temperature keys that follow it; `celsius` or `fahrenheit`
+    temperature_unit: TemperatureUnit,
+
+    /// Show temperatures in the log in degrees Fahrenheit
+    log_fahrenheit: bool,
+
+    /// Consecutive failed sensor reads before the fan runs at sensor_fail_duty; 0 disables it
+    sensor_fail_cycles: usize,
+
+    /// Duty cycle while the sensor cannot be read; `None` takes max_duty_cycle
+    sensor_fail_duty: Option<f32>,
+
+    /// Fail on keys that match no known field instead of warning about them
+    strict: bool,
+
+    /// Serve `dev.fanctrl.Rock5b` on the system bus; needs the `dbus` feature
+    dbus: bool,
+
+    /// Curve parameters of the `[quiet]` section, toggled by `sigusr1_action = toggle_quiet`
+    quiet: Option<CurveArgs>,
+
+    /// Read the real sensor but only log the pwm writes
+    observe: bool,
+
+    /// `[fan.NAME]` sections, each starting from a copy of the keys set above it
+    fans: Vec<(String, Args)>,
+
+    /// keys that match no known field, with their line; warned about or, with `strict`, rejected once the file is read
+    unknown_keys: Vec<FieldParseError>,
+}
+
+
+impl Default for Args {
+    fn default() -> Self {
+        Self {
+            watch: PathBuf::new(),
+            execute: PathBuf::new(),
+            interval: Duration::from_secs(5),
+            max_speed_time_cycle: 32,
+            lag_time_cycle: 8,
+            stop_temperature: 30.0,
+            start_temperature: 40.0,
+            high_temperature: 70.0,
+            min_duty_cycle: 0.5,
+            max_duty_cycle: 0.9,
+            pwm_frequency: 10000,
+            pidfile: None,
+            max_speed_log_window_cycle: 0,
+            dry_run: false,
+            control: ControlKind::Hysteresis,
+            setpoint: 55.0,
+            kp: 0.05,
+            ki: 0.002,
+            kd: 0.0,
+            verify_pwm: false,
+            verify_enable: true,
+            curve: None,
+            min_on_cycles: 0,
+            sigusr1_action: SignalAction::Status,
+            stop_hysteresis: 0.0,
+            min_duty_ns: None,
+            max_duty_ns: None,
+            critical_temperature: None,
+            critical_action: CriticalAction::MaxFan,
+            critical_time_cycle: 3,
+            status_socket: None,
+            process_nice: None,
+            metrics_address: None,
+            avoid: Vec::new(),
+            defer_cooling_device: None,
+            defer_time_cycle: 12,
+            rampdown_cycles: 0,
+            spinup_duty: 1.0,
+            spinup_cycles: 0,
+            sensor_scale: 1000.0,
+            stuck_sensor_cycles: 0,
+            stuck_sensor_action: StuckAction::Warn,
+            keep_decay: 0.5,
+            invert_duty: false,
+            watchdog_cycles: 0,
+            sensor_command: None,
+            sensor_command_timeout: 2000,
+            log_file: None,
+            log_max_bytes: 0,
+            log_format: LogFormat::Text,
+            idle_interval: None,
+            idle_cycles: 6,
+            idle_delta: 0.5,
+            startup_delay_ms: 0,
+            initial_duty: None,
+            sensor_offset: None,
+            state_file: None,
+            duty_deadband: 0.0,
+            fan_kind: FanKind::Pwm,
+            gpio: None,
+            summary_every: 0,
+            terminate_action: TerminateAction::Stop,
+            cooling_device: None,
+            max_duty_slew: 0.0,
+            min_start_duty: None,
+            min_run_duty: None,
+            cpu_affinity: None,
+            signal_boost: Some(Signal(libc::SIGUSR2)),
+            signal_reload: Some(Signal(libc::SIGHUP)),
+            signal_clear_boost: None,
+            critical_trip_margin: None,
+            temperature_unit: TemperatureUnit::Celsius,
+            log_fahrenheit: false,
+            sensor_fail_cycles: 3,
+            sensor_fail_duty: None,
+            strict: false,
+            dbus: false,
+            quiet: None,
+            observe: false,
+            fans: Vec::new(),
+            unknown_keys: Vec::new(),
+        }
+    }
+}
+
+impl Args {
+
+    /// keys accepted both in the config file and as `--key value` on the command line
+    pub const KEYS: &'static [&'static str] = &[
+        "watch",
+        "execute",
+        "pwmchip",
+        "interval",
+        "max_speed_time_cycle",
+        "lag_time_cycle",
+        "stop_temperature",
+        "start_temperature",
+        "high_temperature",
+        "min_duty_cycle",
+        "max_duty_cycle",
+        "pwm_frequency",
+        "pidfile",
+        "max_speed_log_window_cycle",
+        "dry_run",
+        "control",
+        "setpoint",
+        "kp",
+        "ki",
+        "kd",
+        "verify_pwm",
+        "verify_enable",
+        "curve",
+        "min_on_cycles",
+        "sigusr1_action",
+        "stop_hysteresis",
+        "min_duty_ns",
+        "max_duty_ns",
+        "critical_temperature",
+        "critical_action",
+        "critical_time_cycle",
+        "status_socket",
+        "process_nice",
+        "nice",
+        "metrics_address",
+        "defer_cooling_device",
+        "defer_time_cycle",
+        "rampdown_cycles",
+        "spinup_duty",
+        "spinup_cycles",
+        "sensor_scale",
+        "stuck_sensor_cycles",
+        "stuck_sensor_action",
+        "keep_decay",
+        "invert_duty",
+        "watchdog_cycles",
+        "sensor_command",
+        "sensor_command_timeout",
+        "log_file",
+        "log_max_bytes",
+        "log_format",
+        "idle_interval",
+        "idle_cycles",
+        "idle_delta",
+        "startup_delay_ms",
+        "initial_duty",
+        "sensor_offset",
+        "state_file",
+        "duty_deadband",
+        "fan_kind",
+        "gpio",
+        "summary_every",
+        "terminate_action",
+        "cooling_device",
+        "max_duty_slew",
+        "min_start_duty",
+        "min_run_duty",
+        "cpu_affinity",
+        "signal_boost",
+        "signal_reload",
+        "signal_clear_boost",
+        "critical_trip_margin",
+        "temperature_unit",
+        "log_fahrenheit",
+        "sensor_fail_cycles",
+        "sensor_fail_duty",
+        "strict",
+        "dbus",
+    ];
+
+    /// keys of the `[quiet]` section
+    const QUIET_KEYS: &'static [&'static str] = &[
+        "stop_temperature",
+        "start_temperature",
+        "high_temperature",
+        "min_duty_cycle",
+        "max_duty_cycle",
+        "curve",
+    ];
+
+    /// keys read by `FieldParseError::parse_duration`, whose bare numbers are deprecated
+    const DURATION_KEYS: &'static [&'static str] = &["interval", "idle_interval"];
+
+    /// keys shared by all fans, ignored in `[fan.NAME]` sections
+    const GLOBAL_KEYS: &'static [&'static str] = &[
+        "interval",
+        "pidfile",
+        "sigusr1_action",
+        "status_socket",
+        "process_nice",
+        "nice",
+        "cpu_affinity",
+        "signal_boost",
+        "signal_reload",
+        "signal_clear_boost",
+        "log_fahrenheit",
+        "metrics_address",
+        "dbus",
+        "watchdog_cycles",
+        "log_file",
+        "log_max_bytes",
+        "log_format",
+        "idle_interval",
+        "state_file",
+        "strict",
+    ];
+
+    fn curve_args(&self) -> CurveArgs {
+        CurveArgs {
+            stop_temperature: self.stop_temperature,
+            start_temperature: self.start_temperature,
+            high_temperature: self.high_temperature,
+            min_duty_cycle: self.min_duty_cycle,
+            max_duty_cycle: self.max_duty_cycle,
+            curve: self.curve.clone(),
+        }
+    }
+
+    pub fn apply_overrides(&mut self, overrides: &[(String, String)]) -> Result<(), FieldParseError> {
+        for (key, value) in overrides {
+            self.callback(Path::new("<command line>"), value, 0, "", key, Some(value))?;
+        }
+        Ok(())
+    }
+}
+
+impl Args {
+
+    /// whether `apply` reads `key` in `section`
+    fn is_known(section: &str, key: &str) -> bool {
+        match section {
+            "" => Self::KEYS.contains(&key),
+            "quiet" => Self::QUIET_KEYS.contains(&key),
+            "avoid" => key == "band",
+            _ => section.starts_with("fan.") && Self::KEYS.contains(&key),
+        }
+    }
+
+    /// logs every key that matched no known field; with `strict` the first one is the error
+    fn check_unknown_keys(&self) -> Result<(), FieldParseError> {
+        for e in self.unknown_keys.iter() {
+            log::warn!("{}", e);
+        }
+        match self.unknown_keys.first() {
+            Some(e) if self.strict => Err(e.clone()),
+            _ => Ok(()),
+        }
+    }
+
+    fn apply(&mut self, section: &str, key: &str, value: Option<&str>) -> Result<(), FieldParseError> {
+        if section.is_empty() {
+            match key {
+                "watch" => self.watch = PathBuf::from(FieldParseError::parse(value, "watch")?),
+                "execute" => self.execute = PathBuf::from(FieldParseError::parse(value, "execute")?),
+                // the later of `execute` and `pwmchip` wins, also over one inherited by a `[fan.NAME]` section
+                "pwmchip" => self.execute = pwm::chip_path(FieldParseError::parse_value(value, "pwmchip")?),
+                "interval" => self.interval = FieldParseError::parse_duration(value, "interval")?,
+                "max_speed_time_cycle" => self.max_speed_time_cycle = FieldParseError::parse_value(value, "max_speed_time_cycle")?,
+                "lag_time_cycle" => self.lag_time_cycle = FieldParseError::parse_value(value, "lag_time_cycle")?,
+                "stop_temperature" => self.stop_temperature = self.temperature_unit.to_celsius(FieldParseError::parse_value(value, "stop_temperature")?),
+                "start_temperature" => self.start_temperature = self.temperature_unit.to_celsius(FieldParseError::parse_value(value, "start_temperature")?),
+                "high_temperature" => self.high_temperature = self.temperature_unit.to_celsius(FieldParseError::parse_value(value, "high_temperature")?),
+                "min_duty_cycle" => self.min_duty_cycle = FieldParseError::parse_fraction(value, "min_duty_cycle")?,
+                "max_duty_cycle" => self.max_duty_cycle = FieldParseError::parse_fraction(value, "max_duty_cycle")?,
+                "pwm_frequency" => self.pwm_frequency = FieldParseError::parse_value(value, "pwm_frequency")?,
+                "pidfile" => self.pidfile = Some(PathBuf::from(FieldParseError::parse(value, "pidfile")?)),
+                "max_speed_log_window_cycle" => self.max_speed_log_window_cycle = FieldParseError::parse_value(value, "max_speed_log_window_cycle")?,
+                "dry_run" => self.dry_run = FieldParseError::parse_value(value, "dry_run")?,
+                "control" => self.control = FieldParseError::parse_value(value, "control")?,
+                "setpoint" => self.setpoint = self.temperature_unit.to_celsius(FieldParseError::parse_value(value, "setpoint")?),
+                "kp" => self.kp = FieldParseError::parse_value(value, "kp")?,
+                "ki" => self.ki = FieldParseError::parse_value(value, "ki")?,
+                "kd" => self.kd = FieldParseError::parse_value(value, "kd")?,
+                "verify_pwm" => self.verify_pwm = FieldParseError::parse_value(value, "verify_pwm")?,
+                "verify_enable" => self.verify_enable = FieldParseError::parse_value(value, "verify_enable")?,
+                "curve" => self.curve = Some(FieldParseError::parse_value::<CurvePoints>(value, "curve")?.map_temperatures(|t| self.temperature_unit.to_celsius(t))),
+                "min_on_cycles" => self.min_on_cycles = FieldParseError::parse_value(value, "min_on_cycles")?,
+                "sigusr1_action" => self.sigusr1_action = FieldParseError::parse_value(value, "sigusr1_action")?,
+                "stop_hysteresis" => self.stop_hysteresis = self.temperature_unit.delta_to_celsius(FieldParseError::parse_value(value, "stop_hysteresis")?),
+                "min_duty_ns" => self.min_duty_ns = Some(FieldParseError::parse_value(value, "min_duty_ns")?),
+                "max_duty_ns" => self.max_duty_ns = Some(FieldParseError::parse_value(value, "max_duty_ns")?),
+                "critical_temperature" => self.critical_temperature = Some(self.temperature_unit.to_celsius(FieldParseError::parse_value(value, "critical_temperature")?)),
+                "critical_action" => self.critical_action = FieldParseError::parse_value(value, "critical_action")?,
+                "critical_time_cycle" => self.critical_time_cycle = FieldParseError::parse_value(value, "critical_time_cycle")?,
+                "status_socket" => self.status_socket = Some(PathBuf::from(FieldParseError::parse(value, "status_socket")?)),
+                "process_nice" | "nice" => self.process_nice = Some(FieldParseError::parse_value(value, "process_nice")?),
+                "metrics_address" => self.metrics_address = Some(String::from(FieldParseError::parse(value, "metrics_address")?)),
+                "defer_cooling_device" => self.defer_cooling_device = Some(PathBuf::from(FieldParseError::parse(value, "defer_cooling_device")?)),
+                "defer_time_cycle" => self.defer_time_cycle = FieldParseError::parse_value(value, "defer_time_cycle")?,
+                "rampdown_cycles" => self.rampdown_cycles = FieldParseError::parse_value(value, "rampdown_cycles")?,
+                "spinup_duty" => self.spinup_duty = FieldParseError::parse_fraction(value, "spinup_duty")?,
+                "spinup_cycles" => self.spinup_cycles = FieldParseError::parse_value(value, "spinup_cycles")?,
+                "sensor_scale" => self.sensor_scale = FieldParseError::parse_value(value, "sensor_scale")?,
+                "stuck_sensor_cycles" => self.stuck_sensor_cycles = FieldParseError::parse_value(value, "stuck_sensor_cycles")?,
+                "stuck_sensor_action" => self.stuck_sensor_action = FieldParseError::parse_value(value, "stuck_sensor_action")?,
+                "keep_decay" => self.keep_decay = FieldParseError::parse_value(value, "keep_decay")?,
+                "invert_duty" => self.invert_duty = FieldParseError::parse_value(value, "invert_duty")?,
+                "watchdog_cycles" => self.watchdog_cycles = FieldParseError::parse_value(value, "watchdog_cycles")?,
+                "sensor_command" => self.sensor_command = Some(String::from(FieldParseError::parse(value, "sensor_command")?)),
+                "sensor_command_timeout" => self.sensor_command_timeout = FieldParseError::parse_value(value, "sensor_command_timeout")?,
+                "log_file" => self.log_file = Some(PathBuf::from(FieldParseError::parse(value, "log_file")?)),
+                "log_max_bytes" => self.log_max_bytes = FieldParseError::parse_value(value, "log_max_bytes")?,
+                "log_format" => self.log_format = FieldParseError::parse_value(value, "log_format")?,
+                "idle_interval" => self.idle_interval = Some(FieldParseError::parse_duration(value, "idle_interval")?),
+                "idle_cycles" => self.idle_cycles = FieldParseError::parse_value(value, "idle_cycles")?,
+                "idle_delta" => self.idle_delta = self.temperature_unit.delta_to_celsius(FieldParseError::parse_value(value, "idle_delta")?),
+                "startup_delay_ms" => self.startup_delay_ms = FieldParseError::parse_value(value, "startup_delay_ms")?,
+                "initial_duty" => self.initial_duty = Some(FieldParseError::parse_fraction(value, "initial_duty")?),
+                "sensor_offset" => self.sensor_offset = Some(FieldParseError::parse_value(value, "sensor_offset")?),
+                "state_file" => self.state_file = Some(PathBuf::from(FieldParseError::parse(value, "state_file")?)),
+                "duty_deadband" => self.duty_deadband = FieldParseError::parse_fraction(value, "duty_deadband")?,
+                "fan_kind" => self.fan_kind = FieldParseError::parse_value(value, "fan_kind")?,
+                "gpio" => self.gpio = Some(FieldParseError::parse_value(value, "gpio")?),
+                "summary_every" => self.summary_every = FieldParseError::parse_value(value, "summary_every")?,
+                "terminate_action" => self.terminate_action = FieldParseError::parse_value(value, "terminate_action")?,
+                "cooling_device" => self.cooling_device = Some(PathBuf::from(FieldParseError::parse(value, "cooling_device")?)),
+                "max_duty_slew" => self.max_duty_slew = FieldParseError::parse_fraction(value, "max_duty_slew")?,
+                "min_start_duty" => self.min_start_duty = Some(FieldParseError::parse_fraction(value, "min_start_duty")?),
+                "min_run_duty" => self.min_run_duty = Some(FieldParseError::parse_fraction(value, "min_run_duty")?),
+                "cpu_affinity" => self.cpu_affinity = Some(FieldParseError::parse_value(value, "cpu_affinity")?),
+                "signal_boost" => self.signal_boost = parse_signal(value, "signal_boost")?,
+                "signal_reload" => self.signal_reload = parse_signal(value, "signal_reload")?,
+                "signal_clear_boost" => self.signal_clear_boost = parse_signal(value, "signal_clear_boost")?,
+                "critical_trip_margin" => self.critical_trip_margin = Some(self.temperature_unit.delta_to_celsius(FieldParseError::parse_value(value, "critical_trip_margin")?)),
+                "temperature_unit" => self.temperature_unit = FieldParseError::parse_value(value, "temperature_unit")?,
+                "log_fahrenheit" => self.log_fahrenheit = FieldParseError::parse_value(value, "log_fahrenheit")?,
+                "sensor_fail_cycles" => self.sensor_fail_cycles = FieldParseError::parse_value(value, "sensor_fail_cycles")?,
+                "sensor_fail_duty" => self.sensor_fail_duty = Some(FieldParseError::parse_fraction(value, "sensor_fail_duty")?),
+                "strict" => self.strict = FieldParseError::parse_value(value, "strict")?,
+                "dbus" => self.dbus = FieldParseError::parse_value(value, "dbus")?,
+                _ => {}
+            }
+        } else if section == "quiet" {
+            let defaults = self.curve_args();
+            let unit = self.temperature_unit;
+            let quiet = self.quiet.get_or_insert(defaults);
+            match key {
+                "stop_temperature" => quiet.stop_temperature = unit.to_celsius(FieldParseError::parse_value(value, "stop_temperature")?),
+                "start_temperature" => quiet.start_temperature = unit.to_celsius(FieldParseError::parse_value(value, "start_temperature")?),
+                "high_temperature" => quiet.high_temperature = unit.to_celsius(FieldParseError::parse_value(value, "high_temperature")?),
+                "min_duty_cycle" => quiet.min_duty_cycle = FieldParseError::parse_fraction(value, "min_duty_cycle")?,
+                "max_duty_cycle" => quiet.max_duty_cycle = FieldParseError::parse_fraction(value, "max_duty_cycle")?,
+                "curve" => quiet.curve = Some(FieldParseError::parse_value::<CurvePoints>(value, "curve")?.map_temperatures(|t| unit.to_celsius(t))),
+                _ => {}
+            }
+        } else if section == "avoid" && key == "band" {
+            self.avoid.push(FieldParseError::parse_value(value, "band")?);
+        } else if let Some(name) = section.strip_prefix("fan.") {
+            if Self::GLOBAL_KEYS.contains(&key) {
+                log::warn!("[{}] {} is global and only read from the top-level section", name, key);
+                return Ok(());
+            }
+            let index = match self.fans.iter().position(|(n, _)| n == name) {
+                Some(index) => index,
+                None => {
+                    let mut defaults = self.clone();
+                    defaults.fans.clear();
+                    self.fans.push((String::from(name), defaults));
+                    self.fans.len() - 1
+                }
+            };
+            self.fans[index].1.apply("", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+impl Ini for Args {
+    type Err = FieldParseError;
+
+    fn callback(
+        &mut self, 
+        filename: &Path, 
+        _line: &str, 
+        line_number: usize, 
+        section: &str, 
+        key: &str, 
+        value: Option<&str>
+    ) -> Result<(), Self::Err> {
+        if !Self::is_known(section, key) {
+            self.unknown_keys.push(FieldParseError::unknown("strict", section, key).at(filename, line_number));
+        }
+        if Self::DURATION_KEYS.contains(&key) {
+            if let Some(millis) = value.filter(|value| FieldParseError::is_bare_duration(value)) {
+                let millis: u64 = millis.trim().parse().unwrap_or_default();
+                let suggested = if millis.is_multiple_of(1000) { format!("{}s", millis / 1000) } else { format!("{}ms", millis) };
+                let at = if line_number > 0 { format!("{}:{}", filename.display(), line_number) } else { filename.display().to_string() };
+                log::warn!("{}: {} = {} without a unit is deprecated and read as milliseconds, write {} = {}", at, key, millis, key, suggested);
+            }
+        }
+        self.apply(section, key, value).map_err(|e| e.at(filename, line_number))
+    }
+}
+
+
+/// what one cycle asks of the pwm
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    /// leave the pwm as is
+    Keep,
+    /// disable the pwm
+    Stop,
+    /// write the duty cycle, enabling the pwm if needed
+    Set(f32),
+    /// max duty above `critical_temperature`, then power off once sustained with `critical_action = poweroff`
+    Critical { duty_cycle: f32, poweroff: bool },
+}
+
+impl Action {
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Keep => "keep",
+            Action::Stop => "off",
+            Action::Set(_) => "change",
+            Action::Critical { .. } => "critical",
+        }
+    }
+}
+
+
+/// one sensor, control and pwm triple; the top-level section or a `[fan.NAME]` section
+pub struct Fan {
+    name: String,
+    /// `[NAME] ` for named fans, prepended to log lines
+    prefix: String,
+    sensor: Box<dyn SensorSource>,
+    pwm: Box<dyn FanOutput>,
+    /// `pwm_frequency` as the sysfs `period`, in ns
+    period: u32,
+    on: bool,
+    control: Control,
+    /// `max_speed_time_cycle` cycles of `interval`
+    max_speed_time: Duration,
+    /// counted down by the measured time between cycles
+    max_speed_remaining: Duration,
+    /// a max speed ran and has not been handed back to the control yet
+    max_speed_active: bool,
+    max_speed_log_window: Duration,
+    max_speed_logged: Option<Instant>,
+    verify_pwm: bool,
+    verify_enable: bool,
+    alternate_rule: Option<Box<dyn Rule>>,
+    quiet: bool,
+    min_duty_ns: Option<u32>,
+    max_duty_ns: Option<u32>,
+    critical_temperature: Option<f32>,
+    critical_action: CriticalAction,
+    critical_time_cycle: usize,
+    critical_cycle: usize,
+    avoid: Vec<DutyBand>,
+    cooling_device: Option<CoolingDevice>,
+    defer_time_cycle: usize,
+    defer_cycle: usize,
+    deferred_duty_cycle: Option<f32>,
+    invert_duty: bool,
+    stuck_sensor_cycles: usize,
+    stuck_sensor_action: StuckAction,
+    /// consecutive cycles the reading equalled `stuck_temperature` while on
+    stuck_cycle: usize,
+    stuck_temperature: f32,
+    sensor_fail_cycles: usize,
+    /// `None` takes the max duty cycle of the current rule
+    sensor_fail_duty: Option<f32>,
+    /// consecutive failed sensor reads
+    sensor_fail_cycle: usize,
+    spinup_duty: f32,
+    spinup_cycles: usize,
+    spinup_remaining_cycle: usize,
+    /// duty cycle applied once the kick ends
+    spinup_target: f32,
+    max_duty_slew: f32,
+    /// duty cycle the slew limit last passed on; `None` takes `duty_cycle`
+    slew_duty: Option<f32>,
+    /// duty cycle of the control still being ramped towards
+    slew_target: Option<f32>,
+    initial_duty: Option<f32>,
+    sensor_offset: Option<i32>,
+    summary_every: usize,
+    summary_cycle: usize,
+    terminate_action: TerminateAction,
+    /// time since the control state restored from `state_file` was saved; the startup cycle resumes it
+    resumed: Option<Duration>,
+    idle_cycles: usize,
+    idle_delta: f32,
+    /// consecutive readings within `idle_delta` of `idle_temperature`
+    idle_cycle: usize,
+    idle_temperature: f32,
+    duty_cycle: f32,
+    last_temperature: f32,
+    last_action: Action,
+    starts: u64,
+    events: Option<Sender<FanEvent>>,
+}
+
+impl Fan {
+
+    /// pause between attempts to open a pwm chip that is not ready, see `open_pwm`
+    const STARTUP_RETRY: Duration = Duration::from_millis(500);
+
+    /// a shorter period, a `pwm_frequency` above 50 kHz, only draws a warning
+    const MIN_PERIOD: u32 = 20_000;
+
+    fn build_rule(args: &CurveArgs) -> io::Result<Box<dyn Rule>> {
+        let f: Box<dyn Rule> = match args.curve.clone() {
+            Some(points) => Box::new(
+                Curve::new(args.stop_temperature, points)
+                    .map_err(FieldParseError::from)?
+            ),
+            None => Box::new(
+                Function::new(
+                    args.stop_temperature,
+                    args.start_temperature,
+                    args.high_temperature,
+                    args.min_duty_cycle,
+                    args.max_duty_cycle,
+                )
+                .map_err(FieldParseError::from)?
+            ),
+        };
+        Ok(f)
+    }
+
+    /// opens the sysfs devices named by `args`
+    pub fn open(name: &str, args: &Args) -> io::Result<Self> {
+        let prefix = Self::prefix(name);
+        let mut critical_temperature = None;
+        let sensor: Box<dyn SensorSource> = match args.sensor_command.as_ref() {
+            Some(command) => {
+                let sensor = CommandSensor::new(command.as_str(), args.sensor_scale, Duration::from_millis(args.sensor_command_timeout))?;
+                log::info!("{}sensor initialized: command={}, sensor_scale={}, sensor_command_timeout={}ms", prefix, command, args.sensor_scale, args.sensor_command_timeout);
+                if args.critical_temperature.is_none() && args.critical_trip_margin.is_some() {
+                    log::warn!("{}critical_trip_margin needs a thermal zone, not sensor_command; no critical temperature", prefix);
+                }
+                Box::new(sensor)
+            }
+            None => {
+                let watch = resolve_path(args.watch.as_path())?;
+                let sensor = SensorDevice::new(watch.as_path(), args.sensor_scale)?;
+                log::info!("{}sensor initialized: path={}, resolved={}, sensor_scale={}", prefix, args.watch.as_path().display(), watch.as_path().display(), args.sensor_scale);
+                if let (None, Some(margin)) = (args.critical_temperature, args.critical_trip_margin) {
+                    critical_temperature = Self::critical_from_trip(&prefix, &sensor, margin);
+                }
+                Box::new(sensor)
+            }
+        };
+        let instance = 0;
+        let null_pwm = args.observe || args.dry_run;
+        let pwm: Box<dyn FanOutput> = if args.fan_kind == FanKind::CoolingDevice {
+            let path = Self::cooling_device_path(&prefix, args)?;
+            let resolved = resolve_path(path).unwrap_or_else(|_e| path.to_path_buf());
+            let pwm: Box<dyn FanOutput> = if null_pwm {
+                Box::new(NullPWMDevice::with_path(resolved.as_path()))
+            } else {
+                let output = CoolingDeviceOutput::new(resolved.as_path()).map_err(|e| io::Error::new(e.kind(), format!("{}cooling_device {}: {}", prefix, path.display(), e)))?;
+                log::info!("{}cooling device max_state={}", prefix, output.max_state());
+                Box::new(output)
+            };
+            log::info!("{}cooling device output initialized: path={}, resolved={}, observe={}, dry_run={}", prefix, path.display(), resolved.display(), args.observe, args.dry_run);
+            pwm
+        } else if args.fan_kind == FanKind::Gpio {
+            let line = Self::gpio_line(&prefix, args)?;
+            let path = gpio::line_path(line);
+            let pwm: Box<dyn FanOutput> = if null_pwm {
+                Box::new(NullPWMDevice::with_path(path.as_path()))
+            } else {
+                Box::new(GpioFanDevice::new(line).map_err(|e| io::Error::new(e.kind(), format!("{}gpio {}: {}", prefix, line, e)))?)
+            };
+            log::info!("{}gpio initialized: path={}, observe={}, dry_run={}", prefix, path.display(), args.observe, args.dry_run);
+            pwm
+        } else {
+            let (execute, pwm): (PathBuf, Box<dyn FanOutput>) = if null_pwm {
+                let execute = resolve_path(args.execute.as_path()).unwrap_or_else(|_e| args.execute.clone());
+                let pwm = Box::new(NullPWMDevice::new(execute.as_path(), instance));
+                (execute, pwm)
+            } else {
+                let (execute, pwm) = Self::open_pwm(&prefix, args, instance)?;
+                (execute, Box::new(pwm))
+            };
+            log::info!("{}pwm initialized: path={}/pwm{}, resolved={}, pwm_frequency={}, observe={}, dry_run={}", prefix, args.execute.as_path().display(), instance, execute.as_path().display(), args.pwm_frequency, args.observe, args.dry_run);
+            pwm
+        };
+        let cooling_device = match args.defer_cooling_device.as_ref() {
+            Some(path) => {
+                let resolved = resolve_path(path)?;
+                let cooling_device = CoolingDevice::new(resolved.as_path())?;
+                log::info!("{}cooling device initialized: path={}, resolved={}, defer_time_cycle={}", prefix, path.display(), resolved.display(), args.defer_time_cycle);
+                Some(cooling_device)
+            }
+            None => None,
+        };
+        let mut fan = Self::new(name, args, sensor, pwm)?;
+        fan.cooling_device = cooling_device;
+        if critical_temperature.is_some() {
+            fan.critical_temperature = critical_temperature;
+        }
+        Ok(fan)
+    }
+
+    /// `critical_trip_margin` below the zone's `critical` trip point; `None`, leaving the fan without a
+    /// critical temperature, when the zone has no trip point or it cannot be read
+    fn critical_from_trip(prefix: &str, sensor: &SensorDevice, margin: f32) -> Option<f32> {
+        match sensor.read_trip("critical") {
+            Ok(Some(trip)) => {
+                let critical_temperature = trip - margin;
+                log::info!("{}critical temperature from trip point: trip={}, critical_trip_margin={}, critical_temperature={}", prefix, Temperature(trip), TemperatureDelta(margin), Temperature(critical_temperature));
+                Some(critical_temperature)
+            }
+            Ok(None) => {
+                log::warn!("{}critical_trip_margin is set but the zone has no critical trip point; no critical temperature", prefix);
+                None
+            }
+            Err(e) => {
+                log::warn!("{}failed to read the critical trip point, no critical temperature: {}", prefix, e);
+                None
+            }
+        }
+    }
+
+    /// `gpio`, required with `fan_kind = gpio`
+    fn gpio_line(prefix: &str, args: &Args) -> io::Result<u32> {
+        args.gpio.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("{}missing value for gpio: required with fan_kind = gpio", prefix)))
+    }
+
+    /// `cooling_device`, required with `fan_kind = cooling_device`
+    fn cooling_device_path<'a>(prefix: &str, args: &'a Args) -> io::Result<&'a Path> {
+        args.cooling_device.as_deref().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("{}missing value for cooling_device: required with fan_kind = cooling_device", prefix)))
+    }
+
+    /// resolves `execute` and opens the pwm, retrying every `STARTUP_RETRY` until `startup_delay_ms` has passed
+    fn open_pwm(prefix: &str, args: &Args, instance: u32) -> io::Result<(PathBuf, PWMDevice)> {
+        let deadline = Instant::now() + Duration::from_millis(args.startup_delay_ms);
+        let mut attempt = 1;
+        loop {
+            let result = resolve_path(args.execute.as_path())
+                .and_then(|execute| PWMDevice::new(execute.as_path(), instance).map(|pwm| (execute, pwm)));
+            let now = Instant::now();
+            match result {
+                // an instance beyond `npwm` will not appear by waiting
+                Err(e) if now < deadline && e.kind() != io::ErrorKind::InvalidInput => {
+                    log::warn!("{}pwm not ready, retrying in {}ms: attempt={}, error={}", prefix, Self::STARTUP_RETRY.as_millis(), attempt, e);
+                    attempt += 1;
+                    thread::sleep(Self::STARTUP_RETRY.min(deadline - now));
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// builds the control path around the given sensor and pwm, e.g. mock devices for replay
+    pub fn new(name: &str, args: &Args, sensor: Box<dyn SensorSource>, pwm: Box<dyn FanOutput>) -> io::Result<Self> {
+        let prefix = Self::prefix(name);
+        if let (Some(min_duty_ns), Some(max_duty_ns)) = (args.min_duty_ns, args.max_duty_ns) {
+            if min_duty_ns > max_duty_ns {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}invalid value `{}` for max_duty_ns: lower than min_duty_ns", prefix, max_duty_ns)));
+            }
+        }
+        if let Some(initial_duty) = args.initial_duty {
+            if !(initial_duty > 0.0 && initial_duty <= 1.0) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}invalid value `{}` for initial_duty: not in (0, 1]", prefix, initial_duty)));
+            }
+        }
+        let period = Self::period_ns(&prefix, args.pwm_frequency)?;
+        if !(args.spinup_duty > 0.0 && args.spinup_duty <= 1.0) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}invalid value `{}` for spinup_duty: not in (0, 1]", prefix, args.spinup_duty)));
+        }
+        if args.critical_trip_margin.is_some_and(|margin| !(margin >= 0.0 && margin.is_finite())) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}invalid value `{}` for critical_trip_margin: not a non-negative number", prefix, args.critical_trip_margin.unwrap_or_default())));
+        }
+        if args.sensor_fail_duty == Some(0.0) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}invalid value `0` for sensor_fail_duty: the fan must run; set sensor_fail_cycles = 0 instead", prefix)));
+        }
+        if !(0.0..=1.0).contains(&args.max_duty_slew) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}invalid value `{}` for max_duty_slew: not in [0, 1]", prefix, args.max_duty_slew)));
+        }
+        let f = Self::build_rule(&args.curve_args())?;
+        log::info!("{}control initialized: function={}", prefix, &f);
+        let alternate_rule = match args.quiet.as_ref() {
+            Some(quiet) => {
+                let q = Self::build_rule(quiet)?;
+                log::info!("{}control initialized: quiet function={}", prefix, &q);
+                Some(q)
+            }
+            None => None,
+        };
+        let regulator: Option<Box<dyn Regulator>> = match args.control {
+            ControlKind::Pid => {
+                let pid = Pid::new(args.setpoint, args.kp, args.ki, args.kd, args.interval.as_secs_f32())
+                    .map_err(FieldParseError::from)?;
+                log::info!("{}control initialized: kind={}, setpoint={:.2}°C, kp={}, ki={}, kd={}", prefix, args.control, args.setpoint, args.kp, args.ki, args.kd);
+                Some(Box::new(pid))
+            }
+            ControlKind::Hysteresis | ControlKind::Stateless => {
+                log::info!("{}control initialized: kind={}", prefix, args.control);
+                None
+            }
+        };
+        let mut control = Control::new(f, Fan::cycles(args.interval, args.lag_time_cycle), args.min_on_cycles, args.stop_hysteresis, args.rampdown_cycles, args.keep_decay, regulator)
+            .map_err(FieldParseError::from)?;
+        control.set_duty_deadband(args.duty_deadband)
+            .map_err(FieldParseError::from)?;
+        control.set_kind(args.control);
+        if args.min_start_duty.is_some() || args.min_run_duty.is_some() {
+            let min_run_duty = args.min_run_duty.unwrap_or(0.0);
+            let min_start_duty = args.min_start_duty.unwrap_or(min_run_duty);
+            control.set_min_duty(min_start_duty, min_run_duty)
+                .map_err(FieldParseError::from)?;
+            log::info!("{}control initialized: min_start_duty={:.2}%, min_run_duty={:.2}%", prefix, min_start_duty * 100.0, min_run_duty * 100.0);
+        }
+        log::info!("{}control initialized: lag_time_cycle={}, max_speed_time_cycle={}, min_on_cycles={}, stop_hysteresis={}, rampdown_cycles={}, keep_decay={}, duty_deadband={:.2}%", prefix, args.lag_time_cycle, args.max_speed_time_cycle, args.min_on_cycles, TemperatureDelta(args.stop_hysteresis), args.rampdown_cycles, args.keep_decay, args.duty_deadband * 100.0);
+        for band in args.avoid.iter() {
+            log::info!("{}control initialized: avoid duty band {}", prefix, band);
+        }
+        if args.max_duty_slew > 0.0 {
+            log::info!("{}control initialized: max_duty_slew={:.2}%", prefix, args.max_duty_slew * 100.0);
+        }
+        if args.spinup_cycles > 0 {
+            log::info!("{}control initialized: spinup_duty={:.2}%, spinup_cycles={}", prefix, args.spinup_duty * 100.0, args.spinup_cycles);
+        }
+        Ok(
+            Self {
+                name: String::from(name),
+                prefix,
+                sensor,
+                pwm,
+                period,
+                on: false,
+                control,
+                max_speed_time: Fan::cycles(args.interval, args.max_speed_time_cycle),
+                max_speed_remaining: Duration::ZERO,
+                max_speed_active: false,
+                max_speed_log_window: Fan::cycles(args.interval, args.max_speed_log_window_cycle),
+                max_speed_logged: None,
+                verify_pwm: args.verify_pwm,
+                verify_enable: args.verify_enable,
+                alternate_rule,
+                quiet: false,
+                min_duty_ns: args.min_duty_ns,
+                max_duty_ns: args.max_duty_ns,
+                critical_temperature: args.critical_temperature,
+                critical_action: args.critical_action,
+                critical_time_cycle: args.critical_time_cycle,
+                critical_cycle: 0,
+                avoid: args.avoid.clone(),
+                cooling_device: None,
+                defer_time_cycle: args.defer_time_cycle,
+                defer_cycle: 0,
+                deferred_duty_cycle: None,
+                invert_duty: args.invert_duty,
+                stuck_sensor_cycles: args.stuck_sensor_cycles,
+                stuck_sensor_action: args.stuck_sensor_action,
+                stuck_cycle: 0,
+                stuck_temperature: f32::NAN,
+                sensor_fail_cycles: args.sensor_fail_cycles,
+                sensor_fail_duty: args.sensor_fail_duty,
+                sensor_fail_cycle: 0,
+                spinup_duty: args.spinup_duty,
+                spinup_cycles: args.spinup_cycles,
+                spinup_remaining_cycle: 0,
+                spinup_target: 0.0,
+                max_duty_slew: args.max_duty_slew,
+                slew_duty: None,
+                slew_target: None,
+                initial_duty: args.initial_duty,
+                sensor_offset: args.sensor_offset,
+                summary_every: args.summary_every,
+                summary_cycle: 0,
+                terminate_action: args.terminate_action,
+                resumed: None,
+                idle_cycles: args.idle_cycles.max(1),
+                idle_delta: args.idle_delta,
+                idle_cycle: 0,
+                idle_temperature: f32::NAN,
+                duty_cycle: 0.0,
+                last_temperature: f32::NAN,
+                last_action: Action::Keep,
+                starts: 0,
+                events: None,
+            }
+        )
+    }
+
+    /// builds the event only with a receiver attached; a closed receiver is ignored
+    fn emit(&self, event: impl FnOnce(String) -> FanEvent) {
+        if let Some(events) = self.events.as_ref() {
+            let _ = events.send(event(self.name.clone()));
+        }
+    }
+
+    fn prefix(name: &str) -> String {
+        if name.is_empty() {
+            String::new()
+        } else {
+            format!("[{}] ", name)
+        }
+    }
+
+    /// the sysfs `period` in ns for `pwm_frequency` in Hz
+    fn period_ns(prefix: &str, pwm_frequency: u32) -> io::Result<u32> {
+        let period = match 1_000_000_000u32.checked_div(pwm_frequency) {
+            Some(period) if period > 0 => period,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}invalid value `{}` for pwm_frequency: not in 1 Hz to 1 GHz", prefix, pwm_frequency))),
+        };
+        if period < Self::MIN_PERIOD {
+            log::warn!("{}pwm_frequency={} is a period of {}ns, which fans rarely support; the chip may reject it or the fan may buzz", prefix, pwm_frequency, period);
+        }
+        Ok(period)
+    }
+
+    /// `cycles` times `interval`, for the timings configured in cycles
+    fn cycles(interval: Duration, cycles: usize) -> Duration {
+        interval.saturating_mul(u32::try_from(cycles).unwrap_or(u32::MAX))
+    }
+
+    pub fn initial(&mut self) -> io::Result<()> {
+        self.pwm.set_period(self.period)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}failed to set period={}ns: {}; the chip may not support this pwm_frequency, try one in its range", self.prefix, self.period, e)))?;
+        self.pwm.set_polarity(Polarity::Normal)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}failed to set polarity={}: {}; polarity may not be supported on this chip", self.prefix, Polarity::Normal, e)))?;
+        if self.verify_pwm {
+            self.verify_initial(Polarity::Normal)?;
+        }
+        log::info!("{}fan initialized: period={}ns, polarity={}", self.prefix, self.period, Polarity::Normal);
+        if let Some(offset) = self.sensor_offset {
+            self.sensor.set_offset(offset)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}failed to set sensor_offset={}: {}", self.prefix, offset, e)))?;
+            log::info!("{}sensor offset set: sensor_offset={}", self.prefix, offset);
+        }
+        let temperature = self.sensor.get()?;
+        let output = match self.resumed.take() {
+            // the restored state runs the startup cycle; the pwm was stopped on exit, so a kept duty cycle is written again
+            Some(dt) => match self.control.update(temperature, dt) {
+                ControlOutput::Keep => self.control.current_duty().map_or(ControlOutput::Off, ControlOutput::Change),
+                output => output,
+            },
+            None => {
+                let duty_cycle = self.initial_duty.unwrap_or_else(|| self.control.map(temperature).max(self.control.min_duty_cycle()).max(self.control.min_start_duty()));
+                self.control.update_force(temperature, duty_cycle)
+            }
+        };
+        log::trace!(temp = temperature, duty = self.control.current_duty().unwrap_or(0.0), state = self.control.state_name(); "{}control status: temperature={}, output={:?}, state={}", self.prefix, Temperature(temperature), output, self.control.state_name());
+        // `update_force` always returns `Change` and a resumed state never `Keep`; the other outputs are handled so a future change cannot panic the daemon
+        let action = match output {
+            ControlOutput::Change(duty_cycle) => self.spinup(Action::Set(duty_cycle)),
+            ControlOutput::Off => {
+                log::info!(temp = temperature, duty = 0.0, state = self.control.state_name(); "{}fan left stopped at {}", self.prefix, Temperature(temperature));
+                Action::Stop
+            }
+            ControlOutput::Keep => {
+                log::warn!("{}control returned keep at startup, fan left as is", self.prefix);
+                Action::Keep
+            }
+        };
+        match action {
+            Action::Set(duty_cycle) => {
+                if self.start_pwm(duty_cycle)? {
+                    log::info!(temp = temperature, duty = duty_cycle, state = self.control.state_name(); "{}fan launched at {} with pwm-duty-ratio={:.2}%", self.prefix, Temperature(temperature), duty_cycle * 100.0);
+                    self.emit(|fan| FanEvent::Started { fan, temperature, duty_cycle });
+                }
+            }
+            Action::Stop => {
+                // an inverted output left disabled by the firmware runs at full airflow until stopped
+                if !self.stop_pwm()? && self.invert_duty {
+                    self.halt_pwm()?;
+                }
+            }
+            Action::Keep | Action::Critical { .. } => {}
+        }
+        self.last_temperature = temperature;
+        self.last_action = action;
+        Ok(())
+    }
+
+    fn verify_initial(&mut self, polarity: Polarity) -> io::Result<()> {
+        let period = self.pwm.get_period()?;
+        if period != self.period {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}chip rejected period={}ns (read back {}ns); the chip may not support this pwm_frequency, try one in its range", self.prefix, self.period, period)
+            ));
+        }
+        let actual = self.pwm.get_polarity()?;
+        if actual != polarity {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}polarity {} not supported on this chip (read back {}); the fan may need inverted wiring", self.prefix, polarity, actual)
+            ));
+        }
+        Ok(())
+    }
+
+    /// `dt` is the time since the previous cycle
+    pub fn run(&mut self, dt: Duration) -> io::Result<()> {
+        // without a critical check the sensor is not read while the max speed override is counting down
+        if self.critical_temperature.is_none() && !self.max_speed_remaining.is_zero() {
+            self.max_speed_remaining = self.max_speed_remaining.saturating_sub(dt);
+            // a kick started by the max speed still runs its course
+            return match self.spinup(Action::Keep) {
+                Action::Keep => Ok(()),
+                action => self.apply(self.last_temperature, action),
+            };
+        }
+        let temperature = match self.sensor.get() {
+            Ok(temperature) => temperature,
+            Err(e) => {
+                self.sensor_failed()?;
+                return Err(e);
+            }
+        };
+        self.sensor_recovered(temperature);
+        if (temperature - self.idle_temperature).abs() <= self.idle_delta {
+            self.idle_cycle = self.idle_cycle.saturating_add(1);
+        } else {
+            self.idle_cycle = 0;
+            self.idle_temperature = temperature;
+        }
+        let action = self.tick(temperature, dt);
+        let action = self.defer(action)?;
+        let action = self.slew(action);
+        let action = self.spinup(action);
+        self.apply(temperature, action)
+    }
+
+    /// counts a failed sensor read; the `sensor_fail_cycles`th in a row runs the fan at `sensor_fail_duty`,
+    /// kicked like any start, and the following ones keep it there
+    fn sensor_failed(&mut self) -> io::Result<()> {
+        self.sensor_fail_cycle = self.sensor_fail_cycle.saturating_add(1);
+        if self.sensor_fail_cycles == 0 || self.sensor_fail_cycle < self.sensor_fail_cycles {
+            return Ok(());
+        }
+        let action = if self.sensor_fail_cycle == self.sensor_fail_cycles {
+            let duty_cycle = self.sensor_fail_duty.unwrap_or_else(|| self.control.max_duty_cycle());
+            log::warn!("{}sensor unreadable for {} cycles, fan set to pwm-duty-ratio={:.2}% until it recovers", self.prefix, self.sensor_fail_cycle, duty_cycle * 100.0);
+            self.slew_duty = None;
+            self.slew_target = None;
+            self.deferred_duty_cycle = None;
+            self.spinup(Action::Set(duty_cycle))
+        } else {
+            // a kick started by the failure still runs its course
+            self.spinup(Action::Keep)
+        };
+        match action {
+            Action::Keep => Ok(()),
+            action => self.apply(self.last_temperature, action),
+        }
+    }
+
+    /// hands a fan held at `sensor_fail_duty` back to the control at the first reading
+    fn sensor_recovered(&mut self, temperature: f32) {
+        if self.sensor_fail_cycles > 0 && self.sensor_fail_cycle >= self.sensor_fail_cycles {
+            log::warn!("{}sensor readable again at {} after {} failed cycles", self.prefix, Temperature(temperature), self.sensor_fail_cycle);
+            self.control.update_force(temperature, self.duty_cycle);
+        }
+        self.sensor_fail_cycle = 0;
+    }
+
+    /// off with `idle_cycles` stable readings; never with `critical_temperature`, whose check must keep the normal interval
+    fn is_idle(&self) -> bool {
+        !self.on
+            && self.idle_cycle >= self.idle_cycles
+            && self.max_speed_remaining.is_zero()
+            && self.critical_temperature.is_none()
+    }
+
+    /// decides one cycle from `temperature` alone, without touching the sensor or the pwm
+    pub fn tick(&mut self, temperature: f32, dt: Duration) -> Action {
+        if let Some(action) = self.tick_critical(temperature) {
+            return action;
+        }
+        if let Some(action) = self.tick_stuck(temperature) {
+            return action;
+        }
+        if !self.max_speed_remaining.is_zero() {
+            self.max_speed_remaining = self.max_speed_remaining.saturating_sub(dt);
+            return Action::Keep;
+        }
+        if self.max_speed_active {
+            return self.end_max_speed(temperature);
+        }
+        let output = self.control.update(temperature, dt);
+        log::trace!(temp = temperature, duty = self.control.current_duty().unwrap_or(0.0), state = self.control.state_name(); "{}control status: temperature={}, output={:?}, state={}", self.prefix, Temperature(temperature), output, self.control.state_name());
+        match output {
+            ControlOutput::Off => Action::Stop,
+            ControlOutput::Change(duty_cycle) => Action::Set(duty_cycle),
+            ControlOutput::Keep => Action::Keep,
+        }
+    }
+
+    /// forces max duty above `critical_temperature`, bypassing the control state machine
+    fn tick_critical(&mut self, temperature: f32) -> Option<Action> {
+        let critical_temperature = self.critical_temperature?;
+        let duty_cycle = self.control.max_duty_cycle();
+        if temperature <= critical_temperature {
+            if self.critical_cycle > 0 {
+                self.critical_cycle = 0;
+                self.control.update_force(temperature, duty_cycle);
+                log::warn!("{}temperature back to {} below critical {}", self.prefix, Temperature(temperature), Temperature(critical_temperature));
+            }
+            return None;
+        }
+        self.critical_cycle += 1;
+        Some(Action::Critical {
+            duty_cycle,
+            poweroff: self.critical_action == CriticalAction::Poweroff && self.critical_cycle > self.critical_time_cycle,
+        })
+    }
+
+    /// counts identical readings while the fan is on; past `stuck_sensor_cycles` warns once, and with
+    /// `stuck_sensor_action = maxfan` forces max duty until the reading changes
+    fn tick_stuck(&mut self, temperature: f32) -> Option<Action> {
+        if self.stuck_sensor_cycles == 0 {
+            return None;
+        }
+        if !self.on || temperature != self.stuck_temperature {
+            if self.stuck_cycle >= self.stuck_sensor_cycles {
+                log::warn!("{}sensor reading changed to {} after {} identical cycles", self.prefix, Temperature(temperature), self.stuck_cycle);
+                if self.stuck_sensor_action == StuckAction::MaxFan {
+                    self.control.update_force(temperature, self.control.max_duty_cycle());
+                }
+            }
+            self.stuck_cycle = 0;
+            self.stuck_temperature = temperature;
+            return None;
+        }
+        self.stuck_cycle += 1;
+        if self.stuck_cycle == self.stuck_sensor_cycles {
+            log::warn!("{}sensor stuck at {} for {} cycles while the fan is on", self.prefix, Temperature(temperature), self.stuck_cycle);
+        }
+        match self.stuck_sensor_action {
+            StuckAction::MaxFan if self.stuck_cycle == self.stuck_sensor_cycles => Some(Action::Set(self.control.max_duty_cycle())),
+            StuckAction::MaxFan if self.stuck_cycle > self.stuck_sensor_cycles => Some(Action::Keep),
+            _ => None,
+        }
+    }
+
+    /// holds back increases while the cooling device is active, for at most `defer_time_cycle` cycles;
+    /// the last held-back duty cycle is applied once the deferral ends
+    fn defer(&mut self, action: Action) -> io::Result<Action> {
+        let cooling_device = match self.cooling_device.as_mut() {
+            Some(cooling_device) => cooling_device,
+            None => return Ok(action),
+        };
+        let active = cooling_device.is_active()?;
+        if active && self.defer_cycle < self.defer_time_cycle {
+            self.defer_cycle += 1;
+            return match action {
+                Action::Set(duty_cycle) if !self.on || duty_cycle > self.duty_cycle => {
+                    log::debug!("{}fan increase to pwm-duty-ratio={:.2}% deferred to active cooling device ({}/{})", self.prefix, duty_cycle * 100.0, self.defer_cycle, self.defer_time_cycle);
+                    self.deferred_duty_cycle = Some(duty_cycle);
+                    Ok(Action::Keep)
+                }
+                Action::Keep | Action::Critical { .. } => Ok(action),
+                _ => {
+                    self.deferred_duty_cycle = None;
+                    Ok(action)
+                }
+            };
+        }
+        if !active {
+            self.defer_cycle = 0;
+        }
+        match (action, self.deferred_duty_cycle.take()) {
+            (Action::Keep, Some(duty_cycle)) => Ok(Action::Set(duty_cycle)),
+            (action, _) => Ok(action),
+        }
+    }
+
+    /// limits a running fan's duty cycle change to `max_duty_slew` per cycle, ramping towards the latest duty cycle
+    /// of the control while it keeps; starting, stopping and `Action::Critical` pass unchanged
+    fn slew(&mut self, action: Action) -> Action {
+        if self.max_duty_slew <= 0.0 {
+            return action;
+        }
+        let target = match action {
+            Action::Set(duty_cycle) => duty_cycle,
+            Action::Keep => match self.slew_target {
+                Some(duty_cycle) => duty_cycle,
+                None => return action,
+            },
+            Action::Stop | Action::Critical { .. } => {
+                self.slew_duty = None;
+                self.slew_target = None;
+                return action;
+            }
+        };
+        let duty_cycle = if self.on {
+            let current = self.slew_duty.unwrap_or(self.duty_cycle);
+            target.clamp(current - self.max_duty_slew, current + self.max_duty_slew)
+        } else {
+            target
+        };
+        if duty_cycle != target {
+            log::debug!("{}duty cycle slewed to pwm-duty-ratio={:.2}% towards {:.2}%", self.prefix, duty_cycle * 100.0, target * 100.0);
+        }
+        self.slew_duty = Some(duty_cycle);
+        self.slew_target = (duty_cycle != target).then_some(target);
+        Action::Set(duty_cycle)
+    }
+
+    /// starts a stopped fan at `spinup_duty` for `spinup_cycles` cycles, then applies the latest computed duty cycle
+    fn spinup(&mut self, action: Action) -> Action {
+        if self.spinup_cycles == 0 {
+            return action;
+        }
+        if self.spinup_remaining_cycle == 0 {
+            return match action {
+                Action::Set(duty_cycle) if !self.on => {
+                    log::debug!("{}fan kicked with pwm-duty-ratio={:.2}% for {} cycles", self.prefix, self.spinup_duty * 100.0, self.spinup_cycles);
+                    self.spinup_remaining_cycle = self.spinup_cycles;
+                    self.spinup_target = duty_cycle;
+                    Action::Set(self.spinup_duty)
+                }
+                action => action,
+            };
+        }
+        match action {
+            Action::Set(duty_cycle) => self.spinup_target = duty_cycle,
+            Action::Keep => {}
+            Action::Stop | Action::Critical { .. } => {
+                self.spinup_remaining_cycle = 0;
+                return action;
+            }
+        }
+        self.spinup_remaining_cycle -= 1;
+        if self.spinup_remaining_cycle == 0 {
+            Action::Set(self.spinup_target)
+        } else {
+            Action::Keep
+        }
+    }
+
+    /// writes the outcome of `tick` to the pwm
+    pub fn apply(&mut self, temperature: f32, action: Action) -> io::Result<()> {
+        match action {
+            Action::Keep => {
+                // do nothing
+            }
+            Action::Stop => {
+                if self.stop_pwm()? {
+                    log::info!(temp = temperature, duty = 0.0, state = self.control.state_name(); "{}fan stopped at {}", self.prefix, Temperature(temperature));
+                    self.emit(|fan| FanEvent::Stopped { fan, temperature });
+                }
+            }
+            Action::Set(duty_cycle) => {
+                if self.start_pwm(duty_cycle)? {
+                    log::info!(temp = temperature, duty = duty_cycle, state = self.control.state_name(); "{}fan started at {} with pwm-duty-ratio={:.2}%", self.prefix, Temperature(temperature), duty_cycle * 100.0);
+                    self.emit(|fan| FanEvent::Started { fan, temperature, duty_cycle });
+                } else {
+                    log::debug!(temp = temperature, duty = duty_cycle, state = self.control.state_name(); "{}fan changed at {} with pwm-duty-ratio={:.2}%", self.prefix, Temperature(temperature), duty_cycle * 100.0);
+                    self.emit(|fan| FanEvent::DutyChanged { fan, temperature, duty_cycle });
+                }
+            }
+            Action::Critical { duty_cycle, poweroff } => {
+                self.start_pwm(duty_cycle)?;
+                log::error!(temp = temperature, duty = duty_cycle, state = self.control.state_name(); "{}critical temperature {} above {}, fan forced to pwm-duty-ratio={:.2}%", self.prefix, Temperature(temperature), Temperature(self.critical_temperature.unwrap_or(f32::NAN)), duty_cycle * 100.0);
+                self.emit(|fan| FanEvent::Critical { fan, temperature });
+                if poweroff {
+                    log::error!("{}critical temperature sustained for {} cycles, powering off", self.prefix, self.critical_cycle);
+                    unsafe {
+                        libc::sync();
+                        if libc::reboot(libc::RB_POWER_OFF) != 0 {
+                            log::error!("failed to power off: {:?}", io::Error::last_os_error());
+                        }
+                    }
+                }
+            }
+        }
+        self.last_temperature = temperature;
+        self.last_action = action;
+        Ok(())
+    }
+
+    /// swaps in the curve of `args`, keeping the control timing state
+    fn reload_curve(&mut self, args: &Args) -> io::Result<()> {
+        if let (Some(min_duty_ns), Some(max_duty_ns)) = (args.min_duty_ns, args.max_duty_ns) {
+            if min_duty_ns > max_duty_ns {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}invalid value `{}` for max_duty_ns: lower than min_duty_ns", self.prefix, max_duty_ns)));
+            }
+        }
+        let f = Self::build_rule(&args.curve_args())?;
+        let q = match args.quiet.as_ref() {
+            Some(quiet) => Some(Self::build_rule(quiet)?),
+            None => None,
+        };
+        log::info!("{}curve reloaded: function={}", self.prefix, &f);
+        match q {
+            Some(q) if self.quiet => {
+                log::info!("{}curve reloaded: quiet function={} (active)", self.prefix, &q);
+                self.control.replace_rule(q);
+                self.alternate_rule = Some(f);
+            }
+            q => {
+                self.control.replace_rule(f);
+                self.alternate_rule = q;
+                self.quiet = false;
+            }
+        }
+        Ok(())
+    }
+
+    fn toggle_quiet(&mut self) {
+        match self.alternate_rule.take() {
+            Some(f) => {
+                log::info!("{}switch to {} curve: function={}", self.prefix, if self.quiet { "primary" } else { "quiet" }, &f);
+                self.alternate_rule = Some(self.control.replace_rule(f));
+                self.quiet = !self.quiet;
+            }
+            None => {
+                log::warn!("{}no [quiet] curve configured to toggle", self.prefix);
+            }
+        }
+    }
+
+    /// `time` overrides `max_speed_time_cycle`
+    pub fn run_max_speed(&mut self, time: Option<Duration>) -> io::Result<()> {
+        let time = time.unwrap_or(self.max_speed_time);
+        if !self.max_speed_remaining.is_zero() {
+            log::info!("{}maximum speed restarted with {}ms of {}ms remaining", self.prefix, self.max_speed_remaining.as_millis(), time.as_millis());
+        }
+        let duty_cycle = self.control.max_duty_cycle();
+        // a stopped fan gets the same kick as a normal start
+        let action = if self.spinup_remaining_cycle > 0 {
+            self.spinup_target = duty_cycle;
+            Action::Keep
+        } else {
+            self.spinup(Action::Set(duty_cycle))
+        };
+        if let Action::Set(duty_cycle) = action {
+            self.start_pwm(duty_cycle)?;
+        }
+        self.max_speed_remaining = time;
+        self.max_speed_active = true;
+        self.slew_duty = Some(duty_cycle);
+        self.slew_target = None;
+        self.emit(|fan| FanEvent::MaxSpeed { fan, duty_cycle });
+        let level = self.max_speed_log_level(Instant::now());
+        log::log!(level, "{}fan set to maximum speed for {}ms with pwm-duty-ratio={:.2}%", self.prefix, time.as_millis(), duty_cycle * 100.0);
+        Ok(())
+    }
+
+    /// info for the first max speed engage within `max_speed_log_window`, debug for the repeated ones
+    fn max_speed_log_level(&mut self, now: Instant) -> log::Level {
+        match self.max_speed_logged {
+            Some(logged) if now.duration_since(logged) < self.max_speed_log_window => log::Level::Debug,
+            _ => {
+                self.max_speed_logged = Some(now);
+                log::Level::Info
+            }
+        }
+    }
+
+    /// ends a max speed early; the control restarts as if the fan were off, so the next cycle follows the curve
+    fn clear_max_speed(&mut self) {
+        if self.max_speed_remaining.is_zero() {
+            log::info!("{}no maximum speed to clear", self.prefix);
+            return;
+        }
+        log::info!("{}maximum speed cleared with {}ms remaining", self.prefix, self.max_speed_remaining.as_millis());
+        self.max_speed_remaining = Duration::ZERO;
+        self.max_speed_active = false;
+        self.control.reset();
+    }
+
+    /// hands an expired max speed back to the control at the curve's duty cycle for `temperature`,
+    /// held for the lag like any other change, instead of resuming the state from before the max speed
+    fn end_max_speed(&mut self, temperature: f32) -> Action {
+        self.max_speed_active = false;
+        let duty_cycle = self.control.map(temperature);
+        log::info!(temp = temperature, duty = duty_cycle, state = self.control.state_name(); "{}maximum speed expired at {}, back to pwm-duty-ratio={:.2}%", self.prefix, Temperature(temperature), duty_cycle * 100.0);
+        match self.control.update_force(temperature, duty_cycle) {
+            ControlOutput::Change(duty_cycle) => Action::Set(duty_cycle),
+            ControlOutput::Off => Action::Stop,
+            ControlOutput::Keep => Action::Keep,
+        }
+    }
+
+    /// the watchdog enabled the pwm at the maximum behind our back; restart the control as if the fan were off,
+    /// so the next cycle either stops the fan or follows the curve again
+    fn watchdog_fired(&mut self) {
+        log::warn!("{}pwm was forced to maximum by the watchdog", self.prefix);
+        self.on = true;
+        self.duty_cycle = self.control.max_duty_cycle();
+        self.slew_duty = None;
+        self.slew_target = None;
+        self.control.reset();
+    }
+
+    /// a handle to the same pwm opened separately, for the watchdog; `None` with `observe` or `dry_run`
+    fn watchdog_output(&self, args: &Args) -> io::Result<Option<WatchdogOutput>> {
+        if args.observe || args.dry_run {
+            return Ok(None);
+        }
+        let pwm: Box<dyn FanOutput + Send> = if args.fan_kind == FanKind::CoolingDevice {
+            Box::new(CoolingDeviceOutput::new(resolve_path(Self::cooling_device_path(&self.prefix, args)?)?.as_path())?)
+        } else if args.fan_kind == FanKind::Gpio {
+            Box::new(GpioFanDevice::new(Self::gpio_line(&self.prefix, args)?)?)
+        } else {
+            Box::new(PWMDevice::new(resolve_path(args.execute.as_path())?.as_path(), 0)?)
+        };
+        let duty_cycle = self.duty_ns(self.control.max_duty_cycle());
+        Ok(
+            Some(
+                WatchdogOutput {
+                    prefix: self.prefix.clone(),
+                    pwm,
+                    duty_cycle: pwm::clamp_duty_cycle(duty_cycle, self.period),
+                }
+            )
+        )
+    }
+
+    /// leaves the pwm as `terminate_action` asks
+    pub fn terminate(&mut self) -> io::Result<()> {
+        match self.terminate_action {
+            TerminateAction::Stop => {
+                self.stop_pwm()?;
+                log::info!("{}fan terminated", self.prefix);
+            }
+            TerminateAction::MaxFan => {
+                let duty_cycle = self.control.max_duty_cycle();
+                self.start_pwm(duty_cycle)?;
+                log::info!("{}fan terminated at maximum speed with pwm-duty-ratio={:.2}%", self.prefix, duty_cycle * 100.0);
+            }
+            TerminateAction::Leave => {
+                log::info!("{}fan terminated, pwm left {}", self.prefix, if self.on { "running" } else { "stopped" });
+            }
+        }
+        Ok(())
+    }
+
+    /// logs `status` every `summary_every` cycles
+    fn summary(&mut self) {
+        if self.summary_every == 0 {
+            return;
+        }
+        self.summary_cycle += 1;
+        if self.summary_cycle >= self.summary_every {
+            self.summary_cycle = 0;
+            log::info!(target: "fanctrl::summary", "{}summary: {}", self.prefix, self.status());
+        }
+    }
+
+    /// `Control::save` of the control state
+    fn save_state(&self) -> String {
+        self.control.save()
+    }
+
+    /// restores the control state written by `save_state`; the pwm is left as is
+    fn load_state(&mut self, state: &str) -> io::Result<()> {
+        self.control.load(state)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}{}", self.prefix, e)))?;
+        if let Some(duty_cycle) = self.control.current_duty() {
+            self.duty_cycle = duty_cycle;
+        }
+        self.last_temperature = self.control.last_temperature().unwrap_or(f32::NAN);
+        log::debug!("{}control state resumed: {}", self.prefix, state);
+        Ok(())
+    }
+
+    fn status(&self) -> Status {
+        Status {
+            fan: self.name.clone(),
+            temperature: self.last_temperature,
+            duty_cycle: if self.on { self.duty_cycle } else { 0.0 },
+            on: self.on,
+            state: self.control.state_name(),
+            output: self.last_action.name(),
+            last_error: None,
+        }
+    }
+
+    fn stop_pwm(&mut self) -> io::Result<bool> {
+        if self.on {
+            self.halt_pwm()?;
+            self.on = false;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// with `invert_duty` a disabled output is full airflow, so the fan is stopped by the inverted zero duty with the pwm left enabled
+    fn halt_pwm(&mut self) -> io::Result<()> {
+        if self.invert_duty {
+            self.pwm.apply(self.period, pwm::clamp_duty_cycle(self.duty_ns(0.0), self.period), Polarity::Normal, true)?;
+            Ok(())
+        } else {
+            self.pwm.set_enable(false)
+        }
+    }
+
+    /// the value written to `duty_cycle` for `duty_cycle`, after `invert_duty`, `min_duty_ns` and `max_duty_ns`
+    fn duty_ns(&self, duty_cycle: f32) -> u32 {
+        let ratio = if self.invert_duty { 1.0 - duty_cycle } else { duty_cycle };
+        let mut duty = (ratio * self.period as f32) as u32;
+        if let Some(min_duty_ns) = self.min_duty_ns {
+            duty = duty.max(min_duty_ns);
+        }
+        if let Some(max_duty_ns) = self.max_duty_ns {
+            duty = duty.min(max_duty_ns);
+        }
+        duty
+    }
+
+    /// some drivers drop the first enable after export while period and duty cycle are not latched yet, so
+    /// a start that did not take writes the three again once before giving up
+    fn verify_enable(&mut self, duty_cycle: f32) -> io::Result<()> {
+        if self.pwm.get_enable()? {
+            return Ok(());
+        }
+        log::warn!("{}pwm enable did not take, writing period, duty cycle and enable again", self.prefix);
+        self.pwm.set_period(self.period)?;
+        self.pwm.set_duty_cycle(self.duty_ns(duty_cycle))?;
+        self.pwm.set_enable(true)?;
+        if !self.pwm.get_enable()? {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}pwm enable did not take after a retry", self.prefix)));
+        }
+        Ok(())
+    }
+
+    fn start_pwm(&mut self, duty_cycle: f32) -> io::Result<bool> {
+        let duty_cycle = self.avoid.iter().fold(duty_cycle, |duty_cycle, band| band.avoid(duty_cycle));
+        if self.on {
+            self.pwm.set_duty_cycle(self.duty_ns(duty_cycle))?;
+            self.duty_cycle = duty_cycle;
+            Ok(false)
+        } else {
+            // a start may follow a re-initialization or a chip left at another period, so all four are written in order
+            self.pwm.apply(self.period, self.duty_ns(duty_cycle), Polarity::Normal, true)?;
+            if self.verify_enable {
+                self.verify_enable(duty_cycle)?;
+            }
+            self.duty_cycle = duty_cycle;
+            self.on = true;
+            self.starts += 1;
+            Ok(true)
+        }
+    }
+}
+
+
+pub struct Application {
+    source: Option<CommandLine>,
+    pidfile: Option<PidFile>,
+    fans: Vec<Fan>,
+    interval: Duration,
+    idle_interval: Option<Duration>,
+    idle: bool,
+    /// start of the previous cycle, for the elapsed time passed to the control
+    last_run: Option<Instant>,
+    /// control state of every fan saved on exit
+    state_file: Option<PathBuf>,
+    /// when the last cycle or SIGUSR2 failed and why, for the status socket and metrics; cleared by a successful cycle
+    last_error: Option<(Instant, String)>,
+    sigusr1_action: SignalAction,
+    /// the handled signals and what each asks for
+    signals: Vec<(Signal, SignalCommand)>,
+    status: Option<StatusServer>,
+    watchdog: Option<Watchdog>,
+    #[cfg(feature = "metrics")]
+    metrics: Option<metrics::MetricsServer>,
+    #[cfg(feature = "dbus")]
+    dbus: Option<dbus::DbusServer>,
+}
+
+impl Application {
+
+    pub fn new_from_command_line(cmd: &CommandLine) -> io::Result<Self> {
+        let args = Self::load_args(cmd)?;
+        let mut app = Self::new(args)?;
+        app.source = Some(cmd.clone());
+        Ok(app)
+    }
+
+    fn load_args(cmd: &CommandLine) -> io::Result<Args> {
+        let mut args = Args::default();
+        let path = cmd.config.as_path();
+        if path.extension().is_some_and(|ext| ext == "toml") {
+            #[cfg(feature = "toml")]
+            toml_file::parse_from_file(&mut args, path)?;
+            #[cfg(not(feature = "toml"))]
+            return Err(io::Error::new(io::ErrorKind::Unsupported, format!("{}: built without the `toml` feature", path.display())));
+        } else {
+            args.parse_from_file(path)?;
+        }
+        args.check_unknown_keys()?;
+        args.apply_overrides(&cmd.overrides)?;
+        args.observe = cmd.observe;
+        // command line overrides apply to every fan
+        for (_, fan) in args.fans.iter_mut() {
+            fan.apply_overrides(&cmd.overrides)?;
+            fan.observe = cmd.observe;
+        }
+        // a curve the control rejects is reported with the configuration, before any device is opened
+        for (name, fan) in Self::fan_args(&args) {
+            // a `temperature_unit` set after some of the temperatures mixes units, so the error names the conversion
+            let unit = if fan.temperature_unit == TemperatureUnit::Fahrenheit { " (temperatures converted from °F to °C)" } else { "" };
+            Fan::build_rule(&fan.curve_args())
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}{}{}", cmd.config.display(), Fan::prefix(name), e, unit)))?;
+        }
+        Ok(args)
+    }
+
+    /// SIGINT and SIGTERM terminate, the `signal_*` keys map their signals, SIGUSR1 runs `sigusr1_action`
+    /// unless a key took it, and SIGHUP and SIGUSR2 are ignored when no key does; a signal mapped twice is an error
+    fn signal_commands(args: &Args) -> io::Result<Vec<(Signal, SignalCommand)>> {
+        let mut signals = vec![(Signal(libc::SIGINT), SignalCommand::Terminate), (Signal(libc::SIGTERM), SignalCommand::Terminate)];
+        let keys = [
+            ("signal_boost", args.signal_boost, SignalCommand::Boost),
+            ("signal_reload", args.signal_reload, SignalCommand::Reload),
+            ("signal_clear_boost", args.signal_clear_boost, SignalCommand::ClearBoost),
+        ];
+        for (key, signal, command) in keys {
+            let Some(signal) = signal else {
+                continue;
+            };
+            if let Some((_, taken)) = signals.iter().find(|(s, _)| *s == signal) {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} = {}: signal already used for {:?}", key, signal, taken)));
+            }
+            signals.push((signal, command));
+        }
+        if signals.iter().all(|(signal, _)| signal.0 != libc::SIGUSR1) {
+            signals.push((Signal(libc::SIGUSR1), SignalCommand::Sigusr1));
+        }
+        // a default signal moved away or set to `none` would otherwise terminate the daemon
+        for signum in [libc::SIGHUP, libc::SIGUSR2] {
+            if signals.iter().all(|(signal, _)| signal.0 != signum) {
+                signals.push((Signal(signum), SignalCommand::Ignore));
+            }
+        }
+        log::debug!("signals: {}", signals.iter().map(|(signal, command)| format!("{}={:?}", signal, command)).collect::<Vec<_>>().join(", "));
+        Ok(signals)
+    }
+
+    /// the handled signals and what each asks for
+    pub fn signal_table(&self) -> &[(Signal, SignalCommand)] {
+        &self.signals
+    }
+
+    /// `(name, args)` of each fan: the `[fan.NAME]` sections, or the top-level section as a single unnamed fan
+    fn fan_args(args: &Args) -> Vec<(&str, &Args)> {
+        if args.fans.is_empty() {
+            vec![("", args)]
+        } else {
+            args.fans.iter().map(|(name, fan)| (name.as_str(), fan)).collect()
+        }
+    }
+
+    pub fn new(args: Args) -> io::Result<Self> {
+        unit::set_log_fahrenheit(args.log_fahrenheit);
+        logger::set_format(args.log_format);
+        if let Some(path) = args.log_file.as_ref() {
+            logger::open_file(path, args.log_max_bytes).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+            log::info!("log file opened: path={}, log_max_bytes={}", path.display(), args.log_max_bytes);
+        }
+        let signals = Self::signal_commands(&args)?;
+        if let Some(nice) = args.process_nice {
+            set_nice(nice)?;
+        }
+        if let Some(affinity) = args.cpu_affinity.as_ref() {
+            set_cpu_affinity(affinity);
+        }
+        let pidfile = match args.pidfile.as_ref() {
+            Some(path) => {
+                let pidfile = PidFile::create(path)?;
+                log::info!("pidfile created: path={}", pidfile.path().display());
+                Some(pidfile)
+            }
+            None => None,
+        };
+        let fan_args = Self::fan_args(&args);
+        let fans = fan_args
+            .iter()
+            .map(|(name, fan)| Fan::open(name, fan))
+            .collect::<io::Result<Vec<_>>>()?;
+        let watchdog = if args.watchdog_cycles > 0 {
+            let mut outputs = Vec::new();
+            for (fan, (_, args)) in fans.iter().zip(fan_args.iter()) {
+                outputs.extend(fan.watchdog_output(args)?);
+            }
+            // the loop legitimately sleeps for `idle_interval`
+            let interval = args.idle_interval.unwrap_or_default().max(args.interval);
+            let timeout = interval.saturating_mul(args.watchdog_cycles as u32);
+            log::info!("watchdog started: timeout={}ms, outputs={}", timeout.as_millis(), outputs.len());
+            Some(Watchdog::spawn(timeout, outputs)?)
+        } else {
+            None
+        };
+        let status = match args.status_socket.as_ref() {
+            Some(path) => {
+                let status = StatusServer::bind(path)?;
+                log::info!("status socket listening: path={}", status.path().display());
+                Some(status)
+            }
+            None => None,
+        };
+        #[cfg(feature = "metrics")]
+        let metrics = match args.metrics_address.as_ref() {
+            Some(address) => Some(metrics::MetricsServer::bind(address.as_str())?),
+            None => None,
+        };
+        #[cfg(not(feature = "metrics"))]
+        if args.metrics_address.is_some() {
+            log::warn!("metrics_address is set but fanctrl was built without the `metrics` feature");
+        }
+        // the bus is for desktop integration, the fans run without it
+        #[cfg(feature = "dbus")]
+        let dbus = if args.dbus {
+            dbus::DbusServer::connect(signal::wake)
+                .map_err(|e| log::warn!("failed to connect to dbus, continuing without it: {}", e))
+                .ok()
+        } else {
+            None
+        };
+        #[cfg(not(feature = "dbus"))]
+        if args.dbus {
+            log::warn!("dbus is set but fanctrl was built without the `dbus` feature");
+        }
+        let mut app = Self::with_fans(&args, fans);
+        if let Some(path) = args.state_file.as_ref() {
+            app.resume(path);
+        }
+        app.state_file = args.state_file.clone();
+        app.signals = signals;
+        app.pidfile = pidfile;
+        app.status = status;
+        app.watchdog = watchdog;
+        #[cfg(feature = "metrics")]
+        {
+            app.metrics = metrics;
+        }
+        #[cfg(feature = "dbus")]
+        {
+            app.dbus = dbus;
+            app.publish_status();
+        }
+        Ok(app)
+    }
+
+    /// a single unnamed fan around the given sensor and pwm, e.g. mock devices for replay
+    pub fn with_devices(args: Args, sensor: Box<dyn SensorSource>, pwm: Box<dyn FanOutput>) -> io::Result<Self> {
+        let fan = Fan::new("", &args, sensor, pwm)?;
+        Ok(Self::with_fans(&args, vec![fan]))
+    }
+
+    fn with_fans(args: &Args, fans: Vec<Fan>) -> Self {
+        if args.sigusr1_action == SignalAction::ToggleQuiet && fans.iter().all(|fan| fan.alternate_rule.is_none()) {
+            log::warn!("sigusr1_action = toggle_quiet without a [quiet] section; SIGUSR1 will have no effect");
+        }
+        log::info!("control initialized: interval={}ms, fans={}", args.interval.as_millis(), fans.len());
+        Self {
+            source: None,
+            pidfile: None,
+            fans,
+            interval: args.interval,
+            idle_interval: args.idle_interval.map(|idle_interval| idle_interval.max(args.interval)),
+            idle: false,
+            last_run: None,
+            state_file: None,
+            last_error: None,
+            sigusr1_action: args.sigusr1_action,
+            signals: Vec::new(),
+            status: None,
+            watchdog: None,
+            #[cfg(feature = "metrics")]
+            metrics: None,
+            #[cfg(feature = "dbus")]
+            dbus: None,
+        }
+    }
+
+    /// sends a `FanEvent` for each start, stop, duty change, max speed and critical reading of every fan
+    pub fn set_events(&mut self, events: Sender<FanEvent>) {
+        for fan in self.fans.iter_mut() {
+            fan.events = Some(events.clone());
+        }
+    }
+
+    pub fn initial(&mut self) -> io::Result<()> {
+        for fan in self.fans.iter_mut() {
+            fan.initial()?;
+        }
+        self.last_run = Some(Instant::now());
+        self.publish_status();
+        Ok(())
+    }
+
+    /// runs every fan with the time measured since the previous cycle
+    pub fn run(&mut self) -> io::Result<()> {
+        let now = Instant::now();
+        let dt = self.last_run.map_or(self.interval, |last_run| now.duration_since(last_run));
+        self.last_run = Some(now);
+        self.run_for(dt)
+    }
+
+    /// runs every fan even if one fails, `dt` after the previous cycle; returns the first error
+    pub fn run_for(&mut self, dt: Duration) -> io::Result<()> {
+        if self.watchdog.as_ref().is_some_and(Watchdog::take_fired) {
+            for fan in self.fans.iter_mut() {
+                fan.watchdog_fired();
+            }
+        }
+        let mut result = Ok(());
+        for fan in self.fans.iter_mut() {
+            if let Err(e) = fan.run(dt) {
+                if result.is_ok() {
+                    result = Err(io::Error::new(e.kind(), format!("{}{}", fan.prefix, e)));
+                } else {
+                    log::error!("{}failed to run loop: {:?}", fan.prefix, e);
+                }
+            }
+            fan.summary();
+        }
+        self.last_error = result.as_ref().err().map(|e| (Instant::now(), e.to_string()));
+        self.publish_status();
+        if let Some(watchdog) = self.watchdog.as_ref() {
+            watchdog.feed();
+        }
+        result
+    }
+
+    /// `idle_interval` while every fan is idle, `interval` otherwise
+    pub fn next_interval(&mut self) -> Duration {
+        let idle_interval = match self.idle_interval {
+            Some(idle_interval) => idle_interval,
+            None => return self.interval,
+        };
+        let idle = self.fans.iter().all(Fan::is_idle);
+        if idle != self.idle {
+            self.idle = idle;
+            let interval = if idle { idle_interval } else { self.interval };
+            log::debug!("{} idle, polling every {}ms", if idle { "entering" } else { "leaving" }, interval.as_millis());
+        }
+        if idle { idle_interval } else { self.interval }
+    }
+
+    /// re-reads the configuration and swaps only the curves, keeping the control timing state
+    pub fn reload_curve(&mut self) -> io::Result<()> {
+        let cmd = match self.source.as_ref() {
+            Some(cmd) => cmd,
+            None => return Ok(()),
+        };
+        let args = Self::load_args(cmd)?;
+        let fan_args = Self::fan_args(&args);
+        for fan in self.fans.iter_mut() {
+            match fan_args.iter().find(|(name, _)| *name == fan.name) {
+                Some((_, args)) => fan.reload_curve(args)?,
+                None => log::warn!("{}fan no longer configured; keeping its curve until restart", fan.prefix),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn on_sigusr1(&mut self) {
+        match self.sigusr1_action {
+            SignalAction::Status => {
+                log::debug!("receive SIGUSR1");
+            }
+            SignalAction::ToggleQuiet => {
+                for fan in self.fans.iter_mut() {
+                    fan.toggle_quiet();
+                }
+            }
+            SignalAction::ClearBoost => {
+                self.clear_max_speed();
+            }
+        }
+    }
+
+    /// runs the action of `command` received as `signal`; `Terminate` is left to the loop
+    pub fn on_signal(&mut self, signal: Signal, command: SignalCommand) {
+        match command {
+            SignalCommand::Terminate => {}
+            SignalCommand::Boost => {
+                log::debug!("receive {} to maximum fan speed", signal);
+                if let Err(e) = self.run_max_speed() {
+                    log::error!("failed to set fan speed to maximum: {:?}", e);
+                }
+            }
+            SignalCommand::Reload => {
+                log::debug!("receive {} to reload curve", signal);
+                if let Err(e) = self.reload_curve() {
+                    log::error!("failed to reload curve: {:?}", e);
+                }
+            }
+            SignalCommand::ClearBoost => {
+                log::debug!("receive {} to clear maximum fan speed", signal);
+                self.clear_max_speed();
+            }
+            SignalCommand::Sigusr1 => {
+                self.on_sigusr1();
+            }
+            SignalCommand::Ignore => {
+                log::debug!("receive {} with no action", signal);
+            }
+        }
+    }
+
+    /// runs the commands queued by dbus method calls
+    #[cfg(feature = "dbus")]
+    pub fn poll_dbus(&mut self) {
+        let commands: Vec<dbus::Command> = match self.dbus.as_ref() {
+            Some(dbus) => dbus.commands().collect(),
+            None => return,
+        };
+        for command in commands {
+            match command {
+                dbus::Command::Boost(cycles) => {
+                    let time = self.boost_time(cycles);
+                    if let Err(e) = self.run_max_speed_for(time) {
+                        log::error!("failed to set fan speed to maximum: {:?}", e);
+                    }
+                }
+                dbus::Command::ReloadConfig => {
+                    if let Err(e) = self.reload_curve() {
+                        log::error!("failed to reload curve: {:?}", e);
+                    }
+                }
+            }
+        }
+        self.publish_status();
+    }
+
+    /// `cycles` of a dbus `Boost` as a time, at most the longest `max_speed_time_cycle`, so any user allowed on the
+    /// bus cannot hold the fans at maximum indefinitely; `None` for 0
+    #[cfg(feature = "dbus")]
+    fn boost_time(&self, cycles: u32) -> Option<Duration> {
+        let limit = self.fans.iter().map(|fan| fan.max_speed_time).max().unwrap_or_default();
+        let time = (cycles > 0).then(|| self.interval.saturating_mul(cycles))?;
+        if time > limit {
+            log::warn!("dbus boost of {} cycles capped to {}ms by max_speed_time_cycle", cycles, limit.as_millis());
+        }
+        Some(time.min(limit))
+    }
+
+    /// replaces what a dbus method call wakes, `signal::wake` by default
+    #[cfg(feature = "dbus")]
+    pub fn set_dbus_waker(&self, waker: impl Fn() + Send + 'static) {
+        if let Some(dbus) = self.dbus.as_ref() {
+            dbus.set_waker(waker);
+        }
+    }
+
+    pub fn clear_max_speed(&mut self) {
+        for fan in self.fans.iter_mut() {
+            fan.clear_max_speed();
+        }
+    }
+
+    pub fn run_max_speed(&mut self) -> io::Result<()> {
+        self.run_max_speed_for(None)
+    }
+
+    /// `time` overrides the `max_speed_time_cycle` of every fan
+    pub fn run_max_speed_for(&mut self, time: Option<Duration>) -> io::Result<()> {
+        let result = self.fans
+            .iter_mut()
+            .try_for_each(|fan| fan.run_max_speed(time).map_err(|e| io::Error::new(e.kind(), format!("{}{}", fan.prefix, e))));
+        if let Err(e) = result.as_ref() {
+            self.last_error = Some((Instant::now(), e.to_string()));
+            self.publish_status();
+        }
+        result
+    }
+
+    pub fn terminate(&mut self) -> io::Result<()> {
+        if let Some(pidfile) = self.pidfile.take() {
+            if let Err(e) = pidfile.remove() {
+                log::warn!("failed to remove pidfile: {:?}", e);
+            }
+        }
+        // dropping the server removes the socket file
+        self.status = None;
+        // the watchdog must not enable the pwm again once the fans are stopped
+        self.watchdog = None;
+        let mut result = Ok(());
+        for fan in self.fans.iter_mut() {
+            if let Err(e) = fan.terminate() {
+                log::error!("{}failed to terminate: {:?}", fan.prefix, e);
+                result = Err(e);
+            }
+        }
+        if let Some(path) = self.state_file.as_ref() {
+            let state = StateFile::new(self.fans.iter().map(|fan| (fan.name.clone(), fan.save_state())).collect());
+            match state.write(path) {
+                Ok(()) => log::info!("control state saved: path={}", path.display()),
+                Err(e) => log::warn!("failed to save control state to {}: {:?}", path.display(), e),
+            }
+        }
+        result
+    }
+
+    /// restores the control state saved in `path` by the previous run; a missing file, or a state that does not
+    /// fit the current configuration, starts the fan as usual
+    fn resume(&mut self, path: &Path) {
+        let saved = match StateFile::read(path) {
+            Ok(Some(saved)) => saved,
+            Ok(None) => return,
+            Err(e) => {
+                log::warn!("ignoring control state {}: {}", path.display(), e);
+                return;
+            }
+        };
+        let age = saved.age().unwrap_or(self.interval);
+        for fan in self.fans.iter_mut() {
+            let state = match saved.fans.get(&fan.name) {
+                Some(state) => state,
+                None => continue,
+            };
+            match fan.load_state(state) {
+                Ok(()) => {
+                    log::info!("{}control state resumed: state={}, saved {}ms ago", fan.prefix, fan.control.state_name(), age.as_millis());
+                    fan.resumed = Some(age);
+                }
+                Err(e) => log::warn!("ignoring saved control state: {}", e),
+            }
+        }
+    }
+
+    fn publish_status(&self) {
+        if let Some(status) = self.status.as_ref() {
+            status.update(
+                self.fans
+                    .iter()
+                    .map(|fan| Status { last_error: self.last_error.clone(), ..fan.status() })
+                    .collect()
+            );
+        }
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = self.metrics.as_ref() {
+            metrics.update(
+                self.fans
+                    .iter()
+                    .map(|fan| metrics::Metrics {
+                        fan: fan.name.clone(),
+                        temperature: fan.last_temperature,
+                        duty_cycle: if fan.on { fan.duty_cycle } else { 0.0 },
+                        on: fan.on,
+                        fan_starts: fan.starts,
+                    })
+                    .collect(),
+                self.last_error.as_ref().map(|(time, _)| *time),
+            );
+        }
+        #[cfg(feature = "dbus")]
+        if let Some(dbus) = self.dbus.as_ref() {
+            dbus.update(dbus::Properties {
+                temperature: self.fans.iter().map(|fan| fan.last_temperature).fold(f32::NAN, f32::max),
+                duty_cycle: self.fans.iter().filter(|fan| fan.on).map(|fan| fan.duty_cycle).fold(0.0, f32::max),
+                on: self.fans.iter().any(|fan| fan.on),
+            });
+        }
+    }
+}
+
+
+/// canonicalize a configured sysfs path, resolving symlinks and `..`
+fn resolve_path(path: &Path) -> io::Result<PathBuf> {
+    fs::canonicalize(path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+}
+
+
+/// runs `initial()` on the first trace line and `run()` on each following one; returns the number of mismatching cycles
+pub fn replay(cmd: &CommandLine, trace: &Path) -> io::Result<usize> {
+    let lines = replay::parse_trace(trace)?;
+    let replay = Replay::default();
+    let (sensor, pwm) = replay.devices();
+    let mut app = Application::with_devices(Application::load_args(cmd)?, sensor, pwm)?;
+    let mut mismatches = 0;
+    for (i, line) in lines.iter().enumerate() {
+        replay.set_temperature(line.temperature);
+        if i == 0 {
+            app.initial()?;
+        } else {
+            // the trace was recorded at the configured interval
+            app.run_for(app.interval)?;
+        }
+        if let Some(diff) = replay.check(line) {
+            println!("{}", diff);
+            mismatches += 1;
+        }
+    }
+    println!("replayed {} cycles from {}: {} mismatches", lines.len(), trace.display(), mismatches);
+    Ok(mismatches)
+}
+
+
+/// runs the top-level fan against mock devices over a `seconds,temperature` profile, `initial()` on the first
+/// sample and `run_for()` the time between samples on each following one; prints the output of each sample as
+/// CSV and the `Summary` as a trailing `#` line
+pub fn simulate(cmd: &CommandLine, profile: &Path) -> io::Result<()> {
+    // the logger writes to stdout, which carries the CSV
+    log::set_max_level(log::LevelFilter::Off);
+    let samples = simulate::parse_profile(profile)?;
+    let replay = Replay::default();
+    let (sensor, pwm) = replay.devices();
+    let mut app = Application::with_devices(Application::load_args(cmd)?, sensor, pwm)?;
+    let mut summary = Summary::default();
+    println!("seconds,temperature,duty_cycle,on");
+    for (i, sample) in samples.iter().enumerate() {
+        replay.set_temperature(sample.temperature);
+        let dt = if i == 0 {
+            app.initial()?;
+            Duration::ZERO
+        } else {
+            let dt = Duration::from_secs_f64(sample.seconds - samples[i - 1].seconds);
+            app.run_for(dt)?;
+            dt
+        };
+        let status = app.fans[0].status();
+        summary.add(dt, status.on, status.duty_cycle);
+        println!("{},{:.2},{:.4},{}", sample.seconds, sample.temperature, status.duty_cycle, status.on as u8);
+    }
+    println!("# {}", summary);
+    Ok(())
+}
+
+
+/// one cycle for an external scheduler: each fan resumes its control state from `state_file` and runs once,
+/// a fan without a usable saved state runs `initial()` instead; the fans are left running and the state is saved
+pub fn once(cmd: &CommandLine) -> io::Result<()> {
+    let args = Application::load_args(cmd)?;
+    let path = args.state_file
+        .as_ref()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "--once needs state_file"))?;
+    let fans = Application::fan_args(&args)
+        .iter()
+        .map(|(name, fan)| Fan::open(name, fan))
+        .collect::<io::Result<Vec<_>>>()?;
+    let mut app = Application::with_fans(&args, fans);
+    let saved = StateFile::read(path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+    // the time since the last run stands in for the interval
+    let dt = saved.as_ref().and_then(StateFile::age).unwrap_or(app.interval);
+    for fan in app.fans.iter_mut() {
+        let loaded = match saved.as_ref().and_then(|saved| saved.fans.get(&fan.name)) {
+            Some(state) => fan.load_state(state).map_err(|e| log::warn!("ignoring saved control state: {}", e)).is_ok(),
+            None => false,
+        };
+        if loaded {
+            // the previous run left the pwm at the saved duty cycle
+            fan.on = fan.control.current_duty().is_some();
+            fan.run(dt).map_err(|e| io::Error::new(e.kind(), format!("{}{}", fan.prefix, e)))?;
+        } else {
+            fan.initial()?;
+        }
+    }
+    let state = StateFile::new(app.fans.iter().map(|fan| (fan.name.clone(), fan.save_state())).collect());
+    state.write(path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+}
+
+
+/// parses the configuration, reads each sensor, probes each pwm and builds each control without writing to the pwm
+pub fn check(cmd: &CommandLine) -> io::Result<()> {
+    let args = Application::load_args(cmd)?;
+    println!("config: {} ok", cmd.config.display());
+    for (name, fan) in Application::fan_args(&args) {
+        let prefix = Fan::prefix(name);
+        check_fan(name, &prefix, fan).map_err(|e| io::Error::new(e.kind(), format!("{}{}", prefix, e)))?;
+    }
+    Ok(())
+}
+
+fn check_fan(name: &str, prefix: &str, args: &Args) -> io::Result<()> {
+    let (mut sensor, source): (Box<dyn SensorSource>, String) = match args.sensor_command.as_ref() {
+        Some(command) => (Box::new(CommandSensor::new(command.as_str(), args.sensor_scale, Duration::from_millis(args.sensor_command_timeout))?), command.clone()),
+        None => {
+            let watch = resolve_path(args.watch.as_path())?;
+            (Box::new(SensorDevice::new(watch.as_path(), args.sensor_scale)?), watch.display().to_string())
+        }
+    };
+    let temperature = sensor.get().map_err(|e| io::Error::new(e.kind(), format!("{}: {}", source, e)))?;
+    println!("{}sensor: {} ok, {:.2}°C", prefix, source, temperature);
+    let execute = if args.fan_kind == FanKind::CoolingDevice {
+        let path = resolve_path(Fan::cooling_device_path("", args)?)?;
+        let max_state = cooling::probe(path.as_path())?;
+        println!("{}cooling device: {} ok, max_state={}", prefix, path.display(), max_state);
+        path
+    } else if args.fan_kind == FanKind::Gpio {
+        let line = Fan::gpio_line("", args)?;
+        let exported = gpio::probe(line)?;
+        println!("{}gpio: {} ok, {}", prefix, gpio::line_path(line).display(), if exported { "value writable" } else { "export writable" });
+        gpio::line_path(line)
+    } else {
+        let execute = resolve_path(args.execute.as_path())?;
+        let exported = pwm::probe(execute.as_path(), 0)?;
+        println!("{}pwm: {} ok, {}", prefix, execute.display(), if exported { "pwm0 writable" } else { "export writable" });
+        execute
+    };
+    if let Some(path) = args.defer_cooling_device.as_ref() {
+        let resolved = resolve_path(path)?;
+        CoolingDevice::new(resolved.as_path())?.is_active()?;
+        println!("{}cooling device: {} ok", prefix, resolved.display());
+    }
+    let rule = Fan::build_rule(&args.curve_args())?;
+    Fan::new(name, args, sensor, Box::new(NullPWMDevice::new(execute.as_path(), 0)))?;
+    println!("{}control: {} ok", prefix, rule);
+    Ok(())
+}
+
+
+/// prints `Rule::map` of each fan for every degree from `stop_temperature - 5` to `high_temperature + 5` as CSV;
+/// a `fan` column is added with `[fan.NAME]` sections
+pub fn plot(cmd: &CommandLine) -> io::Result<()> {
+    // the logger writes to stdout, which carries the CSV
+    log::set_max_level(log::LevelFilter::Off);
+    let args = Application::load_args(cmd)?;
+    let named = !args.fans.is_empty();
+    let rules = Application::fan_args(&args)
+        .into_iter()
+        .map(|(name, fan)| Ok((name, Fan::build_rule(&fan.curve_args())?)))
+        .collect::<io::Result<Vec<_>>>()?;
+    if named {
+        println!("fan,temperature,duty_cycle");
+    } else {
+        println!("temperature,duty_cycle");
+    }
+    for (name, rule) in rules {
+        let low = (rule.stop_temperature() - 5.0).floor() as i32;
+        let high = (rule.high_temperature() + 5.0).ceil() as i32;
+        for t in low..=high {
+            let duty_cycle = rule.map(t as f32);
+            if named {
+                println!("{},{},{:.4}", name, t, duty_cycle);
+            } else {
+                println!("{},{:.4}", t, duty_cycle);
+            }
+        }
+    }
+    Ok(())
+}
+
+
+/// EPERM (raising priority without CAP_SYS_NICE) is logged and ignored
+fn set_nice(nice: i32) -> io::Result<()> {
+    if !(-20..=19).contains(&nice) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("invalid value `{}` for process_nice: expected -20 to 19", nice)));
+    }
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) } != 0 {
+        let e = io::Error::last_os_error();
+        if e.raw_os_error() == Some(libc::EPERM) || e.raw_os_error() == Some(libc::EACCES) {
+            log::warn!("not permitted to set process_nice={}, keeping the current priority: {}", nice, e);
+            return Ok(());
+        }
+        return Err(e);
+    }
+    log::info!("process priority set: nice={}", nice);
+    Ok(())
+}
+
+
+/// restricts the process to `affinity`, logging the CPUs it ends up on; a failure is logged and ignored
+fn set_cpu_affinity(affinity: &CpuAffinity) {
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    for &cpu in affinity.cpus.iter() {
+        unsafe { libc::CPU_SET(cpu, &mut set) };
+    }
+    if unsafe { libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) } != 0 {
+        log::warn!("failed to set cpu_affinity={}, keeping the current cpus: {}", affinity, io::Error::last_os_error());
+        return;
+    }
+    let mut applied: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    if unsafe { libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut applied) } != 0 {
+        log::info!("cpu affinity set: cpu_affinity={}", affinity);
+        return;
+    }
+    let cpus: Vec<String> = (0..libc::CPU_SETSIZE as usize)
+        .filter(|&cpu| unsafe { libc::CPU_ISSET(cpu, &applied) })
+        .map(|cpu| cpu.to_string())
+        .collect();
+    log::info!("cpu affinity set: cpu_affinity={}, cpus={}", affinity, cpus.join(","));
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::replay::Replay;
+
+    fn fan(overrides: &[(&str, &str)]) -> Fan {
+        let mut args = Args::default();
+        let overrides: Vec<(String, String)> = overrides.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        args.apply_overrides(&overrides).unwrap();
+        let (sensor, pwm) = Replay::default().devices();
+        Fan::new("", &args, sensor, pwm).unwrap()
+    }
+
+    #[test]
+    fn tick_follows_temperature() {
+        let mut fan = fan(&[]);
+        let dt = Duration::from_secs(5);
+        let actions: Vec<Action> = [35.0, 45.0, 45.0, 70.0].iter().map(|t| fan.tick(*t, dt)).collect();
+        assert_eq!(actions, [Action::Stop, Action::Set(0.56666666), Action::Keep, Action::Set(0.9)]);
+        // falling below stop_temperature keeps the duty for lag_time_cycle = 8 cycles, then stops
+        let falling: Vec<Action> = (0..10).map(|_| fan.tick(25.0, dt)).collect();
+        assert!(falling[..9].iter().all(|action| *action == Action::Keep));
+        assert_eq!(falling[9], Action::Stop);
+    }
+    #[test]
+    fn tick_critical_overrides_control() {
+        let mut fan = fan(&[("critical_temperature", "80"), ("critical_action", "poweroff"), ("critical_time_cycle", "2")]);
+        let dt = Duration::from_secs(5);
+        let actions: Vec<Action> = [85.0, 85.0, 85.0].iter().map(|t| fan.tick(*t, dt)).collect();
+        assert_eq!(actions, [
+            Action::Critical { duty_cycle: 0.9, poweroff: false },
+            Action::Critical { duty_cycle: 0.9, poweroff: false },
+            Action::Critical { duty_cycle: 0.9, poweroff: true },
+        ]);
+    }
+    #[test]
+    fn events_follow_the_fan() {
+        let replay = Replay::default();
+        let (sensor, pwm) = replay.devices();
+        let mut app = Application::with_devices(Args::default(), sensor, pwm).unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        app.set_events(sender);
+        replay.set_temperature(35.0);
+        app.initial().unwrap();
+        let mut temperatures = vec![45.0, 70.0];
+        temperatures.resize(12, 25.0);
+        for temperature in temperatures {
+            replay.set_temperature(temperature);
+            app.run_for(Duration::from_secs(5)).unwrap();
+        }
+        let events: Vec<FanEvent> = receiver.try_iter().collect();
+        assert_eq!(events, [
+            FanEvent::Started { fan: String::new(), temperature: 35.0, duty_cycle: 0.5 },
+            FanEvent::DutyChanged { fan: String::new(), temperature: 45.0, duty_cycle: 0.56666666 },
+            FanEvent::DutyChanged { fan: String::new(), temperature: 70.0, duty_cycle: 0.9 },
+            FanEvent::Stopped { fan: String::new(), temperature: 25.0 },
+        ]);
+    }
+    #[test]
+    fn boost_ending_near_start_temperature_keeps_the_fan() {
+        let mut fan = fan(&[("max_speed_time_cycle", "2")]);
+        let dt = Duration::from_secs(5);
+        fan.run_max_speed(None).unwrap();
+        assert_eq!(fan.tick(41.0, dt), Action::Keep);
+        assert_eq!(fan.tick(41.0, dt), Action::Keep);
+        // end_max_speed re-enters the lag at the curve duty instead of the state before the boost
+        match fan.tick(41.0, dt) {
+            Action::Set(duty_cycle) => assert!((duty_cycle - (0.5 + 0.4 / 30.0)).abs() < 1e-6),
+            action => panic!("expected the curve duty, got {:?}", action),
+        }
+        // below start_temperature but above stop_temperature: the fan keeps running through the lag and after it
+        let after: Vec<Action> = (0..12).map(|_| fan.tick(39.0, dt)).collect();
+        assert!(after[..8].iter().all(|action| *action == Action::Keep));
+        assert!(!after.contains(&Action::Stop));
+    }
+    #[test]
+    fn max_duty_cycle_as_percentage() {
+        let mut fan = fan(&[("max_duty_cycle", "100%")]);
+        assert_eq!(fan.tick(75.0, Duration::from_secs(5)), Action::Set(1.0));
+        let mut args = Args::default();
+        args.apply_overrides(&[(String::from("max_duty_cycle"), String::from("90"))]).unwrap();
+        let (sensor, pwm) = Replay::default().devices();
+        let e = Fan::new("", &args, sensor, pwm).err().unwrap();
+        assert!(e.to_string().contains("greater than 1; write a fraction like 0.5 or a percentage like 50%"), "{}", e);
+    }
+    #[test]
+    fn interval_with_units() {
+        for (value, interval) in [("5s", Duration::from_secs(5)), ("500ms", Duration::from_millis(500)), ("2m", Duration::from_secs(120)), ("5000", Duration::from_secs(5))] {
+            let mut args = Args::default();
+            args.apply_overrides(&[(String::from("interval"), String::from(value)), (String::from("idle_interval"), String::from(value))]).unwrap();
+            assert_eq!((args.interval, args.idle_interval), (interval, Some(interval)), "{}", value);
+        }
+        let mut args = Args::default();
+        assert!(args.apply_overrides(&[(String::from("interval"), String::from("5 seconds"))]).is_err());
+    }
+    #[test]
+    fn control_kind_is_parsed_with_the_config() {
+        let mut args = Args::default();
+        args.apply_overrides(&[(String::from("control"), String::from("stateless"))]).unwrap();
+        assert_eq!(args.control, ControlKind::Stateless);
+        // a typo is reported while the config is read, not when the fan is built
+        let e = args.apply_overrides(&[(String::from("control"), String::from("hysteresys"))]).unwrap_err();
+        assert!(e.to_string().contains("control"), "{}", e);
+    }
+    #[test]
+    fn nice_is_an_alias_of_process_nice() {
+        let mut args = Args::default();
+        args.apply_overrides(&[(String::from("nice"), String::from("10"))]).unwrap();
+        assert_eq!(args.process_nice, Some(10));
+    }
+    /// a chip that keeps its polarity whatever is written
+    #[derive(Debug)]
+    struct FixedPolarity {
+        period: u32,
+    }
+
+    impl FanOutput for FixedPolarity {
+
+        fn set_period(&mut self, period: u32) -> io::Result<()> {
+            self.period = period;
+            Ok(())
+        }
+
+        fn set_duty_cycle(&mut self, duty_cycle: u32) -> io::Result<u32> {
+            Ok(duty_cycle)
+        }
+
+        fn set_polarity(&mut self, _polarity: Polarity) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn set_enable(&mut self, _enable: bool) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn get_period(&mut self) -> io::Result<u32> {
+            Ok(self.period)
+        }
+
+        fn get_polarity(&mut self) -> io::Result<Polarity> {
+            Ok(Polarity::Inversed)
+        }
+
+        fn get_enable(&mut self) -> io::Result<bool> {
+            Ok(true)
+        }
+    }
+
+    #[test]
+    fn verify_pwm_reports_polarity_mismatch() {
+        let replay = Replay::default();
+        replay.set_temperature(45.0);
+        let mut args = Args::default();
+        args.apply_overrides(&[(String::from("verify_pwm"), String::from("true"))]).unwrap();
+        let (sensor, _) = replay.devices();
+        let mut fan = Fan::new("", &args, sensor, Box::new(FixedPolarity { period: 0 })).unwrap();
+        let e = fan.initial().unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(e.to_string(), "polarity normal not supported on this chip (read back inversed); the fan may need inverted wiring");
+        // off by default: the same chip starts
+        let (sensor, _) = replay.devices();
+        let mut fan = Fan::new("", &Args::default(), sensor, Box::new(FixedPolarity { period: 0 })).unwrap();
+        fan.initial().unwrap();
+    }
+    /// a thermal zone `zone` at `temperature` millidegrees and a chip `chip` with an exported pwm0, in a fresh
+    /// directory under the temporary directory
+    fn sysfs_dir(name: &str, temperature: i32) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("fanctrl-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("zone")).unwrap();
+        fs::create_dir_all(root.join("chip/pwm0")).unwrap();
+        fs::write(root.join("zone/temp"), format!("{}\n", temperature)).unwrap();
+        for (attribute, value) in [("period", "0"), ("duty_cycle", "0"), ("polarity", "normal"), ("enable", "0")] {
+            fs::write(root.join("chip/pwm0").join(attribute), format!("{}\n", value)).unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn observe_reads_the_sensor_but_never_writes_the_pwm() {
+        let root = sysfs_dir("observe", 45000);
+        let args = Args { watch: root.join("zone"), execute: root.join("chip"), observe: true, ..Args::default() };
+        let mut fan = Fan::open("", &args).unwrap();
+        fan.initial().unwrap();
+        assert_eq!(fan.status().temperature, 45.0);
+        fs::write(root.join("zone/temp"), "70000\n").unwrap();
+        fan.run(Duration::from_secs(5)).unwrap();
+        let status = fan.status();
+        assert_eq!((status.temperature, status.duty_cycle, status.on), (70.0, 0.9, true));
+        for (attribute, value) in [("period", "0"), ("duty_cycle", "0"), ("polarity", "normal"), ("enable", "0")] {
+            assert_eq!(fs::read_to_string(root.join("chip/pwm0").join(attribute)).unwrap(), format!("{}\n", value), "{}", attribute);
+        }
+        fs::remove_dir_all(&root).unwrap();
+    }
+    #[test]
+    fn symlinked_paths_are_canonicalized() {
+        let root = sysfs_dir("symlink", 45000);
+        std::os::unix::fs::symlink(root.join("zone"), root.join("zone-link")).unwrap();
+        std::os::unix::fs::symlink(root.join("chip"), root.join("chip-link")).unwrap();
+        let canonical = fs::canonicalize(root.join("zone")).unwrap();
+        assert_eq!(resolve_path(&root.join("zone-link")).unwrap(), canonical);
+        assert_eq!(resolve_path(&root.join("chip-link/../zone-link/")).unwrap(), canonical);
+        let e = resolve_path(&root.join("missing")).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+        assert!(e.to_string().starts_with(&root.join("missing").display().to_string()), "{}", e);
+        // the devices open through the links
+        let args = Args { watch: root.join("zone-link"), execute: root.join("chip-link"), ..Args::default() };
+        let mut fan = Fan::open("", &args).unwrap();
+        fan.initial().unwrap();
+        assert_eq!(fs::read_to_string(root.join("chip/pwm0/enable")).unwrap().trim(), "1");
+        fs::remove_dir_all(&root).unwrap();
+    }
+    #[test]
+    fn repeated_max_speed_logs_at_debug_within_the_window() {
+        let mut windowed = fan(&[("max_speed_log_window_cycle", "12")]);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        // a 60s window at the 5s default interval
+        let levels: Vec<log::Level> = [0, 5, 30, 59, 60, 61].into_iter().map(|secs| windowed.max_speed_log_level(at(secs))).collect();
+        assert_eq!(levels, [log::Level::Info, log::Level::Debug, log::Level::Debug, log::Level::Debug, log::Level::Info, log::Level::Debug]);
+        // without a window every engage is logged at info
+        let mut unwindowed = fan(&[]);
+        assert!([0, 1, 2].into_iter().all(|secs| unwindowed.max_speed_log_level(at(secs)) == log::Level::Info));
+    }
+    #[test]
+    fn sigusr1_alternates_the_curve() {
+        let mut args = Args::default();
+        args.apply_overrides(&[(String::from("sigusr1_action"), String::from("toggle_quiet"))]).unwrap();
+        args.apply("quiet", "max_duty_cycle", Some("0.6")).unwrap();
+        let (sensor, pwm) = Replay::default().devices();
+        let mut app = Application::with_devices(args, sensor, pwm).unwrap();
+        assert_eq!(app.fans[0].control.map(75.0), 0.9);
+        let mut duty_cycles = Vec::new();
+        for _ in 0..4 {
+            app.on_signal(Signal(libc::SIGUSR1), SignalCommand::Sigusr1);
+            duty_cycles.push(app.fans[0].control.map(75.0));
+        }
+        assert_eq!(duty_cycles, [0.6, 0.9, 0.6, 0.9]);
+        assert!(!app.fans[0].quiet);
+    }
+    #[test]
+    fn min_duty_ns_raises_a_short_pulse() {
+        let bounded = fan(&[("min_duty_ns", "60000"), ("max_duty_ns", "85000")]);
+        // 50% of the 100000ns period of 10 kHz is below the minimum pulse width
+        assert_eq!(bounded.duty_ns(0.5), 60000);
+        assert_eq!(bounded.duty_ns(0.7), 70000);
+        assert_eq!(bounded.duty_ns(0.9), 85000);
+        let unbounded = fan(&[]);
+        assert_eq!(unbounded.duty_ns(0.5), 50000);
+    }
+    #[test]
+    fn set_nice_lowers_the_priority() {
+        assert_eq!(set_nice(20).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(set_nice(-21).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        // raising the nice value needs no privilege; on Linux it applies to the calling thread only
+        std::thread::spawn(|| {
+            let current = unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) };
+            let nice = (current + 1).min(19);
+            set_nice(nice).unwrap();
+            assert_eq!(unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) }, nice);
+        }).join().unwrap();
+    }
+    #[test]
+    fn increases_are_deferred_while_the_cooling_device_is_active() {
+        let sysfs = std::sync::Arc::new(crate::sysfs::MemorySysfs::new());
+        sysfs.set("/cooling/cur_state", 1);
+        let mut deferring = fan(&[("defer_time_cycle", "3")]);
+        deferring.cooling_device = Some(CoolingDevice::with_sysfs(sysfs.clone(), "/cooling").unwrap());
+        assert_eq!(deferring.defer(Action::Set(0.6)).unwrap(), Action::Keep);
+        assert_eq!(deferring.defer(Action::Keep).unwrap(), Action::Keep);
+        // the held-back duty cycle is applied once the device goes idle
+        sysfs.set("/cooling/cur_state", 0);
+        assert_eq!(deferring.defer(Action::Keep).unwrap(), Action::Set(0.6));
+        // a device that stays active holds increases back for defer_time_cycle cycles only
+        sysfs.set("/cooling/cur_state", 2);
+        let actions: Vec<Action> = (0..4).map(|_| deferring.defer(Action::Set(0.7)).unwrap()).collect();
+        assert_eq!(actions, [Action::Keep, Action::Keep, Action::Keep, Action::Set(0.7)]);
+    }
+    #[test]
+    fn invert_duty_writes_the_complement() {
+        let inverted = fan(&[("invert_duty", "true")]);
+        assert_eq!(inverted.duty_ns(0.3), 70000);
+        assert_eq!(inverted.duty_ns(1.0), 0);
+        assert_eq!(fan(&[]).duty_ns(0.3), 30000);
+    }
+    #[test]
+    fn invert_duty_stops_at_the_full_period() {
+        let replay = Replay::default();
+        let (sensor, pwm) = replay.devices();
+        let args = Args { invert_duty: true, ..Args::default() };
+        let mut inverted = Fan::new("", &args, sensor, pwm).unwrap();
+        replay.set_temperature(70.0);
+        inverted.initial().unwrap();
+        assert!(inverted.on);
+        // a stop on inverted wiring keeps the pwm enabled at the full period instead of disabling it into full airflow
+        replay.take_writes();
+        replay.set_temperature(20.0);
+        for _ in 0..20 {
+            inverted.run(Duration::from_secs(5)).unwrap();
+        }
+        assert!(!inverted.on);
+        let writes = replay.take_writes();
+        assert!(writes.contains(&String::from("duty_cycle=99999")), "{:?}", writes);
+        assert!(!writes.contains(&String::from("enable=0")), "{:?}", writes);
+        replay.set_temperature(70.0);
+        inverted.run(Duration::from_secs(5)).unwrap();
+        assert!(inverted.on);
+        replay.take_writes();
+        inverted.terminate().unwrap();
+        assert!(!inverted.on);
+        let writes = replay.take_writes();
+        assert!(writes.contains(&String::from("duty_cycle=99999")), "{:?}", writes);
+        assert!(!writes.contains(&String::from("enable=0")), "{:?}", writes);
+    }
+    #[test]
+    #[cfg(feature = "dbus")]
+    fn dbus_boost_is_capped_by_max_speed_time_cycle() {
+        let replay = Replay::default();
+        let (sensor, pwm) = replay.devices();
+        // the default 5s interval and max_speed_time_cycle = 32 allow at most 160s
+        let app = Application::with_devices(Args::default(), sensor, pwm).unwrap();
+        assert_eq!(app.boost_time(0), None);
+        assert_eq!(app.boost_time(12), Some(Duration::from_secs(60)));
+        assert_eq!(app.boost_time(u32::MAX), Some(Duration::from_secs(160)));
+    }
+    #[test]
+    fn boost_kicks_a_stopped_fan_and_hands_back_to_the_curve() {
+        let replay = Replay::default();
+        let (sensor, pwm) = replay.devices();
+        let args = Args { spinup_duty: 1.0, spinup_cycles: 1, max_speed_time_cycle: 2, ..Args::default() };
+        let mut boosted = Fan::new("", &args, sensor, pwm).unwrap();
+        let dt = Duration::from_secs(5);
+        replay.set_temperature(55.0);
+        assert!(!boosted.on);
+        // a stopped fan gets the same kick as a normal start before the maximum
+        boosted.run_max_speed(None).unwrap();
+        assert_eq!(boosted.duty_cycle, 1.0);
+        let mut duty_cycles = Vec::new();
+        for _ in 0..5 {
+            boosted.run(dt).unwrap();
+            duty_cycles.push(boosted.duty_cycle);
+        }
+        // on expiry the curve duty at 55°C is forced and kept, without a jump back to the maximum or a stop
+        assert_eq!(duty_cycles, [0.9, 0.9, 0.7, 0.7, 0.7]);
+        assert!(boosted.on);
+        assert!(!boosted.max_speed_active);
+    }
+    #[test]
+    fn pwm_frequency_boundaries() {
+        // the period written by `initial`, in ns
+        let written = |pwm_frequency: u32| -> io::Result<Vec<String>> {
+            let replay = Replay::default();
+            let (sensor, pwm) = replay.devices();
+            let mut fan = Fan::new("", &Args { pwm_frequency, ..Args::default() }, sensor, pwm)?;
+            fan.initial()?;
+            Ok(replay.take_writes().into_iter().filter(|write| write.starts_with("period=")).collect())
+        };
+        for invalid in [0, 1_000_000_001] {
+            let e = written(invalid).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+            assert!(e.to_string().contains("pwm_frequency"), "{}", e);
+        }
+        assert_eq!(written(1).unwrap(), ["period=1000000000"]);
+        assert_eq!(written(10000).unwrap(), ["period=100000"]);
+        assert_eq!(written(25000).unwrap(), ["period=40000"]);
+        // a period below MIN_PERIOD is only warned about
+        assert_eq!(written(50000).unwrap(), [format!("period={}", Fan::MIN_PERIOD)]);
+        assert_eq!(written(100000).unwrap(), ["period=10000"]);
+        assert_eq!(written(1_000_000_000).unwrap(), ["period=1"]);
+    }
+    #[test]
+    fn slew_clamps_both_ways_but_not_critical() {
+        let mut slewed = fan(&[("max_duty_slew", "0.1")]);
+        slewed.on = true;
+        slewed.duty_cycle = 0.5;
+        let approx = |actions: Vec<Action>| -> Vec<f32> {
+            actions.into_iter().map(|action| match action {
+                Action::Set(duty_cycle) => (duty_cycle * 100.0).round() / 100.0,
+                action => panic!("expected a duty cycle, got {:?}", action),
+            }).collect()
+        };
+        // rising: the target is reached over several cycles, also while the control keeps
+        let rising = vec![slewed.slew(Action::Set(0.85)), slewed.slew(Action::Keep), slewed.slew(Action::Keep), slewed.slew(Action::Keep)];
+        assert_eq!(approx(rising), [0.6, 0.7, 0.8, 0.85]);
+        assert_eq!(slewed.slew(Action::Keep), Action::Keep);
+        // falling
+        let falling = vec![slewed.slew(Action::Set(0.6)), slewed.slew(Action::Keep), slewed.slew(Action::Keep)];
+        assert_eq!(approx(falling), [0.75, 0.65, 0.6]);
+        // critical jumps straight to its duty cycle
+        let critical = Action::Critical { duty_cycle: 0.9, poweroff: false };
+        assert_eq!(slewed.slew(critical), critical);
+        assert_eq!(slewed.slew(Action::Keep), Action::Keep);
+    }
+    #[test]
+    fn fahrenheit_temperatures_are_checked_after_conversion() {
+        let fahrenheit = fan(&[("temperature_unit", "fahrenheit"), ("stop_temperature", "86"), ("start_temperature", "104"), ("high_temperature", "158")]);
+        assert_eq!((fahrenheit.control.stop_temperature(), fahrenheit.control.start_temperature(), fahrenheit.control.high_temperature()), (30.0, 40.0, 70.0));
+        // 80 is above the default stop_temperature of 30, but 80°F is below 30°C
+        let mut args = Args::default();
+        let overrides = [("temperature_unit", "fahrenheit"), ("start_temperature", "80")];
+        args.apply_overrides(&overrides.map(|(k, v)| (k.to_string(), v.to_string()))).unwrap();
+        assert!(args.start_temperature < args.stop_temperature);
+        let (sensor, pwm) = Replay::default().devices();
+        let e = Fan::new("", &args, sensor, pwm).err().unwrap();
+        assert!(e.to_string().contains("start_temperature"), "{}", e);
+    }
+    /// reads the shared temperature, failing while it is `None`
+    #[derive(Debug)]
+    struct FlakySensor {
+        temperature: std::sync::Arc<std::sync::Mutex<Option<f32>>>,
+    }
+
+    impl SensorSource for FlakySensor {
+
+        fn get(&mut self) -> io::Result<f32> {
+            self.temperature.lock().unwrap().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "sensor gone"))
+        }
+    }
+
+    #[test]
+    fn failing_sensor_falls_back_and_recovers() {
+        let temperature = std::sync::Arc::new(std::sync::Mutex::new(Some(45.0)));
+        let (_sensor, pwm) = Replay::default().devices();
+        let args = Args { sensor_fail_cycles: 3, sensor_fail_duty: Some(0.8), ..Args::default() };
+        let mut flaky = Fan::new("", &args, Box::new(FlakySensor { temperature: temperature.clone() }), pwm).unwrap();
+        let dt = Duration::from_secs(5);
+        flaky.run(dt).unwrap();
+        assert_eq!(flaky.duty_cycle, 0.56666666);
+        *temperature.lock().unwrap() = None;
+        let mut duty_cycles = Vec::new();
+        for _ in 0..5 {
+            assert_eq!(flaky.run(dt).unwrap_err().kind(), io::ErrorKind::NotFound);
+            duty_cycles.push(flaky.duty_cycle);
+        }
+        // the fallback from the third failure on
+        assert_eq!(duty_cycles, [0.56666666, 0.56666666, 0.8, 0.8, 0.8]);
+        // back under control: the fallback duty is kept through the lag, then follows the curve down
+        *temperature.lock().unwrap() = Some(45.0);
+        flaky.run(dt).unwrap();
+        assert_eq!(flaky.sensor_fail_cycle, 0);
+        let after: Vec<f32> = (0..10).map(|_| { flaky.run(dt).unwrap(); flaky.duty_cycle }).collect();
+        assert!(after[..7].iter().all(|duty_cycle| *duty_cycle == 0.8), "{:?}", after);
+        assert_eq!(after[9], 0.56666666);
+        assert!(flaky.on);
+    }
+    /// a chip that drops the first `ignored` enables, recording every write
+    #[derive(Debug)]
+    struct LazyEnable {
+        ignored: usize,
+        enable: bool,
+        period: u32,
+        writes: std::sync::Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    impl FanOutput for LazyEnable {
+
+        fn set_period(&mut self, period: u32) -> io::Result<()> {
+            self.writes.lock().unwrap().push(format!("period={}", period));
+            self.period = period;
+            Ok(())
+        }
+
+        fn set_duty_cycle(&mut self, duty_cycle: u32) -> io::Result<u32> {
+            self.writes.lock().unwrap().push(format!("duty_cycle={}", duty_cycle));
+            Ok(duty_cycle)
+        }
+
+        fn set_polarity(&mut self, _polarity: Polarity) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn set_enable(&mut self, enable: bool) -> io::Result<()> {
+            self.writes.lock().unwrap().push(format!("enable={}", enable as u8));
+            if enable && self.ignored > 0 {
+                self.ignored -= 1;
+            } else {
+                self.enable = enable;
+            }
+            Ok(())
+        }
+
+        fn get_period(&mut self) -> io::Result<u32> {
+            Ok(self.period)
+        }
+
+        fn get_polarity(&mut self) -> io::Result<Polarity> {
+            Ok(Polarity::Normal)
+        }
+
+        fn get_enable(&mut self) -> io::Result<bool> {
+            Ok(self.enable)
+        }
+    }
+
+    #[test]
+    fn lost_first_enable_is_written_again() {
+        let args = Args::default();
+        let lazy = |ignored: usize| {
+            let writes = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+            let (sensor, _) = Replay::default().devices();
+            let pwm = LazyEnable { ignored, enable: false, period: 0, writes: writes.clone() };
+            (Fan::new("", &args, sensor, Box::new(pwm)).unwrap(), writes)
+        };
+        let (mut started, writes) = lazy(1);
+        started.start_pwm(0.6).unwrap();
+        assert!(started.on);
+        assert_eq!(*writes.lock().unwrap(), [
+            "period=100000", "duty_cycle=60000", "enable=1",
+            // the retry
+            "period=100000", "duty_cycle=60000", "enable=1",
+        ]);
+        // a chip that never enables is an error after the one retry
+        let (mut stuck, writes) = lazy(usize::MAX);
+        assert_eq!(stuck.start_pwm(0.6).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(writes.lock().unwrap().iter().filter(|write| *write == "enable=1").count(), 2);
+    }
+}
@@ -0,0 +1,129 @@
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::signal::unix::SignalKind;
+use tokio::sync::mpsc;
+use tokio::sync::Notify;
+use tokio::time::Interval;
+use tokio::time::MissedTickBehavior;
+use tokio_util::sync::CancellationToken;
+
+use crate::app::Application;
+use crate::app::SignalCommand;
+
+
+/// drives an initialized `app` like the blocking main loop, for embedding in a tokio process: a cycle on every
+/// tick of `next_interval`, the signals of the `signal_*` keys through `tokio::signal`, and the sensor and pwm
+/// accesses on the blocking pool since they are plain sysfs files, and with the `dbus` feature the queued method
+/// calls; returns after `terminate` once `shutdown` is cancelled or a terminating signal arrives
+pub async fn run_loop(app: Application, shutdown: CancellationToken) -> io::Result<()> {
+    let woken = Arc::new(Notify::new());
+    #[cfg(feature = "dbus")]
+    {
+        let woken = woken.clone();
+        app.set_dbus_waker(move || woken.notify_one());
+    }
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    for &(signal, command) in app.signal_table() {
+        let mut stream = tokio::signal::unix::signal(SignalKind::from_raw(signal.0))
+            .map_err(|e| io::Error::new(e.kind(), format!("failed to listen for {}: {}", signal, e)))?;
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            while stream.recv().await.is_some() {
+                if sender.send((signal, command)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    let (mut app, period) = blocking(app, |app| app.next_interval()).await?;
+    let mut ticker = ticker(period);
+    let mut app = loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => {
+                log::debug!("shutdown requested to terminate");
+                break app;
+            }
+            Some((signal, command)) = receiver.recv() => {
+                if command == SignalCommand::Terminate {
+                    log::debug!("receive {} to terminate", signal);
+                    break app;
+                }
+                (app, _) = blocking(app, move |app| app.on_signal(signal, command)).await?;
+            }
+            _ = woken.notified() => {
+                #[cfg(feature = "dbus")]
+                {
+                    (app, _) = blocking(app, |app| app.poll_dbus()).await?;
+                }
+            }
+            _ = ticker.tick() => {
+                let next;
+                (app, next) = blocking(app, |app| {
+                    if let Err(e) = app.run() {
+                        log::error!("failed to run loop: {:?}", e);
+                    }
+                    app.next_interval()
+                }).await?;
+                // `idle_interval` changes the period
+                if next != ticker.period() {
+                    ticker = self::ticker(next);
+                }
+            }
+        }
+    };
+    tokio::task::spawn_blocking(move || app.terminate())
+        .await
+        .map_err(io::Error::other)?
+}
+
+/// runs `f` on the blocking pool and hands `app` back with the result
+async fn blocking<T: Send + 'static>(mut app: Application, f: impl FnOnce(&mut Application) -> T + Send + 'static) -> io::Result<(Application, T)> {
+    tokio::task::spawn_blocking(move || {
+        let result = f(&mut app);
+        (app, result)
+    })
+    .await
+    .map_err(io::Error::other)
+}
+
+/// first tick after one `period`, like the blocking loop's first wait; late ticks shift the schedule
+fn ticker(period: Duration) -> Interval {
+    let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    ticker
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::app::Args;
+    use crate::event::FanEvent;
+    use crate::replay::Replay;
+
+    #[test]
+    fn run_loop_cycles_until_cancelled() {
+        let replay = Replay::default();
+        let (sensor, pwm) = replay.devices();
+        let mut args = Args::default();
+        args.apply_overrides(&[(String::from("interval"), String::from("10ms"))]).unwrap();
+        let mut app = Application::with_devices(args, sensor, pwm).unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        app.set_events(sender);
+        replay.set_temperature(45.0);
+        app.initial().unwrap();
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        runtime.block_on(async {
+            let shutdown = CancellationToken::new();
+            let task = tokio::spawn(run_loop(app, shutdown.clone()));
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            shutdown.cancel();
+            task.await.unwrap().unwrap();
+        });
+        let events: Vec<FanEvent> = receiver.try_iter().collect();
+        assert!(events.iter().any(|event| matches!(event, FanEvent::DutyChanged { .. })));
+    }
+}
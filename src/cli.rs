@@ -0,0 +1,121 @@
+use std::fmt;
+use std::path::PathBuf;
+
+
+#[derive(Debug)]
+pub struct UsageError {
+    message: String,
+}
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for UsageError {
+
+}
+
+
+#[derive(Debug, Clone)]
+pub struct CommandLine {
+    pub config: PathBuf,
+    pub observe: bool,
+    /// trace to replay against mock devices instead of running the loop
+    pub replay: Option<PathBuf>,
+    /// validate the configuration and device paths, then exit
+    pub check: bool,
+    /// print the curve as CSV, then exit
+    pub plot: bool,
+    /// temperature profile to run through the control instead of running the loop
+    pub simulate: Option<PathBuf>,
+    /// run a single cycle resumed from `state_file`, then exit
+    pub once: bool,
+    /// `(key, value)` pairs in config-key form, applied after the config file
+    pub overrides: Vec<(String, String)>,
+}
+
+
+#[derive(Debug)]
+pub enum Command {
+    Run(CommandLine),
+    Help,
+    Version,
+}
+
+
+/// `keys` are the config keys accepted as `--key value`, with `_` written as `-`
+pub fn parse(mut args: impl Iterator<Item = String>, keys: &[&str]) -> Result<Command, UsageError> {
+    let mut cmd = CommandLine {
+        config: PathBuf::from("fanctrl.conf"),
+        observe: false,
+        replay: None,
+        check: false,
+        plot: false,
+        simulate: None,
+        once: false,
+        overrides: Vec::new(),
+    };
+    let mut positional = false;
+    while let Some(arg) = args.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if arg.starts_with("--") => (flag, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        match flag {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-v" | "--version" => return Ok(Command::Version),
+            "--observe" => {
+                cmd.observe = true;
+            }
+            "--dry-run" if inline.is_none() => {
+                cmd.overrides.push((String::from("dry_run"), String::from("true")));
+            }
+            "--check" => {
+                cmd.check = true;
+            }
+            "--plot" => {
+                cmd.plot = true;
+            }
+            "--once" => {
+                cmd.once = true;
+            }
+            "--simulate" => {
+                cmd.simulate = Some(PathBuf::from(value(flag, inline, &mut args)?));
+            }
+            "--replay" => {
+                cmd.replay = Some(PathBuf::from(value(flag, inline, &mut args)?));
+            }
+            "-c" | "--config" => {
+                cmd.config = PathBuf::from(value(flag, inline, &mut args)?);
+            }
+            _ if flag.starts_with("--") => {
+                let key = flag[2..].replace('-', "_");
+                if !keys.contains(&key.as_str()) {
+                    return Err(UsageError { message: format!("unknown option: {}", flag) });
+                }
+                let value = value(flag, inline, &mut args)?;
+                cmd.overrides.push((key, value));
+            }
+            _ if flag.starts_with('-') && flag.len() > 1 => {
+                return Err(UsageError { message: format!("unknown option: {}", flag) });
+            }
+            _ => {
+                if positional {
+                    return Err(UsageError { message: format!("unexpected argument: {}", flag) });
+                }
+                positional = true;
+                cmd.config = PathBuf::from(flag);
+            }
+        }
+    }
+    Ok(Command::Run(cmd))
+}
+
+fn value(flag: &str, inline: Option<String>, args: &mut impl Iterator<Item = String>) -> Result<String, UsageError> {
+    match inline {
+        Some(value) => Ok(value),
+        None => args.next().ok_or_else(|| UsageError { message: format!("missing value for option: {}", flag) }),
+    }
+}
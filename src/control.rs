@@ -1,4 +1,8 @@
 use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::ini::FieldParseError;
 
 #[derive(Debug)]
 pub struct ParameterError<T> {
@@ -17,10 +21,16 @@ impl<T: fmt::Debug> std::error::Error for ParameterError<T> {
     
 }
 
+impl<T: fmt::Debug> From<ParameterError<T>> for FieldParseError {
+    fn from(e: ParameterError<T>) -> Self {
+        FieldParseError::out_of_range(e.field, format!("`{:?}` {}", e.value, e.reason))
+    }
+}
 
 
 
-#[derive(Debug)]
+
+#[derive(Debug, PartialEq)]
 pub enum ControlOutput {
     Off,
     Change(f32),
@@ -28,7 +38,24 @@ pub enum ControlOutput {
 }
 
 
-#[derive(Debug)]
+/// temperature to duty cycle mapping, with the knees `Control` needs for on/off decisions
+pub trait Rule: fmt::Debug + fmt::Display + Send {
+
+    fn map(&self, t: f32) -> f32;
+
+    fn stop_temperature(&self) -> f32;
+
+    fn start_temperature(&self) -> f32;
+
+    fn high_temperature(&self) -> f32;
+
+    fn min_duty_cycle(&self) -> f32;
+
+    fn max_duty_cycle(&self) -> f32;
+}
+
+
+#[derive(Debug, Clone)]
 pub struct Function {
     stop_temperature: f32, // T0
     start_temperature: f32, // T1
@@ -47,6 +74,8 @@ impl std::fmt::Display for Function {
 
 impl Function {
 
+    const OVER_ONE: &'static str = "greater than 1; write a fraction like 0.5 or a percentage like 50%";
+
     pub fn new(stop_temperature: f32, start_temperature: f32, high_temperature: f32, min_duty_cycle: f32, max_duty_cycle: f32) -> Result<Self, ParameterError<f32>> {
         if stop_temperature >= start_temperature {
             return Err(ParameterError { field: "start_temperature", reason: "lower than stop_temperature", value: start_temperature });
@@ -54,11 +83,18 @@ impl Function {
         if start_temperature >= high_temperature {
             return Err(ParameterError { field: "high_temperature", reason: "lower than start_temperature", value: high_temperature });
         }
+        if min_duty_cycle > 1.0 {
+            return Err(ParameterError { field: "min_duty_cycle", reason: Self::OVER_ONE, value: min_duty_cycle });
+        }
+        if max_duty_cycle > 1.0 {
+            return Err(ParameterError { field: "max_duty_cycle", reason: Self::OVER_ONE, value: max_duty_cycle });
+        }
         if min_duty_cycle <= 0.0 || min_duty_cycle >= 1.0 {
             return Err(ParameterError { field: "min_duty_cycle", reason: "not in (0, 1)", value: min_duty_cycle });
         }
-        if max_duty_cycle <= 0.0 || max_duty_cycle >= 1.0 {
-            return Err(ParameterError { field: "max_duty_cycle", reason: "not in (0, 1)", value: max_duty_cycle });
+        // 1.0 is written as period - 1, see `clamp_duty_cycle`
+        if max_duty_cycle <= 0.0 {
+            return Err(ParameterError { field: "max_duty_cycle", reason: "not in (0, 1]", value: max_duty_cycle });
         }
         if min_duty_cycle >= max_duty_cycle {
             return Err(ParameterError { field: "max_duty_cycle", reason: "lower than min_duty_cycle", value: max_duty_cycle });
@@ -74,128 +110,1018 @@ impl Function {
         )
     }
 
-    pub fn map(&self, t: f32) -> f32 {
+    pub fn stop_temperature(&self) -> f32 {
+        self.stop_temperature
+    }
+
+    pub fn start_temperature(&self) -> f32 {
+        self.start_temperature
+    }
+
+    pub fn high_temperature(&self) -> f32 {
+        self.high_temperature
+    }
+
+    pub fn min_duty_cycle(&self) -> f32 {
+        self.min_duty_cycle
+    }
+
+    pub fn max_duty_cycle(&self) -> f32 {
+        self.max_duty_cycle
+    }
+
+    /// the temperature `map` reaches `duty_cycle` at on the linear segment; `start_temperature` at or below
+    /// `min_duty_cycle` and `high_temperature` at or above `max_duty_cycle`
+    pub fn inverse(&self, duty_cycle: f32) -> f32 {
+        if duty_cycle <= self.min_duty_cycle {
+            return self.start_temperature
+        }
+        if duty_cycle >= self.max_duty_cycle {
+            return self.high_temperature
+        }
+        self.start_temperature + (self.high_temperature - self.start_temperature) * (duty_cycle - self.min_duty_cycle) / (self.max_duty_cycle - self.min_duty_cycle)
+    }
+}
+
+impl Rule for Function {
+
+    fn map(&self, t: f32) -> f32 {
         if t < self.start_temperature {
             return self.min_duty_cycle
         }
         if t > self.high_temperature {
             return self.max_duty_cycle
         } 
-        return self.min_duty_cycle + (self.max_duty_cycle - self.min_duty_cycle) * (t - self.start_temperature) / (self.high_temperature - self.start_temperature)
+        self.min_duty_cycle + (self.max_duty_cycle - self.min_duty_cycle) * (t - self.start_temperature) / (self.high_temperature - self.start_temperature)
+    }
+
+    fn stop_temperature(&self) -> f32 {
+        Function::stop_temperature(self)
+    }
+
+    fn start_temperature(&self) -> f32 {
+        Function::start_temperature(self)
+    }
+
+    fn high_temperature(&self) -> f32 {
+        Function::high_temperature(self)
+    }
+
+    fn min_duty_cycle(&self) -> f32 {
+        Function::min_duty_cycle(self)
+    }
+
+    fn max_duty_cycle(&self) -> f32 {
+        Function::max_duty_cycle(self)
     }
 }
 
+
+/// sorted `(temperature, duty_cycle)` points, parsed from `40:0.3, 55:0.5, 70:0.9` or `40:30%, 55:50%, 70:90%`
+#[derive(Debug, Clone)]
+pub struct CurvePoints(Vec<(f32, f32)>);
+
+impl FromStr for CurvePoints {
+    type Err = ParameterError<String>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut points: Vec<(f32, f32)> = Vec::new();
+        for item in s.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+            let (t, p) = item
+                .split_once(':')
+                .ok_or_else(|| ParameterError { field: "curve", reason: "expected `temperature:duty_cycle`", value: String::from(item) })?;
+            let t: f32 = t.trim().parse().map_err(|_e| ParameterError { field: "curve", reason: "invalid temperature", value: String::from(item) })?;
+            let p = p.trim();
+            let p: f32 = match p.strip_suffix('%') {
+                Some(percent) => percent.trim_end().parse::<f32>().map(|p| p / 100.0),
+                None => p.parse(),
+            }
+            .map_err(|_e| ParameterError { field: "curve", reason: "invalid duty cycle", value: String::from(item) })?;
+            if p <= 0.0 || p > 1.0 {
+                return Err(ParameterError { field: "curve", reason: "duty cycle not in (0, 1]", value: String::from(item) });
+            }
+            if let Some(&(last_t, _)) = points.last() {
+                if t <= last_t {
+                    return Err(ParameterError { field: "curve", reason: "temperatures not strictly increasing", value: String::from(item) });
+                }
+            }
+            points.push((t, p));
+        }
+        if points.is_empty() {
+            return Err(ParameterError { field: "curve", reason: "no points", value: String::from(s) });
+        }
+        Ok(CurvePoints(points))
+    }
+}
+
+impl CurvePoints {
+
+    /// the same points with every temperature passed through `f`, which must be increasing, like a unit conversion
+    pub fn map_temperatures(&self, f: impl Fn(f32) -> f32) -> Self {
+        CurvePoints(self.0.iter().map(|&(t, p)| (f(t), p)).collect())
+    }
+}
+
+
+/// piecewise-linear curve through `points`, clamped outside the range
+#[derive(Debug, Clone)]
+pub struct Curve {
+    stop_temperature: f32,
+    points: Vec<(f32, f32)>,
+}
+
+impl std::fmt::Display for Curve {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Curve[T0={:.2}°C", self.stop_temperature)?;
+        for (t, p) in self.points.iter() {
+            write!(f, ", {:.2}°C:{:.2}%", t, p * 100.0)?;
+        }
+        write!(f, "]")
+    }
+}
+
+impl Curve {
+
+    pub fn new(stop_temperature: f32, points: CurvePoints) -> Result<Self, ParameterError<f32>> {
+        let points = points.0;
+        if stop_temperature >= points[0].0 {
+            return Err(ParameterError { field: "stop_temperature", reason: "not lower than the first curve point", value: stop_temperature });
+        }
+        Ok(
+            Self {
+                stop_temperature,
+                points,
+            }
+        )
+    }
+}
+
+impl Rule for Curve {
+
+    fn map(&self, t: f32) -> f32 {
+        let (first_t, first_p) = self.points[0];
+        if t <= first_t {
+            return first_p;
+        }
+        for window in self.points.windows(2) {
+            let (t0, p0) = window[0];
+            let (t1, p1) = window[1];
+            if t <= t1 {
+                return p0 + (p1 - p0) * (t - t0) / (t1 - t0);
+            }
+        }
+        self.points[self.points.len() - 1].1
+    }
+
+    fn stop_temperature(&self) -> f32 {
+        self.stop_temperature
+    }
+
+    fn start_temperature(&self) -> f32 {
+        self.points[0].0
+    }
+
+    fn high_temperature(&self) -> f32 {
+        self.points[self.points.len() - 1].0
+    }
+
+    fn min_duty_cycle(&self) -> f32 {
+        self.points.iter().map(|p| p.1).fold(f32::INFINITY, f32::min)
+    }
+
+    fn max_duty_cycle(&self) -> f32 {
+        self.points.iter().map(|p| p.1).fold(0.0, f32::max)
+    }
+}
+
+
+/// forbidden duty cycle range, parsed from `0.45-0.55`
+#[derive(Debug, Clone, Copy)]
+pub struct DutyBand {
+    low: f32,
+    high: f32,
+}
+
+impl FromStr for DutyBand {
+    type Err = ParameterError<String>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (low, high) = s
+            .split_once('-')
+            .ok_or_else(|| ParameterError { field: "band", reason: "expected `low-high`", value: String::from(s) })?;
+        let low: f32 = low.trim().parse().map_err(|_e| ParameterError { field: "band", reason: "invalid lower duty cycle", value: String::from(s) })?;
+        let high: f32 = high.trim().parse().map_err(|_e| ParameterError { field: "band", reason: "invalid upper duty cycle", value: String::from(s) })?;
+        if !(0.0 <= low && low < high && high <= 1.0) {
+            return Err(ParameterError { field: "band", reason: "expected 0 <= low < high <= 1", value: String::from(s) });
+        }
+        Ok(DutyBand { low, high })
+    }
+}
+
+impl fmt::Display for DutyBand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.2}-{:.2}", self.low, self.high)
+    }
+}
+
+impl DutyBand {
+
+    /// snaps a duty cycle strictly inside the band to the nearest edge, the upper one on a tie
+    pub fn avoid(&self, duty_cycle: f32) -> f32 {
+        if duty_cycle <= self.low || duty_cycle >= self.high {
+            duty_cycle
+        } else if duty_cycle - self.low < self.high - duty_cycle {
+            self.low
+        } else {
+            self.high
+        }
+    }
+}
+
+/// closed-loop alternative to the hysteresis state machine
+pub trait Regulator: fmt::Debug + Send {
+
+    /// returns the duty cycle for `temperature`, clamped to `[min_duty_cycle, max_duty_cycle]`;
+    /// `dt` is the time since the previous call in seconds
+    fn regulate(&mut self, temperature: f32, dt: f32, min_duty_cycle: f32, max_duty_cycle: f32) -> f32;
+
+    /// forgets the accumulated history, called when the fan starts
+    fn reset(&mut self);
+}
+
+
+/// `duty = min_duty_cycle + kp * e + ki * ∫e dt + kd * de/dt`, with `e = temperature - setpoint`
+#[derive(Debug)]
+pub struct Pid {
+    setpoint: f32,
+    kp: f32,
+    ki: f32,
+    kd: f32,
+    dt: f32, // seconds, used when the measured one is not positive
+    integral: f32,
+    last_error: Option<f32>,
+}
+
+impl Pid {
+
+    pub fn new(setpoint: f32, kp: f32, ki: f32, kd: f32, dt: f32) -> Result<Self, ParameterError<f32>> {
+        if !setpoint.is_finite() {
+            return Err(ParameterError { field: "setpoint", reason: "not finite", value: setpoint });
+        }
+        if !(kp >= 0.0 && kp.is_finite()) {
+            return Err(ParameterError { field: "kp", reason: "not a non-negative number", value: kp });
+        }
+        if !(ki >= 0.0 && ki.is_finite()) {
+            return Err(ParameterError { field: "ki", reason: "not a non-negative number", value: ki });
+        }
+        if !(kd >= 0.0 && kd.is_finite()) {
+            return Err(ParameterError { field: "kd", reason: "not a non-negative number", value: kd });
+        }
+        if dt <= 0.0 {
+            return Err(ParameterError { field: "interval", reason: "not positive", value: dt });
+        }
+        Ok(
+            Self {
+                setpoint,
+                kp,
+                ki,
+                kd,
+                dt,
+                integral: 0.0,
+                last_error: None,
+            }
+        )
+    }
+}
+
+impl Regulator for Pid {
+
+    fn regulate(&mut self, temperature: f32, dt: f32, min_duty_cycle: f32, max_duty_cycle: f32) -> f32 {
+        let dt = if dt > 0.0 { dt } else { self.dt };
+        let error = temperature - self.setpoint;
+        let derivative = match self.last_error {
+            Some(last_error) => (error - last_error) / dt,
+            None => 0.0,
+        };
+        self.last_error = Some(error);
+        let integral = self.integral + error * dt;
+        let output = min_duty_cycle + self.kp * error + self.ki * integral + self.kd * derivative;
+        // conditional integration: stop winding up while saturated in the direction of the error
+        if (output < max_duty_cycle || error < 0.0) && (output > min_duty_cycle || error > 0.0) {
+            self.integral = integral;
+        }
+        output.clamp(min_duty_cycle, max_duty_cycle)
+    }
+
+    fn reset(&mut self) {
+        self.integral = 0.0;
+        self.last_error = None;
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlKind {
+    /// the lag/keep state machine over the curve
+    Hysteresis,
+    /// `duty = curve(temperature)` every cycle, see `Control::set_kind`
+    Stateless,
+    /// closed loop to a setpoint through the regulator passed to `Control::new`
+    Pid,
+}
+
+impl FromStr for ControlKind {
+    type Err = ParameterError<String>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hysteresis" => Ok(ControlKind::Hysteresis),
+            "stateless" => Ok(ControlKind::Stateless),
+            "pid" => Ok(ControlKind::Pid),
+            _ => Err(ParameterError { field: "control", reason: "expected `hysteresis`, `stateless` or `pid`", value: String::from(s) }),
+        }
+    }
+}
+
+impl fmt::Display for ControlKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ControlKind::Hysteresis => write!(f, "hysteresis"),
+            ControlKind::Stateless => write!(f, "stateless"),
+            ControlKind::Pid => write!(f, "pid"),
+        }
+    }
+}
+
+
 #[derive(Debug)]
 pub enum State {
     Off,
-    Function { last_duty_cycle: f32 },
-    Keep { remain_time_cycle: usize, keep_temperature: f32, keep_duty_cycle: f32 },
+    Function { last_duty_cycle: f32, on_time_cycle: usize },
+    /// `remain_time` counts down by the measured time between updates
+    Keep { remain_time: Duration, keep_temperature: f32, keep_duty_cycle: f32, on_time_cycle: usize },
+    /// stepping down to the minimum duty cycle before `Off`; `step` is subtracted each cycle
+    RampDown { duty_cycle: f32, step: f32, remain_time_cycle: usize, on_time_cycle: usize },
+}
+
+impl State {
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            State::Off => "off",
+            State::Function { .. } => "function",
+            State::Keep { .. } => "keep",
+            State::RampDown { .. } => "rampdown",
+        }
+    }
+
+    /// duty cycle the state holds the fan at; `None` when off
+    pub fn duty_cycle(&self) -> Option<f32> {
+        match self {
+            State::Off => None,
+            State::Function { last_duty_cycle, .. } => Some(*last_duty_cycle),
+            State::Keep { keep_duty_cycle, .. } => Some(*keep_duty_cycle),
+            State::RampDown { duty_cycle, .. } => Some(*duty_cycle),
+        }
+    }
 }
 
 
 #[derive(Debug)]
 pub struct Control {
     state: State,
-    last_temperature: f32,
-    temperature_rule: Function,
-    lag_time_cycle: usize,
+    /// `None` until the first `update`, `update_force` or `load`
+    last_temperature: Option<f32>,
+    temperature_rule: Box<dyn Rule>,
+    lag_time: Duration,
+    min_on_cycles: usize,
+    stop_hysteresis: f32,
+    rampdown_cycles: usize,
+    keep_decay: f32,
+    duty_deadband: f32,
+    min_start_duty: f32,
+    min_run_duty: f32,
+    kind: ControlKind,
+    regulator: Option<Box<dyn Regulator>>,
 }
 
 impl Control {
 
-    pub fn new(temperature_rule: Function, lag_time_cycle: usize) -> Self {
-        Self {
-            state: State::Off,
-            last_temperature: -273.15,
-            temperature_rule,
-            lag_time_cycle
+    /// `regulator` drives the duty cycle once `set_kind` selects `ControlKind::Pid`;
+    /// `lag_time`: how long a falling temperature keeps the duty cycle, counted down by the `dt` of each update;
+    /// `min_on_cycles`: once started, `ControlOutput::Off` is not returned before that many cycles;
+    /// `stop_hysteresis`: the fan stops only below `stop_temperature - stop_hysteresis`;
+    /// `rampdown_cycles`: before `ControlOutput::Off`, the duty cycle steps down to the minimum over that many cycles;
+    /// `keep_decay`: weight of the current temperature when the kept temperature follows a falling one, in (0, 1]
+    pub fn new(temperature_rule: Box<dyn Rule>, lag_time: Duration, min_on_cycles: usize, stop_hysteresis: f32, rampdown_cycles: usize, keep_decay: f32, regulator: Option<Box<dyn Regulator>>) -> Result<Self, ParameterError<f32>> {
+        if !(stop_hysteresis >= 0.0 && stop_hysteresis.is_finite()) {
+            return Err(ParameterError { field: "stop_hysteresis", reason: "not a non-negative number", value: stop_hysteresis });
+        }
+        if !(keep_decay > 0.0 && keep_decay <= 1.0) {
+            return Err(ParameterError { field: "keep_decay", reason: "not in (0, 1]", value: keep_decay });
+        }
+        Ok(
+            Self {
+                state: State::Off,
+                last_temperature: None,
+                temperature_rule,
+                lag_time,
+                min_on_cycles,
+                stop_hysteresis,
+                rampdown_cycles,
+                keep_decay,
+                duty_deadband: 0.0,
+                min_start_duty: 0.0,
+                min_run_duty: 0.0,
+                kind: ControlKind::Hysteresis,
+                regulator,
+            }
+        )
+    }
+
+    /// a rising temperature in `State::Function` changes the duty cycle only once the curve moves it by more
+    /// than `duty_deadband`, or to `max_duty_cycle`; 0 changes it on every rise
+    pub fn set_duty_deadband(&mut self, duty_deadband: f32) -> Result<(), ParameterError<f32>> {
+        if !(0.0..1.0).contains(&duty_deadband) {
+            return Err(ParameterError { field: "duty_deadband", reason: "not in [0, 1)", value: duty_deadband });
+        }
+        self.duty_deadband = duty_deadband;
+        Ok(())
+    }
+
+    /// floors of the duty cycle besides the curve: `min_start_duty` when the fan starts from `State::Off`,
+    /// `min_run_duty` while it runs; a fan that starts at 40% but keeps spinning down to 20% runs a curve
+    /// from 20% with a `min_start_duty` of 40%. 0 leaves the curve as is
+    pub fn set_min_duty(&mut self, min_start_duty: f32, min_run_duty: f32) -> Result<(), ParameterError<f32>> {
+        if !(0.0..=1.0).contains(&min_run_duty) {
+            return Err(ParameterError { field: "min_run_duty", reason: "not in [0, 1]", value: min_run_duty });
+        }
+        if min_start_duty < min_run_duty {
+            return Err(ParameterError { field: "min_start_duty", reason: "lower than min_run_duty", value: min_start_duty });
+        }
+        if min_start_duty > self.temperature_rule.max_duty_cycle() {
+            return Err(ParameterError { field: "min_start_duty", reason: "greater than max_duty_cycle", value: min_start_duty });
+        }
+        self.min_start_duty = min_start_duty;
+        self.min_run_duty = min_run_duty;
+        Ok(())
+    }
+
+    /// `ControlKind::Hysteresis` by default. `Stateless` bypasses the lag/keep state machine: every update maps the
+    /// temperature through the curve, and the fan stops at or below `stop_temperature`; `lag_time`, `min_on_cycles`,
+    /// `stop_hysteresis`, `rampdown_cycles`, `keep_decay` and `duty_deadband` have no effect, the `min_start_duty`
+    /// and `min_run_duty` floors still apply. `Pid` without a regulator keeps the duty cycle
+    pub fn set_kind(&mut self, kind: ControlKind) {
+        self.kind = kind;
+    }
+
+    pub fn min_start_duty(&self) -> f32 {
+        self.min_start_duty
+    }
+
+    fn off_temperature(&self) -> f32 {
+        self.temperature_rule.stop_temperature() - self.stop_hysteresis
+    }
+
+    /// `dt` is the time since the previous update
+    pub fn update(&mut self, temperature: f32, dt: Duration) -> ControlOutput {
+        let output = match (&self.state, self.kind) {
+            (State::RampDown { .. }, _) => self.update_rampdown(temperature, dt),
+            (_, ControlKind::Stateless) => self.update_stateless(temperature),
+            (_, ControlKind::Pid) => self.update_regulator(temperature, dt),
+            (_, ControlKind::Hysteresis) => self.update_state(temperature, dt),
+        };
+        self.last_temperature = Some(temperature);
+        output
+    }
+
+    /// `Off`, or the first step of the ramp down from `duty_cycle` when `rampdown_cycles` is set
+    fn stop(&mut self, duty_cycle: f32, on_time_cycle: usize) -> ControlOutput {
+        if self.rampdown_cycles == 0 {
+            self.state = State::Off;
+            return ControlOutput::Off;
+        }
+        let min_duty_cycle = self.temperature_rule.min_duty_cycle().max(self.min_run_duty);
+        let step = (duty_cycle - min_duty_cycle).max(0.0) / self.rampdown_cycles as f32;
+        self.state = State::RampDown { duty_cycle, step, remain_time_cycle: self.rampdown_cycles, on_time_cycle };
+        self.update_rampdown_step()
+    }
+
+    fn update_rampdown(&mut self, temperature: f32, dt: Duration) -> ControlOutput {
+        if temperature > self.temperature_rule.start_temperature() {
+            // heat is back: start over as if the fan had been off
+            self.state = State::Off;
+            return if self.kind == ControlKind::Pid {
+                self.update_regulator(temperature, dt)
+            } else {
+                self.update_state(temperature, dt)
+            };
+        }
+        self.update_rampdown_step()
+    }
+
+    fn update_rampdown_step(&mut self) -> ControlOutput {
+        let min_duty_cycle = self.temperature_rule.min_duty_cycle().max(self.min_run_duty);
+        match &mut self.state {
+            State::RampDown { remain_time_cycle: 0, .. } => {
+                self.state = State::Off;
+                ControlOutput::Off
+            }
+            State::RampDown { duty_cycle, step, remain_time_cycle, on_time_cycle } => {
+                *remain_time_cycle -= 1;
+                *on_time_cycle = on_time_cycle.saturating_add(1);
+                *duty_cycle = if *remain_time_cycle == 0 { min_duty_cycle } else { (*duty_cycle - *step).max(min_duty_cycle) };
+                ControlOutput::Change(*duty_cycle)
+            }
+            _ => ControlOutput::Keep,
+        }
+    }
+
+    fn update_regulator(&mut self, temperature: f32, dt: Duration) -> ControlOutput {
+        let off_temperature = self.off_temperature();
+        let (min_start_duty, min_run_duty) = (self.min_start_duty, self.min_run_duty);
+        let rule = &self.temperature_rule;
+        let regulator = match self.regulator.as_mut() {
+            Some(regulator) => regulator,
+            None => return ControlOutput::Keep,
+        };
+        let on_time_cycle = match self.state {
+            State::Off => {
+                if temperature <= rule.start_temperature() {
+                    return ControlOutput::Off;
+                }
+                regulator.reset();
+                0
+            }
+            State::Function { last_duty_cycle, on_time_cycle } => {
+                if temperature <= off_temperature && on_time_cycle >= self.min_on_cycles {
+                    return self.stop(last_duty_cycle, on_time_cycle);
+                }
+                on_time_cycle.saturating_add(1)
+            }
+            State::Keep { keep_duty_cycle, on_time_cycle, .. } | State::RampDown { duty_cycle: keep_duty_cycle, on_time_cycle, .. } => {
+                if temperature <= off_temperature && on_time_cycle >= self.min_on_cycles {
+                    return self.stop(keep_duty_cycle, on_time_cycle);
+                }
+                on_time_cycle.saturating_add(1)
+            }
+        };
+        let floor = if matches!(self.state, State::Off) { min_start_duty } else { min_run_duty };
+        let duty_cycle = regulator.regulate(temperature, dt.as_secs_f32(), rule.min_duty_cycle(), rule.max_duty_cycle()).max(floor);
+        self.state = State::Function { last_duty_cycle: duty_cycle, on_time_cycle };
+        ControlOutput::Change(duty_cycle)
+    }
+
+    /// `Keep` when the curve gives the duty cycle already set, so a steady temperature writes nothing
+    fn update_stateless(&mut self, temperature: f32) -> ControlOutput {
+        if temperature <= self.temperature_rule.stop_temperature() {
+            self.state = State::Off;
+            return ControlOutput::Off;
+        }
+        let (last_duty_cycle, on_time_cycle) = match self.state {
+            State::Off => (None, 0),
+            State::Function { last_duty_cycle: duty_cycle, on_time_cycle }
+            | State::Keep { keep_duty_cycle: duty_cycle, on_time_cycle, .. }
+            | State::RampDown { duty_cycle, on_time_cycle, .. } => (Some(duty_cycle), on_time_cycle.saturating_add(1)),
+        };
+        let floor = if last_duty_cycle.is_some() { self.min_run_duty } else { self.min_start_duty };
+        let duty_cycle = self.temperature_rule.map(temperature).max(floor);
+        self.state = State::Function { last_duty_cycle: duty_cycle, on_time_cycle };
+        if last_duty_cycle == Some(duty_cycle) {
+            ControlOutput::Keep
+        } else {
+            ControlOutput::Change(duty_cycle)
         }
     }
 
-    pub fn update(&mut self, temperature: f32) -> ControlOutput {
-        let output = match &mut self.state {
+    fn update_state(&mut self, temperature: f32, dt: Duration) -> ControlOutput {
+        let off_temperature = self.off_temperature();
+        // the first reading has nothing to fall from, so it counts as rising
+        let falling_from = self.last_temperature.filter(|last| temperature <= *last);
+        match &mut self.state {
             State::Off => {
-                if temperature <= self.temperature_rule.start_temperature {
+                if temperature <= self.temperature_rule.start_temperature() {
                     ControlOutput::Off
                 } else {
-                    let duty_cycle = self.temperature_rule.map(temperature);
-                    self.state = State::Function { last_duty_cycle: duty_cycle };
+                    let duty_cycle = self.temperature_rule.map(temperature).max(self.min_start_duty);
+                    self.state = State::Function { last_duty_cycle: duty_cycle, on_time_cycle: 0 };
                     ControlOutput::Change(duty_cycle)
                 }
             },
-            State::Function { last_duty_cycle } => {
-                if temperature <= self.last_temperature {
+            State::Function { last_duty_cycle, on_time_cycle } => {
+                let on_time_cycle = on_time_cycle.saturating_add(1);
+                if let Some(last_temperature) = falling_from {
                     self.state = State::Keep {
-                        remain_time_cycle: self.lag_time_cycle,
-                        keep_temperature: self.last_temperature,
+                        remain_time: self.lag_time,
+                        keep_temperature: last_temperature,
                         keep_duty_cycle: *last_duty_cycle,
+                        on_time_cycle,
                     };
                     ControlOutput::Keep
                 } else {
-                    let duty_cycle = self.temperature_rule.map(temperature);
-                    self.state = State::Function { last_duty_cycle: duty_cycle };
+                    let duty_cycle = self.temperature_rule.map(temperature).max(self.min_run_duty);
+                    let max_duty_cycle = self.temperature_rule.max_duty_cycle();
+                    let reached_max = duty_cycle >= max_duty_cycle && *last_duty_cycle < max_duty_cycle;
+                    if self.duty_deadband > 0.0 && (duty_cycle - *last_duty_cycle).abs() <= self.duty_deadband && !reached_max {
+                        self.state = State::Function { last_duty_cycle: *last_duty_cycle, on_time_cycle };
+                        return ControlOutput::Keep;
+                    }
+                    self.state = State::Function { last_duty_cycle: duty_cycle, on_time_cycle };
                     ControlOutput::Change(duty_cycle)
                 }
             },
-            State::Keep { remain_time_cycle, keep_temperature, keep_duty_cycle } => {
-                if temperature <= self.last_temperature {
-                    if *remain_time_cycle > 0 {
-                        *remain_time_cycle -= 1;
+            State::Keep { remain_time, keep_temperature, keep_duty_cycle, on_time_cycle } => {
+                *on_time_cycle = on_time_cycle.saturating_add(1);
+                if falling_from.is_some() {
+                    if !remain_time.is_zero() {
+                        *remain_time = remain_time.saturating_sub(dt);
                         ControlOutput::Keep
                     } else {
-                        if temperature <= self.temperature_rule.stop_temperature {
-                            self.state = State::Off;
-                            ControlOutput::Off
+                        // the fan must stay on for at least `min_on_cycles` to avoid short-cycling
+                        if temperature <= off_temperature && *on_time_cycle >= self.min_on_cycles {
+                            let (duty_cycle, on_time_cycle) = (*keep_duty_cycle, *on_time_cycle);
+                            self.stop(duty_cycle, on_time_cycle)
                         } else {
-                            *keep_temperature = (temperature + *keep_temperature) / 2.0;
-                            *keep_duty_cycle = self.temperature_rule.map(*keep_temperature);
-                            *remain_time_cycle = self.lag_time_cycle;
+                            // exponential moving average: each lag window closes `keep_decay` of the gap to the current temperature
+                            *keep_temperature += self.keep_decay * (temperature - *keep_temperature);
+                            // a spike above the curve would otherwise keep the blend, and the fan, at max for longer than the readings warrant
+                            *keep_temperature = keep_temperature.clamp(self.temperature_rule.stop_temperature(), self.temperature_rule.high_temperature());
+                            *keep_duty_cycle = self.temperature_rule.map(*keep_temperature).max(self.min_run_duty);
+                            *remain_time = self.lag_time;
                             ControlOutput::Change(*keep_duty_cycle)
                         }
                     }
                 } else {
                     if temperature < *keep_temperature {
-                        *remain_time_cycle = self.lag_time_cycle.min(*remain_time_cycle + 1);
+                        *remain_time = self.lag_time.min(remain_time.saturating_add(dt));
                         ControlOutput::Keep
                     } else {
-                        let duty_cycle = self.temperature_rule.map(temperature);
-                        self.state = State::Function { last_duty_cycle: duty_cycle };
+                        let duty_cycle = self.temperature_rule.map(temperature).max(self.min_run_duty);
+                        self.state = State::Function { last_duty_cycle: duty_cycle, on_time_cycle: *on_time_cycle };
                         ControlOutput::Change(duty_cycle)
                     }
                 }
             },
-        };
-        self.last_temperature = temperature;
-        output
+            State::RampDown { .. } => self.update_rampdown_step(),
+        }
+    }
+
+    /// swaps the curve while keeping the state, lag counters and last temperature; returns the previous curve
+    pub fn replace_rule(&mut self, temperature_rule: Box<dyn Rule>) -> Box<dyn Rule> {
+        std::mem::replace(&mut self.temperature_rule, temperature_rule)
     }
 
+    /// forgets the state, so the next `update` decides as if the fan were off
+    pub fn reset(&mut self) {
+        self.state = State::Off;
+    }
+
+    /// enters `State::Keep` at `duty_cycle` as if the lag had just started; always returns `ControlOutput::Change(duty_cycle)`
     pub fn update_force(&mut self, temperature: f32, duty_cycle: f32) -> ControlOutput {
-        self.last_temperature = temperature;
-        self.state = State::Keep { remain_time_cycle: self.lag_time_cycle, keep_temperature: temperature, keep_duty_cycle: duty_cycle };
+        self.last_temperature = Some(temperature);
+        self.state = State::Keep { remain_time: self.lag_time, keep_temperature: temperature, keep_duty_cycle: duty_cycle, on_time_cycle: 0 };
         ControlOutput::Change(duty_cycle)
     }
 
+    /// duty cycle of the current rule at `temperature`, without changing the state
+    pub fn map(&self, temperature: f32) -> f32 {
+        self.temperature_rule.map(temperature)
+    }
+
     pub fn stop_temperature(&self) -> f32 {
-        self.temperature_rule.stop_temperature
+        self.temperature_rule.stop_temperature()
     }
 
     pub fn start_temperature(&self) -> f32 {
-        self.temperature_rule.start_temperature
+        self.temperature_rule.start_temperature()
     }
 
     pub fn high_temperature(&self) -> f32 {
-        self.temperature_rule.high_temperature
+        self.temperature_rule.high_temperature()
     }
 
     pub fn min_duty_cycle(&self) -> f32 {
-        self.temperature_rule.min_duty_cycle
+        self.temperature_rule.min_duty_cycle()
     }
 
     pub fn max_duty_cycle(&self) -> f32 {
-        self.temperature_rule.max_duty_cycle
+        self.temperature_rule.max_duty_cycle()
+    }
+
+    pub fn lag_time(&self) -> Duration {
+        self.lag_time
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    /// `State::name` of the current state
+    pub fn state_name(&self) -> &'static str {
+        self.state.name()
+    }
+
+    /// duty cycle the current state holds the fan at; `None` when off
+    pub fn current_duty(&self) -> Option<f32> {
+        self.state.duty_cycle()
+    }
+
+    /// temperature of the last `update` or `update_force`; `None` before the first one
+    pub fn last_temperature(&self) -> Option<f32> {
+        self.last_temperature
+    }
+
+    /// the state and last temperature as one line, e.g.
+    /// `keep last_temperature=52.5 remain_time_ms=4000 keep_temperature=53 keep_duty_cycle=0.4 on_time_cycle=12`;
+    /// `last_temperature` is left out before the first reading
+    pub fn save(&self) -> String {
+        let fields = match &self.state {
+            State::Off => String::new(),
+            State::Function { last_duty_cycle, on_time_cycle } =>
+                format!(" last_duty_cycle={} on_time_cycle={}", last_duty_cycle, on_time_cycle),
+            State::Keep { remain_time, keep_temperature, keep_duty_cycle, on_time_cycle } =>
+                format!(" remain_time_ms={} keep_temperature={} keep_duty_cycle={} on_time_cycle={}", remain_time.as_millis(), keep_temperature, keep_duty_cycle, on_time_cycle),
+            State::RampDown { duty_cycle, step, remain_time_cycle, on_time_cycle } =>
+                format!(" duty_cycle={} step={} remain_time_cycle={} on_time_cycle={}", duty_cycle, step, remain_time_cycle, on_time_cycle),
+        };
+        let last_temperature = self.last_temperature.map(|t| format!(" last_temperature={}", t)).unwrap_or_default();
+        format!("{}{}{}", self.state.name(), last_temperature, fields)
+    }
+
+    /// restores the state and last temperature written by `save`, once `check_state` accepts them under the
+    /// current curve and timing; nothing is changed on error
+    pub fn load(&mut self, s: &str) -> Result<(), ParameterError<String>> {
+        let invalid = |field, reason| ParameterError { field, reason, value: String::from(s) };
+        let mut words = s.split_whitespace();
+        let name = words.next().ok_or_else(|| invalid("state", "empty"))?;
+        let fields = words
+            .map(|word| word.split_once('=').ok_or_else(|| invalid("state", "expected `key=value`")))
+            .collect::<Result<Vec<_>, _>>()?;
+        let field = |key| fields.iter().find(|(k, _)| *k == key).map(|(_, v)| *v).unwrap_or_default();
+        let parse = |key| field(key).parse::<f32>().ok().filter(|v| v.is_finite()).ok_or_else(|| invalid(key, "missing or not finite"));
+        let count = |key| field(key).parse::<usize>().map_err(|_e| invalid(key, "missing or not a count"));
+        let state = match name {
+            "off" => State::Off,
+            "function" => State::Function { last_duty_cycle: parse("last_duty_cycle")?, on_time_cycle: count("on_time_cycle")? },
+            "keep" => State::Keep {
+                remain_time: Duration::from_millis(count("remain_time_ms")? as u64),
+                keep_temperature: parse("keep_temperature")?,
+                keep_duty_cycle: parse("keep_duty_cycle")?,
+                on_time_cycle: count("on_time_cycle")?,
+            },
+            "rampdown" => State::RampDown {
+                duty_cycle: parse("duty_cycle")?,
+                step: parse("step")?,
+                remain_time_cycle: count("remain_time_cycle")?,
+                on_time_cycle: count("on_time_cycle")?,
+            },
+            _ => return Err(invalid("state", "unknown state")),
+        };
+        let last_temperature = if field("last_temperature").is_empty() { None } else { Some(parse("last_temperature")?) };
+        self.check_state(&state).map_err(|reason| invalid("state", reason))?;
+        self.state = state;
+        self.last_temperature = last_temperature;
+        Ok(())
+    }
+
+    /// whether a restored state could have been reached with the current parameters, e.g. not after the curve
+    /// or `lag_time_cycle` changed between a save and a load
+    fn check_state(&self, state: &State) -> Result<(), &'static str> {
+        let (min_duty_cycle, max_duty_cycle) = (self.temperature_rule.min_duty_cycle(), self.temperature_rule.max_duty_cycle());
+        // a kept or ramped-down duty cycle may come from `update_force`, e.g. `initial_duty` or a max speed
+        let forced = |duty_cycle: f32| duty_cycle > 0.0 && duty_cycle <= 1.0;
+        match *state {
+            State::Off => Ok(()),
+            State::Function { last_duty_cycle, .. } if !(min_duty_cycle..=max_duty_cycle).contains(&last_duty_cycle) => Err("duty cycle outside the current curve"),
+            State::Function { .. } => Ok(()),
+            State::Keep { remain_time, .. } if remain_time > self.lag_time => Err("remaining lag longer than lag_time_cycle"),
+            State::Keep { keep_duty_cycle, .. } if !forced(keep_duty_cycle) => Err("duty cycle not in (0, 1]"),
+            State::Keep { .. } => Ok(()),
+            State::RampDown { remain_time_cycle, .. } if remain_time_cycle > self.rampdown_cycles => Err("remaining ramp down longer than rampdown_cycles"),
+            State::RampDown { duty_cycle, step, .. } if !forced(duty_cycle) || step < 0.0 => Err("duty cycle not in (0, 1] or negative step"),
+            State::RampDown { .. } => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const CYCLE: Duration = Duration::from_secs(5);
+
+    /// the default curve, 30/40/70°C and 50%..90%
+    fn function() -> Function {
+        Function::new(30.0, 40.0, 70.0, 0.5, 0.9).unwrap()
+    }
+
+    /// the default curve with a lag of `lag_cycles` and no other option
+    fn control(lag_cycles: u32) -> Control {
+        Control::new(Box::new(function()), CYCLE * lag_cycles, 0, 0.0, 0, 0.5, None).unwrap()
     }
 
-    pub fn lag_time_cycle(&self) -> usize {
-        self.lag_time_cycle
+    fn remain_time(control: &Control) -> Option<Duration> {
+        match control.state() {
+            State::Keep { remain_time, .. } => Some(*remain_time),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn function_accepts_full_duty() {
+        let f = Function::new(30.0, 40.0, 70.0, 0.5, 1.0).unwrap();
+        assert_eq!(f.map(70.0), 1.0);
+        assert_eq!(f.map(55.0), 0.75);
+    }
+
+    #[test]
+    fn function_rejects_duty_out_of_range() {
+        let e = Function::new(30.0, 40.0, 70.0, 0.5, 90.0).unwrap_err();
+        assert_eq!((e.field, e.reason), ("max_duty_cycle", Function::OVER_ONE));
+        let e = Function::new(30.0, 40.0, 70.0, 0.5, 0.0).unwrap_err();
+        assert_eq!((e.field, e.reason), ("max_duty_cycle", "not in (0, 1]"));
+        let e = Function::new(30.0, 40.0, 70.0, 1.0, 1.0).unwrap_err();
+        assert_eq!((e.field, e.reason), ("min_duty_cycle", "not in (0, 1)"));
+        let e = Function::new(30.0, 40.0, 70.0, 0.6, 0.5).unwrap_err();
+        assert_eq!((e.field, e.reason), ("max_duty_cycle", "lower than min_duty_cycle"));
+    }
+    #[test]
+    fn replace_rule_keeps_the_timing_state() {
+        let mut control = control(8);
+        assert_eq!(control.update(50.0, CYCLE), ControlOutput::Change(function().map(50.0)));
+        assert_eq!(control.update(45.0, CYCLE), ControlOutput::Keep);
+        assert_eq!(control.update(45.0, CYCLE), ControlOutput::Keep);
+        assert_eq!(remain_time(&control), Some(CYCLE * 7));
+        let quiet = Function::new(30.0, 45.0, 80.0, 0.3, 0.7).unwrap();
+        control.replace_rule(Box::new(quiet.clone()));
+        assert_eq!(remain_time(&control), Some(CYCLE * 7));
+        assert_eq!(control.last_temperature(), Some(45.0));
+        assert_eq!(control.update(45.0, CYCLE), ControlOutput::Keep);
+        assert_eq!(remain_time(&control), Some(CYCLE * 6));
+        // rising past the kept temperature follows the new curve
+        assert_eq!(control.update(60.0, CYCLE), ControlOutput::Change(quiet.map(60.0)));
+        assert_ne!(quiet.map(60.0), function().map(60.0));
     }
-}
\ No newline at end of file
+    #[test]
+    fn min_on_cycles_outlasts_a_dip() {
+        let dip = [45.0, 25.0, 25.0, 25.0, 45.0];
+        // without it the dip stops the fan once the lag is over
+        let mut plain = control(0);
+        let outputs: Vec<ControlOutput> = dip.into_iter().map(|t| plain.update(t, CYCLE)).collect();
+        assert_eq!(outputs[2], ControlOutput::Off);
+        let mut min_on = Control::new(Box::new(function()), Duration::ZERO, 6, 0.0, 0, 0.5, None).unwrap();
+        let outputs: Vec<ControlOutput> = dip.into_iter().map(|t| min_on.update(t, CYCLE)).collect();
+        assert!(!outputs.contains(&ControlOutput::Off), "{:?}", outputs);
+        assert_eq!(min_on.state_name(), "function");
+        // a longer dip stops it after 6 cycles on
+        let mut min_on = Control::new(Box::new(function()), Duration::ZERO, 6, 0.0, 0, 0.5, None).unwrap();
+        let outputs: Vec<ControlOutput> = [45.0, 25.0, 25.0, 25.0, 25.0, 25.0, 25.0].into_iter().map(|t| min_on.update(t, CYCLE)).collect();
+        assert_eq!(outputs.iter().position(|output| *output == ControlOutput::Off), Some(6));
+    }
+    #[test]
+    fn stop_hysteresis_rides_out_oscillation() {
+        let mut oscillating = vec![45.0];
+        oscillating.extend([29.0, 31.0].iter().cycle().take(10));
+        // without it the dips under stop_temperature stop the fan
+        let mut plain = control(0);
+        let outputs: Vec<ControlOutput> = oscillating.iter().map(|t| plain.update(*t, CYCLE)).collect();
+        assert_eq!(outputs.iter().position(|output| *output == ControlOutput::Off), Some(3));
+        let mut hysteresis = Control::new(Box::new(function()), Duration::ZERO, 0, 3.0, 0, 0.5, None).unwrap();
+        let outputs: Vec<ControlOutput> = oscillating.iter().map(|t| hysteresis.update(*t, CYCLE)).collect();
+        assert!(!outputs.contains(&ControlOutput::Off), "{:?}", outputs);
+        // falling past it still stops the fan
+        let outputs: Vec<ControlOutput> = [26.0, 26.0].into_iter().map(|t| hysteresis.update(t, CYCLE)).collect();
+        assert!(outputs.contains(&ControlOutput::Off), "{:?}", outputs);
+        let e = Control::new(Box::new(function()), Duration::ZERO, 0, -1.0, 0, 0.5, None).err().unwrap();
+        assert_eq!(e.field, "stop_hysteresis");
+    }
+    #[test]
+    fn duty_in_an_avoid_band_snaps_to_the_edge() {
+        let band: DutyBand = "0.45-0.55".parse().unwrap();
+        assert_eq!(band.avoid(0.47), 0.45);
+        assert_eq!(band.avoid(0.53), 0.55);
+        assert_eq!(band.avoid(0.5), 0.55);
+        // the edges and everything outside pass through
+        assert_eq!([0.3, 0.45, 0.55, 0.8].map(|duty_cycle| band.avoid(duty_cycle)), [0.3, 0.45, 0.55, 0.8]);
+        assert_eq!("0.55-0.45".parse::<DutyBand>().unwrap_err().reason, "expected 0 <= low < high <= 1");
+        assert_eq!("0.45".parse::<DutyBand>().unwrap_err().reason, "expected `low-high`");
+    }
+    #[test]
+    fn smaller_keep_decay_ramps_down_slower() {
+        let falling: Vec<f32> = (0..12).map(|i| 70.0 - 2.0 * i as f32).collect();
+        let ramp = |keep_decay: f32| -> Vec<f32> {
+            let mut control = Control::new(Box::new(function()), Duration::ZERO, 0, 0.0, 0, keep_decay, None).unwrap();
+            let mut duty_cycle = 0.0;
+            falling.iter().map(|t| {
+                if let ControlOutput::Change(changed) = control.update(*t, CYCLE) {
+                    duty_cycle = changed;
+                }
+                duty_cycle
+            }).collect()
+        };
+        let (fast, slow) = (ramp(0.5), ramp(0.2));
+        assert_eq!(fast[0], 0.9);
+        assert_eq!(slow[0], 0.9);
+        assert!(fast.windows(2).all(|w| w[1] <= w[0]), "{:?}", fast);
+        assert!(slow.windows(2).all(|w| w[1] <= w[0]), "{:?}", slow);
+        // the first fall enters the lag, every later one blends
+        assert!(fast[2..].iter().zip(slow[2..].iter()).all(|(fast, slow)| slow > fast), "{:?} {:?}", fast, slow);
+        // with a weight of 1 the kept temperature is the current one
+        assert_eq!(*ramp(1.0).last().unwrap(), function().map(*falling.last().unwrap()));
+    }
+    #[test]
+    fn lag_counts_elapsed_time() {
+        let mut short = control(2);
+        assert_eq!(short.update(50.0, CYCLE), ControlOutput::Change(function().map(50.0)));
+        assert_eq!(short.update(25.0, CYCLE), ControlOutput::Keep);
+        assert_eq!(remain_time(&short), Some(CYCLE * 2));
+        // three short cycles use up the 10s lag as two default ones would
+        for (dt, remain) in [(1, 9), (2, 7), (7, 0)] {
+            assert_eq!(short.update(25.0, Duration::from_secs(dt)), ControlOutput::Keep);
+            assert_eq!(remain_time(&short), Some(Duration::from_secs(remain)));
+        }
+        assert_eq!(short.update(25.0, Duration::from_secs(1)), ControlOutput::Off);
+        // one long cycle uses it up at once
+        let mut long = control(2);
+        long.update(50.0, CYCLE);
+        long.update(25.0, CYCLE);
+        assert_eq!(long.update(25.0, Duration::from_secs(30)), ControlOutput::Keep);
+        assert_eq!(long.update(25.0, CYCLE), ControlOutput::Off);
+    }
+    #[test]
+    fn duty_deadband_thins_out_a_ramp() {
+        // 45°C to 55°C in steps of 0.1°C, about 0.13 of duty cycle in all
+        let ramp: Vec<f32> = (0..=100).map(|i| 45.0 + 0.1 * i as f32).collect();
+        let changes = |duty_deadband: f32| -> Vec<f32> {
+            let mut control = control(8);
+            control.set_duty_deadband(duty_deadband).unwrap();
+            ramp.iter().filter_map(|t| match control.update(*t, CYCLE) {
+                ControlOutput::Change(duty_cycle) => Some(duty_cycle),
+                _ => None,
+            }).collect()
+        };
+        assert_eq!(changes(0.0).len(), ramp.len());
+        let thinned = changes(0.02);
+        assert!(thinned.len() <= 8, "{:?}", thinned);
+        assert!(thinned.windows(2).all(|w| w[1] - w[0] > 0.02), "{:?}", thinned);
+        assert_eq!(control(8).set_duty_deadband(1.0).unwrap_err().field, "duty_deadband");
+    }
+    #[test]
+    fn spike_decays_instead_of_sticking_at_max() {
+        let mut control = Control::new(Box::new(function()), Duration::ZERO, 0, 0.0, 0, 0.5, None).unwrap();
+        assert_eq!(control.update(45.0, CYCLE), ControlOutput::Change(function().map(45.0)));
+        // a one-cycle spike far above high_temperature
+        assert_eq!(control.update(150.0, CYCLE), ControlOutput::Change(0.9));
+        let outputs: Vec<ControlOutput> = (0..8).map(|_| control.update(50.0, CYCLE)).collect();
+        assert_eq!(outputs[0], ControlOutput::Keep);
+        // the first blend starts from high_temperature, the next one already leaves the maximum
+        assert_eq!(outputs[1], ControlOutput::Change(0.9));
+        assert_eq!(outputs[2], ControlOutput::Change(function().map(60.0)));
+        match &outputs[7] {
+            ControlOutput::Change(duty_cycle) => assert!((duty_cycle - function().map(50.0)).abs() < 0.01, "{}", duty_cycle),
+            output => panic!("expected a change, got {:?}", output),
+        }
+    }
+    #[test]
+    fn inverse_undoes_map() {
+        let f = function();
+        for t in (40..=70).map(|t| t as f32).chain([40.5, 55.25, 69.9]) {
+            assert!((f.inverse(f.map(t)) - t).abs() < 1e-3, "{} -> {} -> {}", t, f.map(t), f.inverse(f.map(t)));
+        }
+        assert_eq!(f.inverse(0.75), 58.75);
+        // outside [min_duty_cycle, max_duty_cycle] the knees
+        assert_eq!(f.inverse(0.2), 40.0);
+        assert_eq!(f.inverse(1.0), 70.0);
+    }
+    #[test]
+    fn stateless_and_hysteresis_on_the_same_input() {
+        let temperatures = [35.0, 45.0, 50.0, 42.0, 38.0, 32.0, 28.0, 35.0];
+        let mut stateless = control(1);
+        stateless.set_kind(ControlKind::Stateless);
+        let mut hysteresis = control(1);
+        let stateless: Vec<ControlOutput> = temperatures.iter().map(|t| stateless.update(*t, CYCLE)).collect();
+        let hysteresis: Vec<ControlOutput> = temperatures.iter().map(|t| hysteresis.update(*t, CYCLE)).collect();
+        // the curve every cycle: running from above stop_temperature, following every fall, off at once
+        assert_eq!(stateless, [ControlOutput::Change(0.5), ControlOutput::Change(0.56666666), ControlOutput::Change(0.6333333), ControlOutput::Change(0.52666664), ControlOutput::Change(0.5), ControlOutput::Keep, ControlOutput::Off, ControlOutput::Change(0.5)]);
+        // only starting from start_temperature, and holding falls for the lag
+        assert_eq!(hysteresis, [ControlOutput::Off, ControlOutput::Change(0.56666666), ControlOutput::Change(0.6333333), ControlOutput::Keep, ControlOutput::Keep, ControlOutput::Change(0.5133333), ControlOutput::Keep, ControlOutput::Keep]);
+    }
+    #[test]
+    fn first_update() {
+        let mut hot = control(8);
+        assert_eq!(hot.last_temperature(), None);
+        assert_eq!(hot.update(45.0, CYCLE), ControlOutput::Change(function().map(45.0)));
+        assert_eq!(hot.last_temperature(), Some(45.0));
+        assert_eq!(hot.state_name(), "function");
+        // a start without a previous reading enters no lag, however high the reading
+        let mut spike = control(8);
+        assert_eq!(spike.update(90.0, CYCLE), ControlOutput::Change(0.9));
+        assert_eq!(remain_time(&spike), None);
+        // a reading at the old -273.15 sentinel is a reading like any other
+        let mut cold = control(8);
+        assert_eq!(cold.update(-273.15, CYCLE), ControlOutput::Off);
+        assert_eq!(cold.last_temperature(), Some(-273.15));
+        assert_eq!(cold.update(45.0, CYCLE), ControlOutput::Change(function().map(45.0)));
+    }
+}
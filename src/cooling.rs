@@ -0,0 +1,175 @@
+use std::fs;
+use std::fs::File;
+use std::io::Error as IOError;
+use std::io::ErrorKind as IOErrorKind;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::pwm::FanOutput;
+use crate::pwm::Polarity;
+use crate::sysfs::Attribute;
+use crate::sysfs::RealSysfs;
+use crate::sysfs::Sysfs;
+
+
+/// the cooling state for `duty_cycle` out of `period`, rounded up so any running duty cycle is at least state 1;
+/// `period` 0 means not set yet and gives `max_state` for any nonzero duty cycle
+pub fn quantize(duty_cycle: u32, period: u32, max_state: u32) -> u32 {
+    if duty_cycle == 0 {
+        return 0;
+    }
+    if period == 0 || duty_cycle >= period {
+        return max_state;
+    }
+    (duty_cycle as u64 * max_state as u64).div_ceil(period as u64) as u32
+}
+
+
+/// drives a thermal cooling device, like `/sys/class/thermal/cooling_device0`, by writing `cur_state`
+/// quantized from the duty cycle with `quantize`; disabled writes state 0
+#[derive(Debug)]
+pub struct CoolingDeviceOutput {
+    cur_state: Attribute,
+    max_state: u32,
+    period: u32,
+    duty_cycle: u32,
+    enable: bool,
+    polarity: Polarity,
+}
+
+impl CoolingDeviceOutput {
+
+    pub fn new(device: impl AsRef<Path>) -> Result<Self, IOError> {
+        Self::with_sysfs(Arc::new(RealSysfs), device)
+    }
+
+    /// like `new`, through `sysfs`, e.g. a `MemorySysfs` in tests
+    pub fn with_sysfs(sysfs: Arc<dyn Sysfs>, device: impl AsRef<Path>) -> Result<Self, IOError> {
+        let path_max_state = device.as_ref().join("max_state");
+        let max_state = Attribute::new(sysfs.clone(), path_max_state.clone(), false).read_to_string()?;
+        let max_state: u32 = max_state.parse()
+            .map_err(|_e| IOError::new(IOErrorKind::InvalidData, format!("{}: invalid value `{}`", path_max_state.display(), max_state)))?;
+        if max_state == 0 {
+            return Err(IOError::new(IOErrorKind::InvalidData, format!("{}: no state to switch to", path_max_state.display())));
+        }
+        let path_cur_state = device.as_ref().join("cur_state");
+        if !sysfs.exists(&path_cur_state)? {
+            return Err(IOError::new(IOErrorKind::NotFound, format!("{}", path_cur_state.display())));
+        }
+        Ok(
+            CoolingDeviceOutput {
+                cur_state: Attribute::new(sysfs, path_cur_state, true),
+                max_state,
+                period: 0,
+                duty_cycle: 0,
+                enable: false,
+                polarity: Polarity::Normal,
+            }
+        )
+    }
+
+    pub fn max_state(&self) -> u32 {
+        self.max_state
+    }
+
+    fn write_state(&mut self) -> Result<(), IOError> {
+        let state = if self.enable { quantize(self.duty_cycle, self.period, self.max_state) } else { 0 };
+        self.cur_state.write(state)
+    }
+}
+
+/// checks the cooling device can be driven without writing anything: reads `max_state` and opens `cur_state`
+/// for writing; returns `max_state`
+pub fn probe(device: &Path) -> Result<u32, IOError> {
+    let path_max_state = device.join("max_state");
+    let max_state = fs::read_to_string(&path_max_state)
+        .map_err(|e| IOError::new(e.kind(), format!("{}: {}", path_max_state.display(), e)))?;
+    let max_state = max_state.trim().parse()
+        .map_err(|_e| IOError::new(IOErrorKind::InvalidData, format!("{}: invalid value `{}`", path_max_state.display(), max_state.trim())))?;
+    let path_cur_state = device.join("cur_state");
+    File::options().write(true).open(&path_cur_state)
+        .map_err(|e| IOError::new(e.kind(), format!("{}: {}", path_cur_state.display(), e)))?;
+    Ok(max_state)
+}
+
+impl FanOutput for CoolingDeviceOutput {
+
+    fn set_period(&mut self, period: u32) -> Result<(), IOError> {
+        self.period = period;
+        Ok(())
+    }
+
+    fn set_duty_cycle(&mut self, duty_cycle: u32) -> Result<u32, IOError> {
+        self.duty_cycle = duty_cycle;
+        if self.enable {
+            self.write_state()?;
+        }
+        Ok(duty_cycle)
+    }
+
+    /// only recorded; a cooling state has no polarity
+    fn set_polarity(&mut self, polarity: Polarity) -> Result<(), IOError> {
+        self.polarity = polarity;
+        Ok(())
+    }
+
+    fn set_enable(&mut self, enable: bool) -> Result<(), IOError> {
+        self.enable = enable;
+        self.write_state()
+    }
+
+    fn get_period(&mut self) -> Result<u32, IOError> {
+        Ok(self.period)
+    }
+
+    fn get_polarity(&mut self) -> Result<Polarity, IOError> {
+        Ok(self.polarity.clone())
+    }
+
+    fn get_enable(&mut self) -> Result<bool, IOError> {
+        Ok(self.enable)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use crate::sysfs::MemorySysfs;
+
+    use super::*;
+
+    const DEVICE: &str = "/sys/class/thermal/cooling_device0";
+
+    #[test]
+    fn quantize_rounds_up() {
+        let states: Vec<u32> = [0, 1, 2500, 2501, 5000, 9999, 10000, 12000].into_iter().map(|duty_cycle| quantize(duty_cycle, 10000, 4)).collect();
+        assert_eq!(states, [0, 1, 1, 2, 2, 4, 4, 4]);
+        assert_eq!(quantize(1, 0, 4), 4);
+        assert_eq!(quantize(0, 0, 4), 0);
+        // no overflow for a large period and max_state
+        assert_eq!(quantize(u32::MAX - 1, u32::MAX, u32::MAX), u32::MAX - 1);
+    }
+
+    #[test]
+    fn output_writes_quantized_states() {
+        let sysfs = MemorySysfs::new();
+        sysfs.set(format!("{}/max_state", DEVICE), "3\n");
+        sysfs.set(format!("{}/cur_state", DEVICE), 0);
+        let mut output = CoolingDeviceOutput::with_sysfs(Arc::new(sysfs.clone()), DEVICE).unwrap();
+        assert_eq!(output.max_state(), 3);
+        output.apply(10000, 5000, Polarity::Normal, true).unwrap();
+        output.set_duty_cycle(9000).unwrap();
+        output.set_duty_cycle(3000).unwrap();
+        output.set_enable(false).unwrap();
+        assert_eq!(sysfs.take_writes(format!("{}/cur_state", DEVICE)), ["2", "3", "1", "0"]);
+    }
+
+    #[test]
+    fn zero_max_state_is_rejected() {
+        let sysfs = MemorySysfs::new();
+        sysfs.set(format!("{}/max_state", DEVICE), 0);
+        sysfs.set(format!("{}/cur_state", DEVICE), 0);
+        assert_eq!(CoolingDeviceOutput::with_sysfs(Arc::new(sysfs), DEVICE).unwrap_err().kind(), IOErrorKind::InvalidData);
+    }
+}
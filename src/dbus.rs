@@ -0,0 +1,762 @@
+use std::env;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Error as IOError;
+use std::io::ErrorKind as IOErrorKind;
+use std::io::Read;
+use std::io::Write;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::SocketAddr;
+use std::os::unix::net::UnixStream;
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+
+
+/// well-known name, object path and interface of the object
+pub const NAME: &str = "dev.fanctrl.Rock5b";
+pub const PATH: &str = "/dev/fanctrl/Rock5b";
+
+/// used when `$DBUS_SYSTEM_BUS_ADDRESS` is not set
+const SYSTEM_BUS_ADDRESS: &str = "unix:path=/var/run/dbus/system_bus_socket";
+
+/// largest message accepted from the bus; ours are a few hundred bytes
+const MAX_MESSAGE: usize = 1 << 20;
+
+const METHOD_CALL: u8 = 1;
+const METHOD_RETURN: u8 = 2;
+const ERROR: u8 = 3;
+const NO_REPLY_EXPECTED: u8 = 0x1;
+
+/// `RequestName` flag: fail instead of waiting in the queue for the name
+const DO_NOT_QUEUE: u32 = 4;
+
+const INTROSPECTION: &str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="dev.fanctrl.Rock5b">
+    <property name="Temperature" type="d" access="read">
+      <annotation name="org.freedesktop.DBus.Property.EmitsChangedSignal" value="false"/>
+    </property>
+    <property name="DutyCycle" type="d" access="read">
+      <annotation name="org.freedesktop.DBus.Property.EmitsChangedSignal" value="false"/>
+    </property>
+    <property name="FanOn" type="b" access="read">
+      <annotation name="org.freedesktop.DBus.Property.EmitsChangedSignal" value="false"/>
+    </property>
+    <method name="Boost">
+      <arg name="cycles" type="u" direction="in"/>
+    </method>
+    <method name="ReloadConfig"/>
+  </interface>
+  <interface name="org.freedesktop.DBus.Properties">
+    <method name="Get">
+      <arg name="interface_name" type="s" direction="in"/>
+      <arg name="property_name" type="s" direction="in"/>
+      <arg name="value" type="v" direction="out"/>
+    </method>
+    <method name="GetAll">
+      <arg name="interface_name" type="s" direction="in"/>
+      <arg name="properties" type="a{sv}" direction="out"/>
+    </method>
+  </interface>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect">
+      <arg name="xml_data" type="s" direction="out"/>
+    </method>
+  </interface>
+  <interface name="org.freedesktop.DBus.Peer">
+    <method name="Ping"/>
+  </interface>
+</node>
+"#;
+
+
+/// the properties of the object; with several fans the hottest temperature, the highest duty cycle and
+/// whether any fan runs
+#[derive(Debug, Clone, Copy)]
+pub struct Properties {
+    /// degrees Celsius, NaN before the first reading
+    pub temperature: f32,
+    /// 0 when off
+    pub duty_cycle: f32,
+    pub on: bool,
+}
+
+impl Default for Properties {
+    fn default() -> Self {
+        Self { temperature: f32::NAN, duty_cycle: 0.0, on: false }
+    }
+}
+
+/// what a method call asks of the control loop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// maximum speed for that many cycles, capped at `max_speed_time_cycle`; 0 for `max_speed_time_cycle`
+    Boost(u32),
+    ReloadConfig,
+}
+
+type Waker = Box<dyn Fn() + Send>;
+
+
+/// owns `dev.fanctrl.Rock5b` on the system bus and answers its method calls from a background thread;
+/// `Boost` and `ReloadConfig` are queued for the control loop, woken through the waker
+pub struct DbusServer {
+    properties: Arc<Mutex<Properties>>,
+    commands: Receiver<Command>,
+    waker: Arc<Mutex<Waker>>,
+}
+
+impl DbusServer {
+
+    /// connects to `$DBUS_SYSTEM_BUS_ADDRESS`, or the default system bus, and requests `NAME`;
+    /// `waker` is called after each queued command
+    pub fn connect(waker: impl Fn() + Send + 'static) -> Result<Self, IOError> {
+        let address = env::var("DBUS_SYSTEM_BUS_ADDRESS").unwrap_or_else(|_e| String::from(SYSTEM_BUS_ADDRESS));
+        let mut connection = Connection::open(&address)?;
+        let unique_name = connection.hello()?;
+        connection.request_name(NAME)?;
+        log::info!("dbus connected: address={}, name={}, unique_name={}", address, NAME, unique_name);
+        let properties = Arc::new(Mutex::new(Properties::default()));
+        let (sender, commands) = mpsc::channel();
+        let waker: Arc<Mutex<Waker>> = Arc::new(Mutex::new(Box::new(waker)));
+        let shared = (properties.clone(), waker.clone());
+        thread::Builder::new()
+            .name(String::from("dbus"))
+            .spawn(move || {
+                if let Err(e) = connection.serve(&shared.0, &sender, &shared.1) {
+                    log::warn!("dbus connection lost: {:?}", e);
+                }
+            })?;
+        Ok(Self { properties, commands, waker })
+    }
+
+    pub fn update(&self, properties: Properties) {
+        if let Ok(mut guard) = self.properties.lock() {
+            *guard = properties;
+        }
+    }
+
+    /// replaces the waker, e.g. for a loop that does not wait in `signal::wait`
+    pub fn set_waker(&self, waker: impl Fn() + Send + 'static) {
+        if let Ok(mut guard) = self.waker.lock() {
+            *guard = Box::new(waker);
+        }
+    }
+
+    /// the commands queued since the last call
+    pub fn commands(&self) -> impl Iterator<Item = Command> + '_ {
+        self.commands.try_iter()
+    }
+}
+
+
+/// a parsed message; only the header fields we act on
+#[derive(Debug, Default)]
+struct Message {
+    kind: u8,
+    flags: u8,
+    serial: u32,
+    path: Option<String>,
+    interface: Option<String>,
+    member: Option<String>,
+    error_name: Option<String>,
+    reply_serial: Option<u32>,
+    sender: Option<String>,
+    signature: String,
+    body: Vec<u8>,
+    big_endian: bool,
+}
+
+impl Message {
+
+    fn body(&self) -> Reader<'_> {
+        Reader { buf: &self.body, pos: 0, big_endian: self.big_endian }
+    }
+
+    /// the first string of an error body, for the log
+    fn error_message(&self) -> String {
+        let name = self.error_name.as_deref().unwrap_or("unknown error");
+        match self.signature.starts_with('s').then(|| self.body().string()) {
+            Some(Ok(message)) => format!("{}: {}", name, message),
+            _ => String::from(name),
+        }
+    }
+}
+
+
+struct Connection {
+    reader: BufReader<UnixStream>,
+    stream: UnixStream,
+    serial: u32,
+}
+
+impl Connection {
+
+    /// connects to the first `unix:` entry of `address` with `path` or `abstract`, and authenticates as our uid
+    fn open(address: &str) -> Result<Self, IOError> {
+        let socket_address = address
+            .split(';')
+            .filter_map(|entry| entry.strip_prefix("unix:"))
+            .find_map(|params| {
+                params.split(',').find_map(|param| match param.split_once('=') {
+                    Some(("path", path)) => Some(SocketAddr::from_pathname(unescape(path))),
+                    Some(("abstract", name)) => Some(SocketAddr::from_abstract_name(unescape(name).as_bytes())),
+                    _ => None,
+                })
+            })
+            .ok_or_else(|| IOError::new(IOErrorKind::InvalidInput, format!("no unix path in dbus address `{}`", address)))??;
+        let stream = UnixStream::connect_addr(&socket_address)
+            .map_err(|e| IOError::new(e.kind(), format!("{}: {}", address, e)))?;
+        let mut connection = Self { reader: BufReader::new(stream.try_clone()?), stream, serial: 0 };
+        connection.authenticate()?;
+        Ok(connection)
+    }
+
+    /// SASL `EXTERNAL`: the bus checks our uid against the socket credentials
+    fn authenticate(&mut self) -> Result<(), IOError> {
+        let uid = unsafe { libc::getuid() }.to_string();
+        let hex: String = uid.bytes().map(|b| format!("{:02x}", b)).collect();
+        self.stream.write_all(format!("\0AUTH EXTERNAL {}\r\n", hex).as_bytes())?;
+        let mut line = String::new();
+        self.reader.read_line(&mut line)?;
+        if !line.starts_with("OK ") {
+            return Err(IOError::new(IOErrorKind::PermissionDenied, format!("dbus authentication rejected: {}", line.trim_end())));
+        }
+        self.stream.write_all(b"BEGIN\r\n")
+    }
+
+    fn hello(&mut self) -> Result<String, IOError> {
+        let reply = self.call_bus("Hello", "", Vec::new())?;
+        reply.body().string()
+    }
+
+    fn request_name(&mut self, name: &str) -> Result<(), IOError> {
+        let mut body = Writer::default();
+        body.string(name);
+        body.u32(DO_NOT_QUEUE);
+        let reply = self.call_bus("RequestName", "su", body.buf)?;
+        match reply.body().u32()? {
+            // primary owner, or already the owner
+            1 | 4 => Ok(()),
+            _ => Err(IOError::new(IOErrorKind::AddrInUse, format!("dbus name {} is already owned", name))),
+        }
+    }
+
+    /// calls `member` on the bus itself and waits for its reply, skipping the signals that come first
+    fn call_bus(&mut self, member: &str, signature: &str, body: Vec<u8>) -> Result<Message, IOError> {
+        let serial = self.send(METHOD_CALL, &[
+            Field::Object(1, "/org/freedesktop/DBus"),
+            Field::String(2, "org.freedesktop.DBus"),
+            Field::String(3, member),
+            Field::String(6, "org.freedesktop.DBus"),
+        ], signature, &body)?;
+        loop {
+            let message = self.receive()?;
+            if message.reply_serial != Some(serial) {
+                continue;
+            }
+            return match message.kind {
+                METHOD_RETURN => Ok(message),
+                _ => Err(IOError::other(format!("dbus {} failed: {}", member, message.error_message()))),
+            };
+        }
+    }
+
+    /// answers method calls until the bus goes away
+    fn serve(&mut self, properties: &Mutex<Properties>, commands: &Sender<Command>, waker: &Mutex<Waker>) -> Result<(), IOError> {
+        loop {
+            let message = self.receive()?;
+            if message.kind != METHOD_CALL {
+                continue;
+            }
+            let properties = properties.lock().map(|guard| *guard).unwrap_or_default();
+            let (reply, command) = dispatch(&message, &properties);
+            if let Some(command) = command {
+                log::debug!("dbus {:?} from {}", command, message.sender.as_deref().unwrap_or("?"));
+                if commands.send(command).is_err() {
+                    return Ok(());
+                }
+                if let Ok(waker) = waker.lock() {
+                    waker();
+                }
+            }
+            if message.flags & NO_REPLY_EXPECTED != 0 {
+                continue;
+            }
+            let destination = message.sender.as_deref().unwrap_or("");
+            match reply {
+                Ok((signature, body)) => {
+                    self.send(METHOD_RETURN, &[Field::U32(5, message.serial), Field::String(6, destination)], signature, &body)?;
+                }
+                Err((name, text)) => {
+                    let mut body = Writer::default();
+                    body.string(&text);
+                    self.send(ERROR, &[Field::String(4, name), Field::U32(5, message.serial), Field::String(6, destination)], "s", &body.buf)?;
+                }
+            }
+        }
+    }
+
+    /// returns the serial of the sent message
+    fn send(&mut self, kind: u8, fields: &[Field<'_>], signature: &str, body: &[u8]) -> Result<u32, IOError> {
+        self.serial += 1;
+        let mut header = Writer::default();
+        header.u8(b'l');
+        header.u8(kind);
+        header.u8(0);
+        header.u8(1);
+        header.u32(body.len() as u32);
+        header.u32(self.serial);
+        let array = header.begin_array(8);
+        for field in fields {
+            field.write(&mut header);
+        }
+        if !signature.is_empty() {
+            Field::Signature(8, signature).write(&mut header);
+        }
+        header.end_array(array);
+        header.pad(8);
+        header.buf.extend_from_slice(body);
+        self.stream.write_all(&header.buf)?;
+        Ok(self.serial)
+    }
+
+    fn receive(&mut self) -> Result<Message, IOError> {
+        let mut fixed = [0u8; 16];
+        self.reader.read_exact(&mut fixed)?;
+        let big_endian = match fixed[0] {
+            b'l' => false,
+            b'B' => true,
+            _ => return Err(IOError::new(IOErrorKind::InvalidData, "dbus message with an unknown byte order")),
+        };
+        let word = |i: usize| {
+            let bytes = [fixed[i], fixed[i + 1], fixed[i + 2], fixed[i + 3]];
+            (if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) }) as usize
+        };
+        let (body_len, fields_len) = (word(4), word(12));
+        let header_len = (16 + fields_len).next_multiple_of(8);
+        if header_len + body_len > MAX_MESSAGE {
+            return Err(IOError::new(IOErrorKind::InvalidData, "dbus message too large"));
+        }
+        let mut buf = vec![0u8; header_len + body_len];
+        buf[..16].copy_from_slice(&fixed);
+        self.reader.read_exact(&mut buf[16..])?;
+        let body = buf.split_off(header_len);
+        let mut message = Message { kind: fixed[1], flags: fixed[2], body, big_endian, ..Message::default() };
+        let mut header = Reader { buf: &buf, pos: 8, big_endian };
+        message.serial = header.u32()?;
+        let end = header.u32()? as usize + header.pos;
+        while header.pos < end {
+            header.pad(8)?;
+            let code = header.u8()?;
+            let signature = header.signature()?;
+            match (code, signature.as_str()) {
+                (1, "o") => message.path = Some(header.string()?),
+                (2, "s") => message.interface = Some(header.string()?),
+                (3, "s") => message.member = Some(header.string()?),
+                (4, "s") => message.error_name = Some(header.string()?),
+                (5, "u") => message.reply_serial = Some(header.u32()?),
+                (7, "s") => message.sender = Some(header.string()?),
+                (8, "g") => message.signature = header.signature()?,
+                (_, "s" | "o") => { header.string()?; }
+                (_, "g") => { header.signature()?; }
+                (_, "u") => { header.u32()?; }
+                _ => return Err(IOError::new(IOErrorKind::InvalidData, format!("dbus header field {} of type {}", code, signature))),
+            }
+        }
+        Ok(message)
+    }
+}
+
+
+type Reply = Result<(&'static str, Vec<u8>), (&'static str, String)>;
+
+/// the reply to a method call on our object, and the command it queues
+fn dispatch(message: &Message, properties: &Properties) -> (Reply, Option<Command>) {
+    let interface = message.interface.as_deref();
+    let member = message.member.as_deref().unwrap_or("");
+    let invalid_args = |expected: &str| Err(("org.freedesktop.DBus.Error.InvalidArgs", format!("expected arguments `{}`, got `{}`", expected, message.signature)));
+    if interface == Some("org.freedesktop.DBus.Peer") && member == "Ping" {
+        return (Ok(("", Vec::new())), None);
+    }
+    if message.path.as_deref() != Some(PATH) {
+        return (Err(("org.freedesktop.DBus.Error.UnknownObject", format!("no object at {}", message.path.as_deref().unwrap_or("")))), None);
+    }
+    let reply = match (interface, member) {
+        (Some("org.freedesktop.DBus.Introspectable") | None, "Introspect") => {
+            let mut body = Writer::default();
+            body.string(INTROSPECTION);
+            Ok(("s", body.buf))
+        }
+        (Some("org.freedesktop.DBus.Properties") | None, "Get") => {
+            if message.signature != "ss" {
+                return (invalid_args("ss"), None);
+            }
+            let mut args = message.body();
+            match (args.string(), args.string()) {
+                (Ok(interface), Ok(name)) if interface == NAME || interface.is_empty() => {
+                    let mut body = Writer::default();
+                    if properties.write(&mut body, &name) {
+                        Ok(("v", body.buf))
+                    } else {
+                        Err(("org.freedesktop.DBus.Error.UnknownProperty", format!("no property {}", name)))
+                    }
+                }
+                (Ok(interface), Ok(_)) => Err(("org.freedesktop.DBus.Error.UnknownInterface", format!("no interface {}", interface))),
+                _ => invalid_args("ss"),
+            }
+        }
+        (Some("org.freedesktop.DBus.Properties") | None, "GetAll") => {
+            if message.signature != "s" {
+                return (invalid_args("s"), None);
+            }
+            let interface = message.body().string().unwrap_or_default();
+            let mut body = Writer::default();
+            let array = body.begin_array(8);
+            if interface == NAME || interface.is_empty() {
+                for name in Properties::NAMES {
+                    body.pad(8);
+                    body.string(name);
+                    properties.write(&mut body, name);
+                }
+            }
+            body.end_array(array);
+            Ok(("a{sv}", body.buf))
+        }
+        (Some("org.freedesktop.DBus.Properties") | None, "Set") => {
+            Err(("org.freedesktop.DBus.Error.PropertyReadOnly", String::from("properties are read-only")))
+        }
+        (Some(NAME) | None, "Boost") => {
+            if message.signature != "u" {
+                return (invalid_args("u"), None);
+            }
+            match message.body().u32() {
+                Ok(cycles) => return (Ok(("", Vec::new())), Some(Command::Boost(cycles))),
+                Err(_e) => invalid_args("u"),
+            }
+        }
+        (Some(NAME) | None, "ReloadConfig") => {
+            return (Ok(("", Vec::new())), Some(Command::ReloadConfig));
+        }
+        _ => Err(("org.freedesktop.DBus.Error.UnknownMethod", format!("no method {}.{}", interface.unwrap_or(""), member))),
+    };
+    (reply, None)
+}
+
+impl Properties {
+
+    const NAMES: [&'static str; 3] = ["Temperature", "DutyCycle", "FanOn"];
+
+    /// writes `name` as a variant; false for an unknown property
+    fn write(&self, body: &mut Writer, name: &str) -> bool {
+        match name {
+            "Temperature" => {
+                body.signature("d");
+                body.f64(self.temperature as f64);
+            }
+            "DutyCycle" => {
+                body.signature("d");
+                body.f64(self.duty_cycle as f64);
+            }
+            "FanOn" => {
+                body.signature("b");
+                body.u32(self.on as u32);
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+
+/// a header field: code and value
+enum Field<'a> {
+    String(u8, &'a str),
+    Object(u8, &'a str),
+    Signature(u8, &'a str),
+    U32(u8, u32),
+}
+
+impl Field<'_> {
+
+    fn write(&self, w: &mut Writer) {
+        w.pad(8);
+        match *self {
+            Field::String(code, value) => {
+                w.u8(code);
+                w.signature("s");
+                w.string(value);
+            }
+            Field::Object(code, value) => {
+                w.u8(code);
+                w.signature("o");
+                w.string(value);
+            }
+            Field::Signature(code, value) => {
+                w.u8(code);
+                w.signature("g");
+                w.signature(value);
+            }
+            Field::U32(code, value) => {
+                w.u8(code);
+                w.signature("u");
+                w.u32(value);
+            }
+        }
+    }
+}
+
+
+/// little-endian marshalling; offsets, and so alignment, count from the start of the buffer
+#[derive(Default)]
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+
+    fn pad(&mut self, align: usize) {
+        let len = self.buf.len().next_multiple_of(align);
+        self.buf.resize(len, 0);
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.pad(4);
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn f64(&mut self, value: f64) {
+        self.pad(8);
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn string(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.buf.extend_from_slice(value.as_bytes());
+        self.buf.push(0);
+    }
+
+    fn signature(&mut self, value: &str) {
+        self.u8(value.len() as u8);
+        self.buf.extend_from_slice(value.as_bytes());
+        self.buf.push(0);
+    }
+
+    /// writes a placeholder length and the padding of the elements; returns where both are
+    fn begin_array(&mut self, align: usize) -> (usize, usize) {
+        self.u32(0);
+        let length = self.buf.len() - 4;
+        self.pad(align);
+        (length, self.buf.len())
+    }
+
+    fn end_array(&mut self, (length, start): (usize, usize)) {
+        let len = (self.buf.len() - start) as u32;
+        self.buf[length..length + 4].copy_from_slice(&len.to_le_bytes());
+    }
+}
+
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    big_endian: bool,
+}
+
+impl Reader<'_> {
+
+    fn take(&mut self, len: usize) -> Result<&[u8], IOError> {
+        let end = self.pos.checked_add(len).filter(|end| *end <= self.buf.len())
+            .ok_or_else(|| IOError::new(IOErrorKind::InvalidData, "truncated dbus message"))?;
+        let bytes = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn pad(&mut self, align: usize) -> Result<(), IOError> {
+        let len = self.pos.next_multiple_of(align) - self.pos;
+        self.take(len).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Result<u8, IOError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, IOError> {
+        self.pad(4)?;
+        let big_endian = self.big_endian;
+        let bytes: [u8; 4] = self.take(4)?.try_into().map_err(IOError::other)?;
+        Ok(if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    }
+
+    fn string(&mut self) -> Result<String, IOError> {
+        let len = self.u32()? as usize;
+        let bytes = self.take(len + 1)?;
+        String::from_utf8(bytes[..len].to_vec()).map_err(|e| IOError::new(IOErrorKind::InvalidData, e))
+    }
+
+    fn signature(&mut self) -> Result<String, IOError> {
+        let len = self.u8()? as usize;
+        let bytes = self.take(len + 1)?;
+        String::from_utf8(bytes[..len].to_vec()).map_err(|e| IOError::new(IOErrorKind::InvalidData, e))
+    }
+}
+
+
+/// undoes the `%XX` escapes of a dbus address value
+fn unescape(value: &str) -> String {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        match (b, tail.get(..2).and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok())) {
+            (b'%', Some(decoded)) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(b);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(interface: &str, member: &str, signature: &str, body: Vec<u8>) -> Message {
+        Message {
+            kind: METHOD_CALL,
+            path: Some(String::from(PATH)),
+            interface: Some(String::from(interface)),
+            member: Some(String::from(member)),
+            signature: String::from(signature),
+            body,
+            ..Message::default()
+        }
+    }
+
+    fn properties() -> Properties {
+        Properties { temperature: 45.0, duty_cycle: 0.5, on: true }
+    }
+
+    #[test]
+    fn writer_and_reader_round_trip() {
+        let mut w = Writer::default();
+        w.u8(7);
+        w.u32(0x01020304);
+        w.string("fan");
+        w.signature("a{sv}");
+        w.u32(u32::MAX);
+        // the u32 after the leading byte is aligned to 4
+        assert_eq!(&w.buf[..8], &[7, 0, 0, 0, 4, 3, 2, 1]);
+        let mut r = Reader { buf: &w.buf, pos: 0, big_endian: false };
+        assert_eq!(r.u8().unwrap(), 7);
+        assert_eq!(r.u32().unwrap(), 0x01020304);
+        assert_eq!(r.string().unwrap(), "fan");
+        assert_eq!(r.signature().unwrap(), "a{sv}");
+        assert_eq!(r.u32().unwrap(), u32::MAX);
+        assert_eq!(r.pos, w.buf.len());
+        assert_eq!(r.u8().unwrap_err().kind(), IOErrorKind::InvalidData);
+        let mut big = Reader { buf: &[0, 0, 0, 12], pos: 0, big_endian: true };
+        assert_eq!(big.u32().unwrap(), 12);
+    }
+
+    #[test]
+    fn reader_rejects_a_truncated_string() {
+        let mut w = Writer::default();
+        w.string("Temperature");
+        let mut r = Reader { buf: &w.buf[..8], pos: 0, big_endian: false };
+        assert_eq!(r.string().unwrap_err().kind(), IOErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn get_all_body() {
+        let mut args = Writer::default();
+        args.string(NAME);
+        let (reply, command) = dispatch(&call("org.freedesktop.DBus.Properties", "GetAll", "s", args.buf), &properties());
+        let expected = [
+            &84u32.to_le_bytes()[..], &[0; 4],
+            &11u32.to_le_bytes(), b"Temperature\0", &[1, b'd', 0], &[0; 5], &45f64.to_le_bytes(),
+            &9u32.to_le_bytes(), b"DutyCycle\0", &[1, b'd', 0], &[0; 7], &0.5f64.to_le_bytes(),
+            &5u32.to_le_bytes(), b"FanOn\0", &[1, b'b', 0], &[0; 3], &1u32.to_le_bytes(),
+        ].concat();
+        assert_eq!(reply, Ok(("a{sv}", expected)));
+        assert_eq!(command, None);
+        // another interface has no properties
+        let mut args = Writer::default();
+        args.string("org.example");
+        let (reply, _) = dispatch(&call("org.freedesktop.DBus.Properties", "GetAll", "s", args.buf), &properties());
+        assert_eq!(reply, Ok(("a{sv}", vec![0; 8])));
+    }
+
+    #[test]
+    fn get_a_property() {
+        let mut args = Writer::default();
+        args.string(NAME);
+        args.string("FanOn");
+        let (reply, _) = dispatch(&call("org.freedesktop.DBus.Properties", "Get", "ss", args.buf), &properties());
+        assert_eq!(reply, Ok(("v", vec![1, b'b', 0, 0, 1, 0, 0, 0])));
+        let mut args = Writer::default();
+        args.string(NAME);
+        args.string("Speed");
+        let (reply, _) = dispatch(&call("org.freedesktop.DBus.Properties", "Get", "ss", args.buf), &properties());
+        assert_eq!(reply.unwrap_err().0, "org.freedesktop.DBus.Error.UnknownProperty");
+    }
+
+    #[test]
+    fn dispatch_queues_the_methods() {
+        let mut args = Writer::default();
+        args.u32(12);
+        assert_eq!(dispatch(&call(NAME, "Boost", "u", args.buf), &properties()), (Ok(("", Vec::new())), Some(Command::Boost(12))));
+        assert_eq!(dispatch(&call(NAME, "ReloadConfig", "", Vec::new()), &properties()), (Ok(("", Vec::new())), Some(Command::ReloadConfig)));
+        // a missing or truncated argument queues nothing
+        let (reply, command) = dispatch(&call(NAME, "Boost", "", Vec::new()), &properties());
+        assert_eq!(reply.unwrap_err().0, "org.freedesktop.DBus.Error.InvalidArgs");
+        assert_eq!(command, None);
+        let (reply, command) = dispatch(&call(NAME, "Boost", "u", vec![12, 0]), &properties());
+        assert_eq!(reply.unwrap_err().0, "org.freedesktop.DBus.Error.InvalidArgs");
+        assert_eq!(command, None);
+    }
+
+    #[test]
+    fn dispatch_errors() {
+        let (reply, _) = dispatch(&call("org.freedesktop.DBus.Properties", "Set", "ssv", Vec::new()), &properties());
+        assert_eq!(reply.unwrap_err().0, "org.freedesktop.DBus.Error.PropertyReadOnly");
+        let (reply, _) = dispatch(&call(NAME, "Shutdown", "", Vec::new()), &properties());
+        assert_eq!(reply.unwrap_err().0, "org.freedesktop.DBus.Error.UnknownMethod");
+        // Ping answers on any path, the rest only on ours
+        let mut ping = call("org.freedesktop.DBus.Peer", "Ping", "", Vec::new());
+        ping.path = Some(String::from("/"));
+        assert_eq!(dispatch(&ping, &properties()).0, Ok(("", Vec::new())));
+        let mut reload = call(NAME, "ReloadConfig", "", Vec::new());
+        reload.path = Some(String::from("/"));
+        assert_eq!(dispatch(&reload, &properties()), (Err(("org.freedesktop.DBus.Error.UnknownObject", String::from("no object at /"))), None));
+    }
+
+    #[test]
+    fn introspect_lists_the_interface() {
+        let (reply, _) = dispatch(&call("org.freedesktop.DBus.Introspectable", "Introspect", "", Vec::new()), &properties());
+        let (signature, body) = reply.unwrap();
+        assert_eq!(signature, "s");
+        let xml = Reader { buf: &body, pos: 0, big_endian: false }.string().unwrap();
+        assert!(xml.contains(r#"<method name="Boost">"#));
+    }
+
+    #[test]
+    fn unescape_address() {
+        assert_eq!(unescape("/run/dbus%2dsocket%zz"), "/run/dbus-socket%zz");
+    }
+}
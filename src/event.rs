@@ -0,0 +1,9 @@
+/// fan state transitions, sent at the points where they are logged; `fan` is empty for the unnamed top-level fan
+#[derive(Debug, Clone, PartialEq)]
+pub enum FanEvent {
+    Started { fan: String, temperature: f32, duty_cycle: f32 },
+    Stopped { fan: String, temperature: f32 },
+    DutyChanged { fan: String, temperature: f32, duty_cycle: f32 },
+    MaxSpeed { fan: String, duty_cycle: f32 },
+    Critical { fan: String, temperature: f32 },
+}
@@ -0,0 +1,115 @@
+use std::fs::File;
+use std::io::Error as IOError;
+use std::io::ErrorKind as IOErrorKind;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::pwm::FanOutput;
+use crate::pwm::Polarity;
+use crate::sysfs::Attribute;
+use crate::sysfs::RealSysfs;
+use crate::sysfs::Sysfs;
+
+
+/// the sysfs gpio interface, where lines are exported by their global number
+pub const GPIO_CLASS: &str = "/sys/class/gpio";
+
+/// `/sys/class/gpio/gpioN`
+pub fn line_path(line: u32) -> PathBuf {
+    Path::new(GPIO_CLASS).join(format!("gpio{}", line))
+}
+
+
+/// a fan switched by a gpio output line, for boards wired without pwm: `set_enable` drives the line
+/// high or low, the duty cycle, period and polarity are only recorded
+#[derive(Debug)]
+pub struct GpioFanDevice {
+    line: u32,
+    value: Attribute,
+    period: u32,
+    polarity: Polarity,
+}
+
+impl GpioFanDevice {
+
+    pub fn new(line: u32) -> Result<Self, IOError> {
+        Self::with_sysfs(Arc::new(RealSysfs), line)
+    }
+
+    /// like `new`, through `sysfs`, e.g. a `MemorySysfs` in tests
+    pub fn with_sysfs(sysfs: Arc<dyn Sysfs>, line: u32) -> Result<Self, IOError> {
+        let path = line_path(line);
+        if !sysfs.exists(&path).unwrap_or(false) {
+            sysfs.write(&Path::new(GPIO_CLASS).join("export"), line.to_string().as_str())?;
+        }
+        // `low` makes the line an output that starts off, without a glitch to high
+        sysfs.write(&path.join("direction"), "low")
+            .map_err(|e| IOError::new(e.kind(), format!("{}/direction: {}", path.display(), e)))?;
+        let value_path = path.join("value");
+        if !sysfs.exists(&value_path)? {
+            return Err(IOError::new(IOErrorKind::NotFound, format!("{}", value_path.display())));
+        }
+        Ok(
+            GpioFanDevice {
+                line,
+                value: Attribute::new(sysfs, value_path, true),
+                period: 0,
+                polarity: Polarity::Normal,
+            }
+        )
+    }
+
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+}
+
+/// checks the line can be driven without writing anything: opens `value` of an exported line, or `export`
+/// otherwise, for writing; returns whether the line is exported
+pub fn probe(line: u32) -> Result<bool, IOError> {
+    let path = line_path(line);
+    let attribute_path = if path.try_exists()? { path.join("value") } else { Path::new(GPIO_CLASS).join("export") };
+    File::options().write(true).open(&attribute_path)
+        .map_err(|e| IOError::new(e.kind(), format!("{}: {}", attribute_path.display(), e)))?;
+    Ok(attribute_path.ends_with("value"))
+}
+
+impl FanOutput for GpioFanDevice {
+
+    fn set_period(&mut self, period: u32) -> Result<(), IOError> {
+        self.period = period;
+        Ok(())
+    }
+
+    /// the line is either on or off, so any duty cycle runs the fan at full speed while enabled
+    fn set_duty_cycle(&mut self, duty_cycle: u32) -> Result<u32, IOError> {
+        Ok(duty_cycle)
+    }
+
+    fn set_polarity(&mut self, polarity: Polarity) -> Result<(), IOError> {
+        self.polarity = polarity;
+        Ok(())
+    }
+
+    fn set_enable(&mut self, enable: bool) -> Result<(), IOError> {
+        self.value.write(enable as u8)
+    }
+
+    fn get_period(&mut self) -> Result<u32, IOError> {
+        Ok(self.period)
+    }
+
+    fn get_polarity(&mut self) -> Result<Polarity, IOError> {
+        Ok(self.polarity.clone())
+    }
+
+    /// the level of the line
+    fn get_enable(&mut self) -> Result<bool, IOError> {
+        match self.value.read_to_string()?.as_str() {
+            "0" => Ok(false),
+            "1" => Ok(true),
+            s => Err(IOError::new(IOErrorKind::InvalidData, format!("invalid value: {}", s))),
+        }
+    }
+}
@@ -1,41 +1,133 @@
+use std::borrow::Cow;
+use std::env;
 use std::error::Error as StdError;
 use std::fmt;
 use std::path::Path;
+use std::path::PathBuf;
 use std::io::Error as IOError;
+use std::io::ErrorKind as IOErrorKind;
 use std::io::BufRead;
 use std::io::BufReader;
 use std::str::FromStr;
+use std::time::Duration;
 
 
-#[derive(Debug)]
+/// what is wrong with a configuration value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldParseErrorKind {
+    /// `key` without `= value`
+    Missing,
+    /// the value does not parse as the type of the key
+    Unparsable,
+    /// the value parses, but is rejected by the control, e.g. a `start_temperature` lower than `stop_temperature`
+    OutOfRange,
+    /// the key matches no known field; `reason` holds the key and its section
+    Unknown,
+}
+
+#[derive(Debug, Clone)]
 pub struct FieldParseError {
     field: &'static str,
-    missing: bool,
+    kind: FieldParseErrorKind,
+    reason: String,
+    file: PathBuf,
+    line: usize,
 }
 
 impl FieldParseError {
+
+    pub fn new(field: &'static str, kind: FieldParseErrorKind) -> Self {
+        Self { field, kind, reason: String::new(), file: PathBuf::new(), line: 0 }
+    }
+
+    /// a value of `field` that parsed but is not allowed, e.g. from a `ParameterError`
+    pub fn out_of_range(field: &'static str, reason: impl Into<String>) -> Self {
+        Self { reason: reason.into(), ..Self::new(field, FieldParseErrorKind::OutOfRange) }
+    }
+
+    /// `key` of `section` matches no known field; reported under `field`, the key that makes it an error
+    pub fn unknown(field: &'static str, section: &str, key: &str) -> Self {
+        let reason = if section.is_empty() { format!("`{}`", key) } else { format!("`{}` in [{}]", key, section) };
+        Self { reason, ..Self::new(field, FieldParseErrorKind::Unknown) }
+    }
+
+    pub fn kind(&self) -> FieldParseErrorKind {
+        self.kind
+    }
+
+    pub fn field(&self) -> &'static str {
+        self.field
+    }
+
+    /// sets where the value came from; `line` 0 means no line, e.g. the command line
+    pub fn at(mut self, file: &Path, line: usize) -> Self {
+        self.file = file.to_path_buf();
+        self.line = line;
+        self
+    }
     
     pub fn parse<'a>(s: Option<&'a str>, field: &'static str) -> Result<&'a str, Self> {
-        s.ok_or_else(|| Self { field, missing: true })
+        s.ok_or(Self::new(field, FieldParseErrorKind::Missing))
     }
 
-    pub fn parse_value<'a, T>(s: Option<&'a str>, field: &'static str) -> Result<T, Self> 
+    pub fn parse_value<T>(s: Option<&str>, field: &'static str) -> Result<T, Self> 
     where 
         T: FromStr, 
         <T as FromStr>::Err: std::error::Error
     {
         let s = Self::parse(s, field)?;
-        s.parse().map_err(|_e| Self { field, missing: false })
+        s.parse().map_err(|_e| Self::new(field, FieldParseErrorKind::Unparsable))
+    }
+
+    /// a fraction like `0.5`, or a percentage like `50%`
+    pub fn parse_fraction(s: Option<&str>, field: &'static str) -> Result<f32, Self> {
+        let s = Self::parse(s, field)?.trim();
+        match s.strip_suffix('%') {
+            Some(percent) => percent.trim_end().parse::<f32>().map(|p| p / 100.0).map_err(|_e| Self::new(field, FieldParseErrorKind::Unparsable)),
+            None => s.parse().map_err(|_e| Self::new(field, FieldParseErrorKind::Unparsable)),
+        }
+    }
+
+    /// a duration like `5s`, `500ms`, `1.5m` or `1h`; a bare number is milliseconds, see `is_bare_duration`
+    pub fn parse_duration(s: Option<&str>, field: &'static str) -> Result<Duration, Self> {
+        let s = Self::parse(s, field)?.trim();
+        if let Ok(millis) = s.parse::<u64>() {
+            return Ok(Duration::from_millis(millis));
+        }
+        let split = s.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(s.len());
+        let (number, unit) = s.split_at(split);
+        let scale = match unit {
+            "ms" => 0.001,
+            "s" => 1.0,
+            "m" => 60.0,
+            "h" => 3600.0,
+            _ => return Err(Self::new(field, FieldParseErrorKind::Unparsable)),
+        };
+        let number: f64 = number.trim_end().parse().map_err(|_e| Self::new(field, FieldParseErrorKind::Unparsable))?;
+        Duration::try_from_secs_f64(number * scale).map_err(|_e| Self::new(field, FieldParseErrorKind::Unparsable))
+    }
+
+    /// whether a duration is written without a unit, the older form in milliseconds
+    pub fn is_bare_duration(s: &str) -> bool {
+        s.trim().parse::<u64>().is_ok()
     }
 }
 
 impl fmt::Display for FieldParseError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "FieldParseError: field '{}'", self.field)?;
-        if self.missing {
-            write!(f, " is missing")?;
+        if !self.file.as_os_str().is_empty() {
+            write!(f, "{}:", self.file.display())?;
+            if self.line > 0 {
+                write!(f, "{}:", self.line)?;
+            }
+            write!(f, " ")?;
+        }
+        match self.kind {
+            FieldParseErrorKind::Missing => write!(f, "missing value for {}", self.field),
+            FieldParseErrorKind::Unparsable => write!(f, "cannot parse value for {}", self.field),
+            FieldParseErrorKind::OutOfRange => write!(f, "value out of range for {}: {}", self.field, self.reason),
+            FieldParseErrorKind::Unknown => write!(f, "unknown key {}", self.reason),
         }
-        Ok(())
     }
 }
 
@@ -43,16 +135,19 @@ impl StdError for FieldParseError {
 
 }
 
-impl Into<IOError> for FieldParseError {
+impl From<FieldParseError> for IOError {
 
-    fn into(self) -> IOError {
-        IOError::new(std::io::ErrorKind::InvalidData, self)
+    fn from(e: FieldParseError) -> Self {
+        IOError::new(std::io::ErrorKind::InvalidData, e)
     }
 }
 
 
 
 
+/// deepest nesting of `include`, as a backstop besides the cycle check
+pub const MAX_INCLUDE_DEPTH: usize = 8;
+
 /// inspired by https://crates.io/crates/cini
 pub trait Ini {
     /// The associated error which can be returned from parsing.
@@ -69,17 +164,20 @@ pub trait Ini {
         value: Option<&str>
     ) -> Result<(), Self::Err>;
 
-    /// Parses a single line of an ini str.
-    fn parse_line<'a>(
+    /// Parses a single line of an ini str; `${VAR}` in values is expanded from the environment.
+    /// `include = FILE` parses FILE at that point, relative to the directory of `filename`;
+    /// `includes` are the files being parsed, outermost first.
+    fn parse_line(
         &mut self,
         filename: &Path,
-        line: &'a str,
+        line: &str,
         line_number: usize,
         mut section: String,
-    ) -> Result<String, Self::Err> {
+        includes: &mut Vec<PathBuf>,
+    ) -> Result<String, IOError> {
         let line = line.trim();
 
-        if line.is_empty() || line.starts_with('#') {
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
             return Ok(section);
         }
 
@@ -87,33 +185,149 @@ pub trait Ini {
             let header = &line[1..line.len() - 1];
             section = String::from(header);
         } else {
-            let pair = split_pair(line);
-            self.callback(filename, line, line_number, section.as_str(), pair.0, pair.1)?;
+            let (key, value) = split_pair(line);
+            let value = match value {
+                Some(value) => Some(
+                    expand(value).map_err(|e| IOError::new(IOErrorKind::InvalidData, format!("{}:{}: {}", filename.display(), line_number, e)))?
+                ),
+                None => None,
+            };
+            if key == "include" {
+                let include = FieldParseError::parse(value.as_deref(), "include").map_err(|e| e.at(filename, line_number))?;
+                let path = filename.parent().unwrap_or(Path::new("")).join(include);
+                // the included file starts in the current section; its section headers end with it
+                self.parse_file(&path, section.clone(), includes)
+                    .map_err(|e| IOError::new(e.kind(), format!("{}:{}: {}", filename.display(), line_number, e)))?;
+            } else {
+                self.callback(filename, line, line_number, section.as_str(), key, value.as_deref()).map_err(Self::Err::into)?;
+            }
         }
         Ok(section)
     }
 
     /// Parses an ini file.
     fn parse_from_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), IOError> {
-        let path = path.as_ref();
-        let ifile = std::fs::File::open(path)?;
+        self.parse_file(path.as_ref(), String::new(), &mut Vec::new())
+    }
+
+    /// Parses an ini file starting in `section`, as the innermost of `includes`.
+    fn parse_file(&mut self, path: &Path, mut section: String, includes: &mut Vec<PathBuf>) -> Result<(), IOError> {
+        let ifile = std::fs::File::open(path).map_err(|e| IOError::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        let canonical = path.canonicalize()?;
+        if includes.contains(&canonical) {
+            let chain: Vec<String> = includes.iter().chain([&canonical]).map(|p| p.display().to_string()).collect();
+            return Err(IOError::new(IOErrorKind::InvalidData, format!("include cycle: {}", chain.join(" -> "))));
+        }
+        if includes.len() > MAX_INCLUDE_DEPTH {
+            return Err(IOError::new(IOErrorKind::InvalidData, format!("{}: includes nested deeper than {}", path.display(), MAX_INCLUDE_DEPTH)));
+        }
+        includes.push(canonical);
         let mut reader = BufReader::new(ifile);
-        let mut section = String::new();
         let mut line = String::new();
         let mut line_number = 0;
         while reader.read_line(&mut line)? > 0 {
             line_number += 1;
-            section = self.parse_line(path, line.as_str(), line_number, section).map_err(Self::Err::into)?;
+            section = self.parse_line(path, line.as_str(), line_number, section, includes)?;
             line.clear();
         }
+        includes.pop();
         Ok(())
     }
 }
 
 fn split_pair(s: &str) -> (&str, Option<&str>) {
-    let mut split = s.splitn(2, '=');
-    (
-        split.next().unwrap().trim_end(),
-        split.next().map(|s| s.trim_start()),
-    )
-}
\ No newline at end of file
+    match s.split_once('=') {
+        Some((key, value)) => (key.trim_end(), Some(parse_value(value))),
+        // `interval 2000` reports a missing value for `interval` instead of an unknown key being ignored
+        None => (s.split_whitespace().next().unwrap_or(s), None),
+    }
+}
+
+/// strips matching single or double quotes, keeping everything inside them;
+/// an unquoted value ends at the first `#` or `;`
+fn parse_value(s: &str) -> &str {
+    let s = s.trim();
+    for quote in ['"', '\''] {
+        if let Some(rest) = s.strip_prefix(quote) {
+            if let Some(end) = rest.find(quote) {
+                return &rest[..end];
+            }
+        }
+    }
+    match s.find(['#', ';']) {
+        Some(end) => s[..end].trim_end(),
+        None => s,
+    }
+}
+
+/// expands `${VAR}` from the process environment; `$$` is a literal `$`
+pub fn expand(s: &str) -> Result<Cow<'_, str>, String> {
+    if !s.contains('$') {
+        return Ok(Cow::Borrowed(s));
+    }
+    let mut expanded = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find('$') {
+        expanded.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+        if let Some(after) = rest.strip_prefix('$') {
+            expanded.push('$');
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix('{') {
+            let end = after.find('}').ok_or_else(|| format!("unterminated `${{` in `{}`", s))?;
+            let name = &after[..end];
+            let value = env::var(name).map_err(|_e| format!("environment variable `{}` is not set", name))?;
+            expanded.push_str(&value);
+            rest = &after[end + 1..];
+        } else {
+            expanded.push('$');
+        }
+    }
+    expanded.push_str(rest);
+    Ok(Cow::Owned(expanded))
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn fraction_or_percentage() {
+        assert_eq!(FieldParseError::parse_fraction(Some("0.9"), "max_duty_cycle").unwrap(), 0.9);
+        assert_eq!(FieldParseError::parse_fraction(Some("90%"), "max_duty_cycle").unwrap(), 0.9);
+        assert_eq!(FieldParseError::parse_fraction(Some(" 100 % "), "max_duty_cycle").unwrap(), 1.0);
+        assert_eq!(FieldParseError::parse_fraction(Some("1"), "max_duty_cycle").unwrap(), 1.0);
+        assert_eq!(FieldParseError::parse_fraction(Some("ninety%"), "max_duty_cycle").unwrap_err().kind(), FieldParseErrorKind::Unparsable);
+        assert_eq!(FieldParseError::parse_fraction(Some("%"), "max_duty_cycle").unwrap_err().kind(), FieldParseErrorKind::Unparsable);
+        assert_eq!(FieldParseError::parse_fraction(None, "max_duty_cycle").unwrap_err().kind(), FieldParseErrorKind::Missing);
+    }
+    #[test]
+    fn duration_with_units() {
+        let parse = |s| FieldParseError::parse_duration(Some(s), "interval");
+        assert_eq!(parse("5s").unwrap(), Duration::from_secs(5));
+        assert_eq!(parse("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse("2m").unwrap(), Duration::from_secs(120));
+        assert_eq!(parse("1.5s").unwrap(), Duration::from_millis(1500));
+        assert_eq!(parse("1h").unwrap(), Duration::from_secs(3600));
+        assert_eq!(parse(" 5 s ").unwrap(), Duration::from_secs(5));
+        // the older form, in milliseconds
+        assert_eq!(parse("5000").unwrap(), Duration::from_secs(5));
+        assert!(FieldParseError::is_bare_duration("5000"));
+        assert!(!FieldParseError::is_bare_duration("5s"));
+        for invalid in ["5x", "s", "-5s", "5 sec", ""] {
+            assert_eq!(parse(invalid).unwrap_err().kind(), FieldParseErrorKind::Unparsable, "{}", invalid);
+        }
+    }
+    #[test]
+    fn quoted_values_and_inline_comments() {
+        assert_eq!(split_pair(r#"watch = "/sys/class/my zone/temp" # zone0"#), ("watch", Some("/sys/class/my zone/temp")));
+        assert_eq!(split_pair("execute = '/sys/pwm #1 ; a'"), ("execute", Some("/sys/pwm #1 ; a")));
+        assert_eq!(split_pair("interval = 5s # every 5 seconds"), ("interval", Some("5s")));
+        assert_eq!(split_pair("interval = 5s; every 5 seconds"), ("interval", Some("5s")));
+        assert_eq!(split_pair("interval = \"  5s  \""), ("interval", Some("  5s  ")));
+        // an unmatched quote is kept as it is
+        assert_eq!(split_pair("watch = \"/sys/zone"), ("watch", Some("\"/sys/zone")));
+    }
+}
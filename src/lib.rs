@@ -0,0 +1,44 @@
+//! Control logic of the `fanctrl` daemon, usable without the binary:
+//!
+//! - `control`: temperature rules (`Function`, `Curve`), the hysteresis state machine `Control` and the PID regulator
+//! - `ini`: the `Ini` callback parser behind the configuration file
+//! - `sensor`: `SensorSource` and the sysfs thermal zone and cooling device readers
+//! - `pwm`: `FanOutput` and the sysfs pwm writer
+//! - `gpio`: `GpioFanDevice`, a `FanOutput` switching a fan on a gpio line
+//! - `cooling`: `CoolingDeviceOutput`, a `FanOutput` writing the `cur_state` of a thermal cooling device
+//! - `sysfs`: the `Sysfs` file access of both, real or in memory (`MemorySysfs`) for tests
+//! - `app`: the daemon itself, `Application` running every `Fan` of an `Args` configuration, and the `Fan::tick`
+//!   policy of one cycle
+//! - `event`: `FanEvent`, the fan state transitions an embedding process can receive
+//! - `async_loop`: with the `tokio` feature, `run_loop` driving an `Application` in a tokio runtime
+
+pub mod app;
+pub mod cli;
+pub mod control;
+pub mod cooling;
+pub mod event;
+pub mod gpio;
+pub mod ini;
+pub mod logger;
+pub mod pwm;
+pub mod sensor;
+pub mod signal;
+pub mod sysfs;
+#[cfg(feature = "systemd")]
+pub mod notify;
+#[cfg(feature = "tokio")]
+pub mod async_loop;
+
+mod pidfile;
+mod replay;
+mod state;
+mod simulate;
+mod status;
+mod unit;
+mod watchdog;
+#[cfg(feature = "metrics")]
+mod metrics;
+#[cfg(feature = "dbus")]
+mod dbus;
+#[cfg(feature = "toml")]
+mod toml_file;
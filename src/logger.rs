@@ -0,0 +1,239 @@
+use std::ffi::OsString;
+use std::fmt::Write as _;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Error as IOError;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use log::kv;
+use log::kv::VisitSource;
+use log::Log;
+use log::Metadata;
+use log::Record;
+use log::SetLoggerError;
+use simple_logger::SimpleLogger;
+
+
+/// file set by `open_file`, written by every thread after the console
+static FILE: Mutex<Option<LogFile>> = Mutex::new(None);
+
+/// set by `set_format`, for the console and the log file alike
+static JSON: AtomicBool = AtomicBool::new(false);
+
+
+/// how each record is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// the console's free text, e.g. `2024-01-31T21:05:09.123+08:00 INFO  [fanctrl] fan started at ...`
+    #[default]
+    Text,
+    /// one JSON object per line, see `json_line`
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown log format: {}", s))),
+        }
+    }
+}
+
+/// switches the console and the log file to `format` for the following records
+pub fn set_format(format: LogFormat) {
+    JSON.store(format == LogFormat::Json, Ordering::Relaxed);
+}
+
+
+/// the console logger, copying each record to the log file once one is opened
+struct Logger {
+    console: SimpleLogger,
+}
+
+impl Log for Logger {
+
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.console.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        let json = JSON.load(Ordering::Relaxed);
+        if !json {
+            self.console.log(record);
+        }
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let json_line = json.then(|| json_line(record));
+        if let Some(line) = json_line.as_ref() {
+            let _ = io::stdout().lock().write_all(line.as_bytes());
+        }
+        if let Ok(mut guard) = FILE.lock() {
+            if let Some(file) = guard.as_mut() {
+                let line = json_line.unwrap_or_else(|| format!("{} {:<5} [{}] {}\n", timestamp(), record.level(), record.target(), record.args()));
+                // logging from here would deadlock on `FILE`
+                if let Err(e) = file.write(&line) {
+                    eprintln!("failed to write log file {}: {}", file.path.display(), e);
+                }
+            }
+        }
+    }
+
+    fn flush(&self) {
+        self.console.flush();
+        let _ = io::stdout().flush();
+        if let Ok(mut guard) = FILE.lock() {
+            if let Some(file) = guard.as_mut() {
+                let _ = file.file.flush();
+            }
+        }
+    }
+}
+
+/// installs `console` as the logger, keeping its level
+pub fn init(console: SimpleLogger) -> Result<(), SetLoggerError> {
+    log::set_max_level(console.max_level());
+    log::set_boxed_logger(Box::new(Logger { console }))
+}
+
+/// also appends every record to `path`; see `LogFile` for `max_bytes`
+pub fn open_file(path: impl AsRef<Path>, max_bytes: u64) -> Result<(), IOError> {
+    let file = LogFile::open(path.as_ref(), max_bytes)?;
+    if let Ok(mut guard) = FILE.lock() {
+        *guard = Some(file);
+    }
+    Ok(())
+}
+
+
+/// appended log file; once a record would grow it past `max_bytes` it is renamed to `PATH.1`,
+/// replacing the previous one, and a new file is started; 0 never rotates
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    len: u64,
+    max_bytes: u64,
+}
+
+impl LogFile {
+
+    fn open(path: &Path, max_bytes: u64) -> Result<Self, IOError> {
+        let file = File::options().create(true).append(true).open(path)?;
+        let len = file.metadata()?.len();
+        Ok(LogFile { path: path.to_path_buf(), file, len, max_bytes })
+    }
+
+    fn write(&mut self, line: &str) -> Result<(), IOError> {
+        if self.max_bytes > 0 && self.len > 0 && self.len + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.len += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> Result<(), IOError> {
+        let mut rotated = OsString::from(self.path.as_os_str());
+        rotated.push(".1");
+        fs::rename(&self.path, &rotated)?;
+        self.file = File::options().create(true).append(true).open(&self.path)?;
+        self.len = 0;
+        Ok(())
+    }
+}
+
+
+/// `record` as one line of JSON: `ts` and `level`, the key-values of the record, like `temp`, `duty` and
+/// `state` of the control loop, then `msg`, e.g.
+/// `{"ts":"2024-01-31T21:05:09.123+08:00","level":"INFO","temp":52.3,"duty":0.65,"state":"function","msg":"fan started at 52.30°C with pwm-duty-ratio=65.00%"}`
+fn json_line(record: &Record) -> String {
+    let mut line = String::from("{\"ts\":");
+    push_json_str(&mut line, &timestamp());
+    line.push_str(",\"level\":");
+    push_json_str(&mut line, record.level().as_str());
+    let _ = record.key_values().visit(&mut JsonFields(&mut line));
+    line.push_str(",\"msg\":");
+    push_json_str(&mut line, &record.args().to_string());
+    line.push_str("}\n");
+    line
+}
+
+/// appends each key-value as `,"key":value`
+struct JsonFields<'a>(&'a mut String);
+
+impl<'kvs> VisitSource<'kvs> for JsonFields<'_> {
+
+    /// numbers and booleans as themselves, a non-finite number as `null` and anything else as its display string
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        self.0.push(',');
+        push_json_str(self.0, key.as_str());
+        self.0.push(':');
+        if let Some(v) = value.to_bool() {
+            let _ = write!(self.0, "{}", v);
+        } else if let Some(v) = value.to_i64() {
+            let _ = write!(self.0, "{}", v);
+        } else if let Some(v) = value.to_u64() {
+            let _ = write!(self.0, "{}", v);
+        } else if let Some(v) = value.to_f64() {
+            if !v.is_finite() {
+                self.0.push_str("null");
+            } else if (v as f32) as f64 == v {
+                // an `f32` widened to `f64` would print like 52.29999923706055
+                let _ = write!(self.0, "{}", v as f32);
+            } else {
+                let _ = write!(self.0, "{}", v);
+            }
+        } else {
+            push_json_str(self.0, &value.to_string());
+        }
+        Ok(())
+    }
+}
+
+fn push_json_str(line: &mut String, s: &str) {
+    line.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => line.push_str("\\\""),
+            '\\' => line.push_str("\\\\"),
+            '\n' => line.push_str("\\n"),
+            '\r' => line.push_str("\\r"),
+            '\t' => line.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(line, "\\u{:04x}", c as u32);
+            }
+            c => line.push(c),
+        }
+    }
+    line.push('"');
+}
+
+
+/// local time like the console, e.g. `2024-01-31T21:05:09.123+08:00`
+fn timestamp() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = now.as_secs() as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&secs, &mut tm) }.is_null() {
+        return format!("{}.{:03}", now.as_secs(), now.subsec_millis());
+    }
+    let offset = tm.tm_gmtoff / 60;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}{}{:02}:{:02}",
+        tm.tm_year + 1900, tm.tm_mon + 1, tm.tm_mday, tm.tm_hour, tm.tm_min, tm.tm_sec, now.subsec_millis(),
+        if offset < 0 { '-' } else { '+' }, offset.abs() / 60, offset.abs() % 60
+    )
+}
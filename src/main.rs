@@ -1,339 +1,278 @@
 use std::env;
-use std::io;
-use std::path::PathBuf;
 use std::process;
-use std::time::Duration; 
 
-use control::Control;
-use control::ControlOutput;
-use control::Function;
-use ini::FieldParseError;
-use ini::Ini;
-use pwm::PWMDevice;
-use pwm::Polarity;
-use sensor::SensorDevice;
-
-mod signal;
-mod sensor;
-mod pwm;
-mod control;
-mod ini;
-
-
-#[derive(Debug)]
-struct Args {
-
-    /// Path to the sensor device; like "/sys/class/thermal/thermal_zone0"
-    watch: PathBuf,
-
-    /// Path to the pwm device; like "/sys/devices/platform/fd8b0010.pwm/pwm/pwmchip1"
-    execute: PathBuf,
-
-    /// Interval between temperature checks, in milliseconds
-    interval: u64,
-
-    /// Time before the pwm change when temperature drop, in times of interval
-    max_speed_time_cycle: usize,
-
-    /// Time before the pwm change when temperature drop, in times of interval
-    lag_time_cycle: usize,
-
-    /// Temperature to stop the pwm, in degrees Celsius
-    stop_temperature: f32,
-
-    /// Temperature to start the pwm, in degrees Celsius
-    start_temperature: f32,
-
-    /// Temperature when pwm should reach maximum, in degrees Celsius
-    high_temperature: f32,
-
-    /// Minimum duty cycle, in (0, 1)
-    min_duty_cycle: f32,
-
-    /// Maximum duty cycle, in (0, 1)
-    max_duty_cycle: f32,
-
-    /// PWM frequency, in Hz
-    pwm_frequency: u32,
+use fanctrl::app;
+use fanctrl::cli;
+use fanctrl::logger;
+#[cfg(feature = "systemd")]
+use fanctrl::notify;
+#[cfg(not(feature = "tokio"))]
+use fanctrl::signal;
+#[cfg(feature = "tokio")]
+use fanctrl::async_loop;
+
+use app::Application;
+use app::Args;
+#[cfg(not(feature = "tokio"))]
+use app::SignalCommand;
+use cli::Command;
+
+
+fn print_usage() {
+    println!("Usage:  {} [OPTIONS] [CONFIGURATION_FILE]", env!("CARGO_BIN_NAME"));
+    println!();
+    println!("Options:");
+    println!("    -c, --config <FILE>    configuration file [default: fanctrl.conf]");
+    println!("    --observe              read the sensor but only log the pwm writes");
+    println!("    --replay <TRACE>       run a recorded trace against mock devices and diff the pwm writes");
+    println!("    --plot                 print the duty cycle of the curve for each degree as CSV");
+    println!("    --simulate <PROFILE>   run a seconds,temperature CSV through the control and print the duty cycles");
+    println!("    --check                validate the configuration, sensor and pwm paths without starting the fan");
+    println!("    --once                 run one cycle resumed from state_file, leave the fans running and exit");
+    println!("    --dry-run              log the pwm writes without touching sysfs (`--dry-run=false` to override the file)");
+    println!("    --<KEY> <VALUE>        override a configuration key, e.g. --max-duty-cycle 0.8");
+    println!("    -h, --help             print help");
+    println!("    -v, --version          print version");
+    println!();
+    println!("Keys:");
+    for key in Args::KEYS {
+        println!("    --{}", key.replace('_', "-"));
+    }
 }
 
 
-impl Default for Args {
-    fn default() -> Self {
-        Self {
-            watch: PathBuf::new(),
-            execute: PathBuf::new(),
-            interval: 5000,
-            max_speed_time_cycle: 32,
-            lag_time_cycle: 8,
-            stop_temperature: 30.0,
-            start_temperature: 40.0,
-            high_temperature: 70.0,
-            min_duty_cycle: 0.5,
-            max_duty_cycle: 0.9,
-            pwm_frequency: 10000,
-        }
+/// `RUST_LOG` in the env_logger form `[target=]level,...`, e.g. `fanctrl=debug,warn`; see `parse_log_level`
+fn get_log_level() -> (log::LevelFilter, Vec<(String, log::LevelFilter)>) {
+    match std::env::var("RUST_LOG") {
+        Ok(s) => parse_log_level(s.as_str()),
+        Err(_e) => (log::LevelFilter::Info, Vec::new()),
     }
 }
 
-impl Ini for Args {
-    type Err = FieldParseError;
-
-    fn callback(
-        &mut self, 
-        filename: &std::path::Path, 
-        line: &str, 
-        line_number: usize, 
-        section: &str, 
-        key: &str, 
-        value: Option<&str>
-    ) -> Result<(), Self::Err> {
-        if section.is_empty() {
-            match key {
-                "watch" => self.watch = PathBuf::from(FieldParseError::parse(value, "watch")?),
-                "execute" => self.execute = PathBuf::from(FieldParseError::parse(value, "execute")?),
-                "interval" => self.interval = FieldParseError::parse_value(value, "interval")?,
-                "max_speed_time_cycle" => self.max_speed_time_cycle = FieldParseError::parse_value(value, "max_speed_time_cycle")?,
-                "lag_time_cycle" => self.lag_time_cycle = FieldParseError::parse_value(value, "lag_time_cycle")?,
-                "stop_temperature" => self.stop_temperature = FieldParseError::parse_value(value, "stop_temperature")?,
-                "start_temperature" => self.start_temperature = FieldParseError::parse_value(value, "start_temperature")?,
-                "high_temperature" => self.high_temperature = FieldParseError::parse_value(value, "high_temperature")?,
-                "min_duty_cycle" => self.min_duty_cycle = FieldParseError::parse_value(value, "min_duty_cycle")?,
-                "max_duty_cycle" => self.max_duty_cycle = FieldParseError::parse_value(value, "max_duty_cycle")?,
-                "pwm_frequency" => self.pwm_frequency = FieldParseError::parse_value(value, "pwm_frequency")?,
-                _ => {}
-            }
+/// the default level and the levels of targets in this crate (`fanctrl`, `fanctrl::control`, ...);
+/// a bare level sets the default, a bare target means `trace`, other targets and invalid directives are ignored
+fn parse_log_level(s: &str) -> (log::LevelFilter, Vec<(String, log::LevelFilter)>) {
+    let mut default = log::LevelFilter::Info;
+    let mut targets = Vec::new();
+    for directive in s.split(',').map(str::trim).filter(|directive| !directive.is_empty()) {
+        let (target, level) = match directive.split_once('=') {
+            Some((target, level)) => match level.trim().parse() {
+                Ok(level) => (target.trim(), level),
+                Err(_e) => continue,
+            },
+            None => match directive.parse() {
+                Ok(level) => {
+                    default = level;
+                    continue;
+                }
+                Err(_e) => (directive, log::LevelFilter::Trace),
+            },
+        };
+        if target.split("::").next() == Some(env!("CARGO_CRATE_NAME")) {
+            targets.push((String::from(target), level));
         }
-        Ok(())
     }
+    (default, targets)
 }
 
+fn main() {
 
-struct Application {
-    sensor: SensorDevice,
-    pwm: PWMDevice,
-    frequency: u32,
-    on: bool,
-    control: Control,
-    interval: Duration,
-    max_speed_time_cycle: usize,
-    max_speed_remaining_cycle: usize,
-}
+    let (level, targets) = get_log_level();
+    let console = targets
+        .iter()
+        .fold(simple_logger::SimpleLogger::new().with_level(level), |console, (target, level)| console.with_module_level(target, *level));
 
-impl Application {
+    #[cfg(feature = "betterlog")]
+    let console = console.with_local_timestamps();
 
-    pub fn new_from_config(config: &str) -> io::Result<Self> {
-        let mut args = Args::default();
-        args.parse_from_file(config)?;
-        Self::new(args)
-    }
+    logger::init(console).unwrap();
 
-    pub fn new(args: Args) -> io::Result<Self> {
-        let sensor = SensorDevice::new(args.watch.as_path())?;
-        log::info!("sensor initialized: path={}", args.watch.as_path().display());
-        let instance = 0;
-        let pwm = PWMDevice::new(args.execute.as_path(), instance)?;
-        log::info!("pwm initialized: path={}/pwm{}, pwm_frequency={}", args.execute.as_path().display(), instance, args.pwm_frequency);
-        let f = Function::new(
-            args.stop_temperature,
-            args.start_temperature,
-            args.high_temperature,
-            args.min_duty_cycle,
-            args.max_duty_cycle,
-        )
-        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-        log::info!("control initialized: function={}", &f);
-        let control = Control::new(f, args.lag_time_cycle);
-        log::info!("control initialized: interval={}ms, lag_time_cycle={}, max_speed_time_cycle={}",args.interval, args.lag_time_cycle, args.max_speed_time_cycle);
-        Ok(
-            Self {
-                sensor,
-                pwm,
-                frequency: args.pwm_frequency,
-                on: false,
-                control,
-                interval: Duration::from_millis(args.interval),
-                max_speed_time_cycle: args.max_speed_time_cycle,
-                max_speed_remaining_cycle: 0,
+    let mut app = {
+        let cmd = match cli::parse(env::args().skip(1), Args::KEYS) {
+            Ok(Command::Run(cmd)) => cmd,
+            Ok(Command::Help) => {
+                println!("{} {}", env!("CARGO_BIN_NAME"), env!("CARGO_PKG_VERSION"));
+                println!("{}", env!("CARGO_PKG_DESCRIPTION"));
+                println!();
+                print_usage();
+                process::exit(0);
             }
-        )
-    }
-
-    pub fn initial(&mut self) -> io::Result<()> {
-        self.pwm.set_period(self.frequency)?;
-        self.pwm.set_polarity(Polarity::Normal)?;
-        log::info!("fan initialized: frequency={}Hz, polarity={}", self.frequency, Polarity::Normal);
-        let temperature = self.sensor.get()?;
-        let output = self.control.update_force(temperature, self.control.min_duty_cycle());
-        log::trace!("control status: temperature={:.2}°C, output={:?}", temperature, output);
-        match output {
-            ControlOutput::Off | ControlOutput::Keep => {
-                unreachable!()
+            Ok(Command::Version) => {
+                println!("{} {}", env!("CARGO_BIN_NAME"), env!("CARGO_PKG_VERSION"));
+                process::exit(0);
+            }
+            Err(e) => {
+                eprintln!("error: {}", e);
+                eprintln!();
+                print_usage();
+                process::exit(2);
             }
-            ControlOutput::Change(duty_cycle) => {
-                if self.start_pwm(duty_cycle)? {
-                    log::info!("fan launched at {:.2}°C with pwm-duty-ratio={:.2}%", temperature, duty_cycle * 100.0);
+        };
+        if cmd.check {
+            match app::check(&cmd) {
+                Ok(()) => process::exit(0),
+                Err(e) => {
+                    eprintln!("check failed: {}", e);
+                    process::exit(1);
                 }
             }
         }
-        Ok(())
-    }
-
-    pub fn run(&mut self) -> io::Result<()> {
-        if self.max_speed_remaining_cycle > 0 {
-            self.max_speed_remaining_cycle -= 1;
-        } else {
-            let temperature = self.sensor.get()?;
-            let output = self.control.update(temperature);
-            log::trace!("control status: temperature={:.2}°C, output={:?}", temperature, output);
-            match output {
-                ControlOutput::Off => {
-                    if self.stop_pwm()? {
-                        log::info!("fan stopped at {:.2}°C", temperature);
-                    }
+        if cmd.plot {
+            match app::plot(&cmd) {
+                Ok(()) => process::exit(0),
+                Err(e) => {
+                    eprintln!("plot failed: {}", e);
+                    process::exit(1);
                 }
-                ControlOutput::Change(duty_cycle) => {
-                    if self.start_pwm(duty_cycle)? {
-                        log::info!("fan started at {:.2}°C with pwm-duty-ratio={:.2}%", temperature, duty_cycle * 100.0);
-                    } else {
-                        log::debug!("fan changed at {:.2}°C with pwm-duty-ratio={:.2}%", temperature, duty_cycle * 100.0);
-                    }
+            }
+        }
+        if let Some(profile) = cmd.simulate.as_ref() {
+            match app::simulate(&cmd, profile) {
+                Ok(()) => process::exit(0),
+                Err(e) => {
+                    eprintln!("simulate failed: {}", e);
+                    process::exit(1);
                 }
-                ControlOutput::Keep => {
-                    // do nothing
+            }
+        }
+        if cmd.once {
+            match app::once(&cmd) {
+                Ok(()) => process::exit(0),
+                Err(e) => {
+                    log::error!("failed to run once: {}", e);
+                    process::exit(1);
                 }
             }
         }
-        Ok(())
-    }
+        if let Some(trace) = cmd.replay.as_ref() {
+            match app::replay(&cmd, trace) {
+                Ok(0) => process::exit(0),
+                Ok(_) => process::exit(1),
+                Err(e) => {
+                    log::error!("failed to replay trace: {}", e);
+                    process::exit(1);
+                }
+            }
+        }
+        match Application::new_from_command_line(&cmd) {
+            Ok(app) => app,
+            Err(e) => {
+                log::error!("failed to create application: {}", e);
+                process::exit(1);
+            }
+        }
+    };
 
-    pub fn run_max_speed(&mut self) -> io::Result<()> {
-        let duty_cycle = self.control.max_duty_cycle();
-        self.start_pwm(duty_cycle)?;
-        self.max_speed_remaining_cycle = self.max_speed_time_cycle;
-        log::info!("fan set to maximum speed for {} cycles with pwm-duty-ratio={:.2}%", self.max_speed_time_cycle, duty_cycle * 100.0);
-        Ok(())
-    }
+    #[cfg(not(feature = "tokio"))]
+    unsafe { signal::register(&app.signal_table().iter().map(|(signal, _)| signal.0).collect::<Vec<_>>()) };
 
-    pub fn terminate(&mut self) -> io::Result<()> {
-        self.stop_pwm()?;
-        log::info!("fan terminated");
-        Ok(())
+    if let Err(e) = app.initial() {
+        log::error!("failed to initialize: {:?}", e);
+        process::exit(1);
     }
 
-    fn stop_pwm(&mut self) -> io::Result<bool> {
-        if self.on {
-            self.pwm.set_enable(false)?;
-            self.on = false;
-            Ok(true)
-        } else {
-            Ok(false)
+    #[cfg(feature = "systemd")]
+    let notifier = match notify::Notifier::from_env() {
+        Ok(notifier) => notifier,
+        Err(e) => {
+            log::warn!("failed to connect to systemd notify socket: {:?}", e);
+            None
         }
-    }
-
-    fn start_pwm(&mut self, duty_cycle: f32) -> io::Result<bool> {
-        self.pwm.set_duty_cycle((duty_cycle * self.frequency as f32) as u32)?;
-        if !self.on {
-            self.pwm.set_enable(true)?;
-            self.on = true;
-            Ok(true)
-        } else {
-            Ok(false)
+    };
+    #[cfg(feature = "systemd")]
+    if let Some(notifier) = notifier.as_ref() {
+        if let Err(e) = notifier.ready() {
+            log::warn!("failed to notify systemd: {:?}", e);
         }
     }
-}
 
-
-fn get_log_level() -> log::LevelFilter {
-    match std::env::var("RUST_LOG") {
-        Ok(s) => {
-            match s.to_lowercase().as_str() {
-                "error" => log::LevelFilter::Error,
-                "warn" => log::LevelFilter::Warn,
-                "info" => log::LevelFilter::Info,
-                "debug" => log::LevelFilter::Debug,
-                "trace" => log::LevelFilter::Trace,
-                _ => log::LevelFilter::Info,
+    #[cfg(feature = "tokio")]
+    {
+        let runtime = match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                log::error!("failed to start tokio runtime: {:?}", e);
+                process::exit(1);
             }
+        };
+        #[cfg(feature = "systemd")]
+        let notifier = notifier.map(std::sync::Arc::new);
+        #[cfg(feature = "systemd")]
+        if let Some(notifier) = notifier.clone() {
+            let period = app.next_interval();
+            runtime.spawn(async move {
+                let mut ticker = tokio::time::interval(period);
+                loop {
+                    ticker.tick().await;
+                    if let Err(e) = notifier.watchdog() {
+                        log::warn!("failed to notify systemd watchdog: {:?}", e);
+                    }
+                }
+            });
+        }
+        if let Err(e) = runtime.block_on(async_loop::run_loop(app, tokio_util::sync::CancellationToken::new())) {
+            log::error!("failed to terminate: {:?}", e);
+        }
+        #[cfg(feature = "systemd")]
+        if let Some(notifier) = notifier.as_ref() {
+            let _ = notifier.stopping();
         }
-        Err(_e) => log::LevelFilter::Info,
     }
-}
-
-fn main() {
 
-    #[cfg(feature = "betterlog")]
-    simple_logger::SimpleLogger::new().env().with_local_timestamps().init().unwrap();
-
-    #[cfg(not(feature = "betterlog"))]
-    simple_logger::SimpleLogger::new().env().init().unwrap();
-
-    let (mut app, path) = {
-        let cfg_path = env::args().nth(1).unwrap_or_else(|| String::from("fanctrl.conf"));
-        match cfg_path.as_str() {
-            "-v" | "--version" => {
-                println!("{} {}", env!("CARGO_BIN_NAME"), env!("CARGO_PKG_VERSION"));
-                process::exit(0);
+    #[cfg(not(feature = "tokio"))]
+    while let Ok(signum) = unsafe { signal::wait(app.next_interval()) } {
+        #[cfg(feature = "systemd")]
+        if let Some(notifier) = notifier.as_ref() {
+            if let Err(e) = notifier.watchdog() {
+                log::warn!("failed to notify systemd watchdog: {:?}", e);
             }
-            "-h" | "--help" => {
-                println!("{} {}", env!("CARGO_BIN_NAME"), env!("CARGO_PKG_VERSION"));
-                println!("{}", env!("CARGO_PKG_DESCRIPTION"));
-                println!();
-                println!("Usage:  {} [CONFIGURATION_FILE]", env!("CARGO_BIN_NAME"));
-                process::exit(0);
-            }
-            _ => {
-                match Application::new_from_config(cfg_path.as_str()) {
-                    Ok(app) => (app, cfg_path),
-                    Err(e) => {
-                        log::error!("failed to create application: {:?}", e);
-                        process::exit(1);
-                    }
-                }
+        }
+        if signum == 0 {
+            if let Err(e) = app.run() {
+                log::error!("failed to run loop: {:?}", e);
             }
+            continue;
         }
-    };
-
-    unsafe { signal::register(&[libc::SIGINT, libc::SIGTERM, libc::SIGUSR1, libc::SIGUSR2]) };
-
-    if let Err(e) = app.initial() {
-        log::error!("failed to initialize: {:?}", e);
-        process::exit(1);
-    }
-
-    while let Ok(signum) = unsafe { signal::wait(app.interval) } {
-        match signum {
-            libc::SIGINT => {
-                log::debug!("receive SIGINT to terminate");
-                if let Err(e) = app.terminate() {
-                    log::error!("failed to terminate: {:?}", e);
+        #[cfg(feature = "dbus")]
+        if signum == signal::WAKE {
+            app.poll_dbus();
+            continue;
+        }
+        let Some(&(signal, command)) = app.signal_table().iter().find(|(signal, _)| signal.0 == signum) else {
+            unreachable!("Unknown signal: {}", signum);
+        };
+        match command {
+            SignalCommand::Terminate => {
+                log::debug!("receive {} to terminate", signal);
+                #[cfg(feature = "systemd")]
+                if let Some(notifier) = notifier.as_ref() {
+                    let _ = notifier.stopping();
                 }
-                break;
-            }
-            libc::SIGTERM => {
-                log::debug!("receive SIGTERM to terminate");
                 if let Err(e) = app.terminate() {
                     log::error!("failed to terminate: {:?}", e);
                 }
                 break;
             }
-            libc::SIGUSR2 => {
-                log::debug!("receive SIGUSR2 to maximum fan speed");
-                if let Err(e) = app.run_max_speed() {
-                    log::error!("failed to set fan speed to maximum: {:?}", e);
-                }
-            }
-            libc::SIGUSR1 => {
-                log::debug!("receive SIGUSR1");
-            }
-            0 => {
-                if let Err(e) = app.run() {
-                    log::error!("failed to run loop: {:?}", e);
-                }
-            }
-            _ => {
-                unreachable!("Unknown signal: {}", signum);
-            }
+            _ => app.on_signal(signal, command),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn rust_log_directives() {
+        assert_eq!(parse_log_level("debug"), (log::LevelFilter::Debug, Vec::new()));
+        assert_eq!(parse_log_level("fanctrl=trace"), (log::LevelFilter::Info, vec![(String::from("fanctrl"), log::LevelFilter::Trace)]));
+        assert_eq!(parse_log_level("fanctrl::app=debug, warn"), (log::LevelFilter::Warn, vec![(String::from("fanctrl::app"), log::LevelFilter::Debug)]));
+        // other crates are left to the default
+        assert_eq!(parse_log_level("hyper=trace,error"), (log::LevelFilter::Error, Vec::new()));
+        for invalid in ["verbose=", "fanctrl=loud", "=", ""] {
+            assert_eq!(parse_log_level(invalid), (log::LevelFilter::Info, Vec::new()), "{}", invalid);
         }
     }
 }
@@ -0,0 +1,175 @@
+use std::fmt::Write as FmtWrite;
+use std::io::Error as IOError;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::net::ToSocketAddrs;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    /// `fan` label; empty for the unnamed top-level fan
+    pub fan: String,
+    pub temperature: f32,
+    pub duty_cycle: f32,
+    pub on: bool,
+    pub fan_starts: u64,
+}
+
+impl Metrics {
+
+    fn labels(&self) -> String {
+        if self.fan.is_empty() {
+            String::new()
+        } else {
+            format!("{{fan=\"{}\"}}", label_value(&self.fan))
+        }
+    }
+}
+
+/// a label value with `\`, `"` and newlines escaped as the text exposition format requires
+fn label_value(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// what the endpoint serves: the fans and when the last failed cycle was
+#[derive(Debug, Default)]
+struct Snapshot {
+    fans: Vec<Metrics>,
+    last_error: Option<Instant>,
+}
+
+/// Prometheus text exposition format, one sample per fan; `fanctrl_last_error_seconds` only after an error
+pub fn render(metrics: &[Metrics], last_error: Option<Instant>) -> String {
+    let mut body = String::new();
+    let _ = writeln!(body, "# HELP fanctrl_temperature_celsius Last sensor reading.");
+    let _ = writeln!(body, "# TYPE fanctrl_temperature_celsius gauge");
+    for m in metrics {
+        let _ = writeln!(body, "fanctrl_temperature_celsius{} {}", m.labels(), m.temperature);
+    }
+    let _ = writeln!(body, "# HELP fanctrl_duty_cycle_ratio Duty cycle written to the pwm, 0 when off.");
+    let _ = writeln!(body, "# TYPE fanctrl_duty_cycle_ratio gauge");
+    for m in metrics {
+        let _ = writeln!(body, "fanctrl_duty_cycle_ratio{} {}", m.labels(), m.duty_cycle);
+    }
+    let _ = writeln!(body, "# HELP fanctrl_fan_on Whether the pwm is enabled.");
+    let _ = writeln!(body, "# TYPE fanctrl_fan_on gauge");
+    for m in metrics {
+        let _ = writeln!(body, "fanctrl_fan_on{} {}", m.labels(), m.on as u8);
+    }
+    let _ = writeln!(body, "# HELP fanctrl_fan_starts_total Times the fan was started.");
+    let _ = writeln!(body, "# TYPE fanctrl_fan_starts_total counter");
+    for m in metrics {
+        let _ = writeln!(body, "fanctrl_fan_starts_total{} {}", m.labels(), m.fan_starts);
+    }
+    if let Some(last_error) = last_error {
+        let _ = writeln!(body, "# HELP fanctrl_last_error_seconds Seconds since the last failed cycle; absent once a cycle succeeded.");
+        let _ = writeln!(body, "# TYPE fanctrl_last_error_seconds gauge");
+        let _ = writeln!(body, "fanctrl_last_error_seconds {:.3}", last_error.elapsed().as_secs_f64());
+    }
+    body
+}
+
+
+/// serves `GET /metrics` from a background thread; anything else gets 404
+#[derive(Debug)]
+pub struct MetricsServer {
+    snapshot: Arc<Mutex<Snapshot>>,
+}
+
+impl MetricsServer {
+
+    pub fn bind(address: impl ToSocketAddrs) -> Result<Self, IOError> {
+        let listener = TcpListener::bind(address)?;
+        log::info!("metrics listening: address={}", listener.local_addr()?);
+        let snapshot = Arc::new(Mutex::new(Snapshot::default()));
+        let shared = snapshot.clone();
+        thread::Builder::new()
+            .name(String::from("metrics"))
+            .spawn(move || serve(listener, shared))?;
+        Ok(Self { snapshot })
+    }
+
+    pub fn update(&self, metrics: Vec<Metrics>, last_error: Option<Instant>) {
+        if let Ok(mut guard) = self.snapshot.lock() {
+            *guard = Snapshot { fans: metrics, last_error };
+        }
+    }
+}
+
+fn serve(listener: TcpListener, snapshot: Arc<Mutex<Snapshot>>) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                if let Err(e) = respond(stream, &snapshot) {
+                    log::debug!("failed to serve metrics: {:?}", e);
+                }
+            }
+            Err(e) => {
+                log::warn!("failed to accept metrics connection: {:?}", e);
+            }
+        }
+    }
+}
+
+fn respond(mut stream: TcpStream, snapshot: &Mutex<Snapshot>) -> Result<(), IOError> {
+    stream.set_read_timeout(Some(Duration::from_millis(500)))?;
+    stream.set_write_timeout(Some(Duration::from_millis(500)))?;
+    // only the request line matters; it fits in the first read
+    let mut buf = [0u8; 1024];
+    let len = stream.read(&mut buf)?;
+    let request = String::from_utf8_lossy(&buf[..len]);
+    let mut parts = request.lines().next().unwrap_or("").split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let body = match snapshot.lock() {
+                Ok(guard) => render(&guard.fans, guard.last_error),
+                Err(_e) => String::new(),
+            };
+            ("200 OK", body)
+        }
+        _ => ("404 Not Found", String::from("not found\n")),
+    };
+    write!(
+        stream,
+        "HTTP/1.0 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, body.len(), body
+    )?;
+    stream.flush()
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn fan_label_is_escaped() {
+        let metrics = Metrics { fan: String::from("case \"top\"\\1\n"), fan_starts: 3, ..Metrics::default() };
+        let body = render(&[metrics], None);
+        assert!(body.contains("fanctrl_fan_starts_total{fan=\"case \\\"top\\\"\\\\1\\n\"} 3\n"), "{}", body);
+        assert!(!body.contains("fanctrl_last_error_seconds"));
+    }
+
+    #[test]
+    fn unnamed_fan_has_no_labels() {
+        let body = render(&[Metrics { duty_cycle: 0.5, ..Metrics::default() }], None);
+        assert!(body.contains("\nfanctrl_duty_cycle_ratio 0.5\n"), "{}", body);
+    }
+}
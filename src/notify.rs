@@ -0,0 +1,54 @@
+use std::env;
+use std::io::Error as IOError;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::SocketAddr;
+use std::os::unix::net::UnixDatagram;
+
+
+/// minimal sd_notify(3) over `$NOTIFY_SOCKET`
+pub struct Notifier {
+    socket: UnixDatagram,
+    address: SocketAddr,
+    watchdog: bool,
+}
+
+impl Notifier {
+
+    /// returns `None` when not started by systemd with `NotifyAccess`
+    pub fn from_env() -> Result<Option<Self>, IOError> {
+        let path = match env::var_os("NOTIFY_SOCKET") {
+            Some(path) if !path.is_empty() => path,
+            _ => return Ok(None),
+        };
+        let path = path.to_string_lossy();
+        let address = match path.strip_prefix('@') {
+            Some(name) => SocketAddr::from_abstract_name(name.as_bytes())?,
+            None => SocketAddr::from_pathname(path.as_ref())?,
+        };
+        let socket = UnixDatagram::unbound()?;
+        let watchdog = env::var_os("WATCHDOG_USEC").is_some();
+        Ok(Some(Self { socket, address, watchdog }))
+    }
+
+    pub fn ready(&self) -> Result<(), IOError> {
+        self.send("READY=1")
+    }
+
+    pub fn stopping(&self) -> Result<(), IOError> {
+        self.send("STOPPING=1")
+    }
+
+    /// no-op unless `WATCHDOG_USEC` is set
+    pub fn watchdog(&self) -> Result<(), IOError> {
+        if self.watchdog {
+            self.send("WATCHDOG=1")
+        } else {
+            Ok(())
+        }
+    }
+
+    fn send(&self, state: &str) -> Result<(), IOError> {
+        self.socket.send_to_addr(state.as_bytes(), &self.address)?;
+        Ok(())
+    }
+}
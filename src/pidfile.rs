@@ -0,0 +1,64 @@
+use std::fs;
+use std::fs::File;
+use std::io::Error as IOError;
+use std::io::ErrorKind as IOErrorKind;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::path::PathBuf;
+
+
+/// pid file guarded by an advisory `flock`; the lock is held as long as the file is open
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+    file: File,
+}
+
+impl PidFile {
+
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, IOError> {
+        let path = path.as_ref();
+        let mut file = File::options().read(true).write(true).create(true).truncate(false).open(path)?;
+        let mut content = String::new();
+        file.read_to_string(&mut content)?;
+        let content = content.trim();
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let e = IOError::last_os_error();
+            if e.kind() == IOErrorKind::WouldBlock {
+                return Err(IOError::new(
+                    IOErrorKind::AlreadyExists,
+                    format!("another instance is running: pidfile={}, pid={}", path.display(), content)
+                ));
+            }
+            return Err(e);
+        }
+        if !content.is_empty() {
+            log::warn!("stale pidfile found: path={}, pid={}", path.display(), content);
+        }
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        write!(file, "{}", std::process::id())?;
+        file.flush()?;
+        Ok(
+            PidFile {
+                path: path.to_path_buf(),
+                file,
+            }
+        )
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// removes the file while still holding the lock, then releases it
+    pub fn remove(self) -> Result<(), IOError> {
+        fs::remove_file(&self.path)?;
+        drop(self.file);
+        Ok(())
+    }
+}
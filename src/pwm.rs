@@ -3,14 +3,22 @@ use std::fmt::Display;
 use std::fs::File;
 use std::io::Error as IOError;
 use std::io::ErrorKind as IOErrorKind;
-use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
 
+use crate::sysfs::Attribute;
+use crate::sysfs::RealSysfs;
+use crate::sysfs::Sysfs;
 
-#[derive(Debug, Clone)]
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Polarity {
     Normal,
+    Inversed,
 }
 
 impl Display for Polarity {
@@ -18,85 +26,560 @@ impl Display for Polarity {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Polarity::Normal => write!(f, "normal"),
+            Polarity::Inversed => write!(f, "inversed"),
         }
     }
 }
 
+impl FromStr for Polarity {
+    type Err = IOError;
 
-#[derive(Debug, Clone)]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "normal" => Ok(Polarity::Normal),
+            "inversed" => Ok(Polarity::Inversed),
+            _ => Err(IOError::new(IOErrorKind::InvalidData, format!("invalid polarity: {}", s))),
+        }
+    }
+}
+
+
+/// keeps `duty_cycle` below `period`, which some drivers reject with EINVAL; `period` 0 means not set yet
+pub fn clamp_duty_cycle(duty_cycle: u32, period: u32) -> u32 {
+    if period > 0 && duty_cycle >= period {
+        log::debug!("duty_cycle={} clamped to {} below period={}", duty_cycle, period - 1, period);
+        period - 1
+    } else {
+        duty_cycle
+    }
+}
+
+
+pub trait FanOutput: Send {
+
+    fn set_period(&mut self, period: u32) -> Result<(), IOError>;
+
+    /// returns the duty cycle actually written, see `clamp_duty_cycle`
+    fn set_duty_cycle(&mut self, duty_cycle: u32) -> Result<u32, IOError>;
+
+    fn set_polarity(&mut self, polarity: Polarity) -> Result<(), IOError>;
+
+    fn set_enable(&mut self, enable: bool) -> Result<(), IOError>;
+
+    fn get_period(&mut self) -> Result<u32, IOError>;
+
+    fn get_polarity(&mut self) -> Result<Polarity, IOError>;
+
+    /// whether the output is enabled, read back from the device where it can be
+    fn get_enable(&mut self) -> Result<bool, IOError>;
+
+    /// writes all four in an order the kernel accepts from any previous state, where `duty_cycle` must never
+    /// exceed `period`: disabling first, the polarity only when it differs, the duty cycle before a period
+    /// shrinking below the old duty cycle and after one growing above it, and enabling last; returns the duty
+    /// cycle actually written
+    fn apply(&mut self, period: u32, duty_cycle: u32, polarity: Polarity, enable: bool) -> Result<u32, IOError> {
+        let duty_cycle = clamp_duty_cycle(duty_cycle, period);
+        if !enable {
+            self.set_enable(false)?;
+        }
+        if self.get_polarity().ok().as_ref() != Some(&polarity) {
+            self.set_polarity(polarity)?;
+        }
+        let previous = self.get_period().unwrap_or(0);
+        let duty_cycle = if previous == period {
+            self.set_duty_cycle(duty_cycle)?
+        } else if duty_cycle < previous {
+            let duty_cycle = self.set_duty_cycle(duty_cycle)?;
+            self.set_period(period)?;
+            duty_cycle
+        } else {
+            self.set_period(period)?;
+            self.set_duty_cycle(duty_cycle)?
+        };
+        if enable {
+            self.set_enable(true)?;
+        }
+        Ok(duty_cycle)
+    }
+}
+
+
+#[derive(Debug)]
 pub struct PWMDevice {
-    instance_period_path: PathBuf,
-    instance_duty_cycle_path: PathBuf,
-    instance_polarity_path: PathBuf,
-    instance_enable_path: PathBuf,
+    sysfs: Arc<dyn Sysfs>,
+    device: PathBuf,
+    instance: u32,
+    /// last period written, 0 before `set_period`
+    period: u32,
+    /// last values written, replayed after a re-initialization
+    duty_cycle: Option<u32>,
+    polarity: Option<Polarity>,
+    enable: Option<bool>,
+    /// set while the chip is gone; writes before it are only recorded and fail with `WouldBlock`
+    retry_at: Option<Instant>,
+    backoff: Duration,
+    instance_period: Attribute,
+    instance_duty_cycle: Attribute,
+    instance_polarity: Attribute,
+    instance_enable: Attribute,
 }
 
 impl PWMDevice {
+
+    const MIN_BACKOFF: Duration = Duration::from_secs(1);
+
+    const MAX_BACKOFF: Duration = Duration::from_secs(60);
     
     pub fn new(device: impl AsRef<Path>, instance: u32) -> Result<Self, IOError> {
-        let path = device.as_ref();
+        Self::with_sysfs(Arc::new(RealSysfs), device, instance)
+    }
+
+    /// like `new`, through `sysfs`, e.g. a `MemorySysfs` in tests
+    pub fn with_sysfs(sysfs: Arc<dyn Sysfs>, device: impl AsRef<Path>, instance: u32) -> Result<Self, IOError> {
+        let [instance_period, instance_duty_cycle, instance_polarity, instance_enable] = Self::open(&sysfs, device.as_ref(), instance)?;
+        Ok(
+            PWMDevice {
+                sysfs,
+                device: device.as_ref().to_path_buf(),
+                instance,
+                period: 0,
+                duty_cycle: None,
+                polarity: None,
+                enable: None,
+                retry_at: None,
+                backoff: Self::MIN_BACKOFF,
+                instance_period,
+                instance_duty_cycle,
+                instance_polarity,
+                instance_enable,
+            }
+        )
+    }
+
+    /// exports the instance if needed; returns the period, duty_cycle, polarity and enable attributes
+    fn open(sysfs: &Arc<dyn Sysfs>, path: &Path, instance: u32) -> Result<[Attribute; 4], IOError> {
+        check_npwm(sysfs, path, instance)?;
         let instance_path = path.join(format!("pwm{}", instance));
-        let is_exist = match instance_path.try_exists() {
+        let is_exist = match sysfs.exists(&instance_path) {
             Ok(true) => true,
             Ok(false) => false,
-            Err(e) => false,
+            Err(_e) => false,
         };
         if !is_exist {
-            let mut ofile = File::options().write(true).open(path.join("export"))?;
-            write!(ofile, "{}", instance)?;
+            sysfs.write(&path.join("export"), instance.to_string().as_str())?;
         }
 
         let instance_period_path = instance_path.join("period");
-        if !instance_period_path.try_exists()? {
+        if !sysfs.exists(&instance_period_path)? {
             return Err(IOError::new(IOErrorKind::NotFound, format!("{}", instance_period_path.display())));
         }
         let instance_duty_cycle_path = instance_path.join("duty_cycle");
-        if !instance_duty_cycle_path.try_exists()? {
+        if !sysfs.exists(&instance_duty_cycle_path)? {
             return Err(IOError::new(IOErrorKind::NotFound, format!("{}", instance_duty_cycle_path.display())));
         }
         let instance_polarity_path = instance_path.join("polarity");
-        if !instance_polarity_path.try_exists()? {
+        if !sysfs.exists(&instance_polarity_path)? {
             return Err(IOError::new(IOErrorKind::NotFound, format!("{}", instance_polarity_path.display())));
         }
         let instance_enable_path = instance_path.join("enable");
-        if !instance_enable_path.try_exists()? {
+        if !sysfs.exists(&instance_enable_path)? {
             return Err(IOError::new(IOErrorKind::NotFound, format!("{}", instance_enable_path.display())));
         }
-        Ok(
-            PWMDevice {
-                instance_period_path,
-                instance_duty_cycle_path,
-                instance_polarity_path,
-                instance_enable_path,
-            }
-        )
+        Ok([
+            Attribute::new(sysfs.clone(), instance_period_path, true),
+            Attribute::new(sysfs.clone(), instance_duty_cycle_path, true),
+            Attribute::new(sysfs.clone(), instance_polarity_path, true),
+            Attribute::new(sysfs.clone(), instance_enable_path, true),
+        ])
     }
 
-    pub fn set_period(&mut self, period: u32) -> Result<(), IOError> {
-        let mut ofile = File::options().write(true).open(&self.instance_period_path)?;
-        write!(ofile, "{}", period)?;
-        Ok(())
+    /// ENOENT, ENODEV or ESTALE: the chip went away, e.g. on a driver reload
+    fn is_gone(e: &IOError) -> bool {
+        e.kind() == IOErrorKind::NotFound || matches!(e.raw_os_error(), Some(libc::ENODEV) | Some(libc::ESTALE))
     }
 
-    pub fn set_duty_cycle(&mut self, duty_cycle: u32) -> Result<(), IOError> {
-        let mut ofile = File::options().write(true).open(&self.instance_duty_cycle_path)?;
-        write!(ofile, "{}", duty_cycle)?;
+    /// re-exports the instance and writes back the last period, polarity, duty cycle and enable
+    fn reinitialize(&mut self) -> Result<(), IOError> {
+        let [instance_period, instance_duty_cycle, instance_polarity, instance_enable] = Self::open(&self.sysfs, &self.device, self.instance)?;
+        self.instance_period = instance_period;
+        self.instance_duty_cycle = instance_duty_cycle;
+        self.instance_polarity = instance_polarity;
+        self.instance_enable = instance_enable;
+        if self.period > 0 {
+            self.instance_period.write(self.period)?;
+        }
+        if let Some(polarity) = self.polarity.as_ref() {
+            self.instance_polarity.write(polarity)?;
+        }
+        if let Some(duty_cycle) = self.duty_cycle {
+            self.instance_duty_cycle.write(duty_cycle)?;
+        }
+        if let Some(enable) = self.enable {
+            self.instance_enable.write(enable as u8)?;
+        }
         Ok(())
     }
 
-    pub fn set_polarity(&mut self, polarity: Polarity) -> Result<(), IOError> {
-        let mut ofile = File::options().write(true).open(&self.instance_polarity_path)?;
-        write!(ofile, "{}", polarity)?;
-        Ok(())
+    /// the duty cycle last written, or the instance's own before any write
+    fn current_duty_cycle(&mut self) -> Option<u32> {
+        match self.duty_cycle {
+            Some(duty_cycle) => Some(duty_cycle),
+            None => self.instance_duty_cycle.read_to_string().ok()?.parse().ok(),
+        }
     }
 
-    pub fn set_enable(&mut self, enable: bool) -> Result<(), IOError> {
-        let mut ofile = File::options().write(true).open(&self.instance_enable_path)?;
-        if enable {
-            write!(ofile, "1")?;
+    /// `WouldBlock` until the next re-initialization attempt of a gone chip, `None` when a write may go through
+    fn backing_off(&self) -> Option<IOError> {
+        let remaining = self.retry_at?.checked_duration_since(Instant::now())?;
+        Some(IOError::new(
+            IOErrorKind::WouldBlock,
+            format!("pwm {}/pwm{} is gone, next re-initialization in {}ms", self.device.display(), self.instance, remaining.as_millis())
+        ))
+    }
+
+    /// writes through `select`; once the chip is gone, re-initializes it with a doubling backoff; the writes
+    /// in between are recorded for the re-initialization and fail with `WouldBlock`, telling them from an
+    /// attempt that failed
+    fn write(&mut self, select: fn(&mut Self) -> &mut Attribute, value: impl Display) -> Result<(), IOError> {
+        if self.retry_at.is_some() {
+            if let Some(e) = self.backing_off() {
+                return Err(e);
+            }
+        } else {
+            match select(self).write(&value) {
+                Err(e) if Self::is_gone(&e) => {
+                    log::warn!("pwm {}/pwm{} is gone ({}), re-initializing", self.device.display(), self.instance, e);
+                }
+                result => return result,
+            }
+        }
+        match self.reinitialize() {
+            Ok(()) => {
+                log::info!("pwm {}/pwm{} re-initialized", self.device.display(), self.instance);
+                self.retry_at = None;
+                self.backoff = Self::MIN_BACKOFF;
+                Ok(())
+            }
+            Err(e) => {
+                self.retry_at = Some(Instant::now() + self.backoff);
+                let message = format!("failed to re-initialize pwm {}/pwm{}, retrying in {}s: {}", self.device.display(), self.instance, self.backoff.as_secs(), e);
+                self.backoff = (self.backoff * 2).min(Self::MAX_BACKOFF);
+                Err(IOError::new(e.kind(), message))
+            }
+        }
+    }
+}
+
+/// where the kernel links every pwm chip, whatever its platform device address
+pub const PWM_CLASS: &str = "/sys/class/pwm";
+
+/// `/sys/class/pwm/pwmchipN`, for a chip named by index instead of its platform device path
+pub fn chip_path(index: u32) -> PathBuf {
+    Path::new(PWM_CLASS).join(format!("pwmchip{}", index))
+}
+
+/// `InvalidInput` naming the available count when `instance` is not below the chip's `npwm`;
+/// a chip without `npwm` is not checked
+fn check_npwm(sysfs: &Arc<dyn Sysfs>, path: &Path, instance: u32) -> Result<(), IOError> {
+    let npwm_path = path.join("npwm");
+    if !sysfs.exists(&npwm_path).unwrap_or(false) {
+        return Ok(());
+    }
+    let npwm = Attribute::new(sysfs.clone(), npwm_path.clone(), false).read_to_string()?;
+    let npwm: u32 = npwm.parse()
+        .map_err(|_e| IOError::new(IOErrorKind::InvalidData, format!("{}: invalid value `{}`", npwm_path.display(), npwm)))?;
+    if instance >= npwm {
+        let available = match npwm {
+            0 => String::from("none"),
+            1 => String::from("pwm0"),
+            n => format!("pwm0..pwm{}", n - 1),
+        };
+        return Err(IOError::new(
+            IOErrorKind::InvalidInput,
+            format!("pwm{} out of range: {} has npwm={} ({} available)", instance, path.display(), npwm, available)
+        ));
+    }
+    Ok(())
+}
+
+/// checks the pwm can be driven without writing anything: opens the attributes of an exported instance,
+/// or `export` otherwise, for writing; returns whether the instance is exported
+pub fn probe(device: impl AsRef<Path>, instance: u32) -> Result<bool, IOError> {
+    let path = device.as_ref();
+    check_npwm(&(Arc::new(RealSysfs) as Arc<dyn Sysfs>), path, instance)?;
+    let instance_path = path.join(format!("pwm{}", instance));
+    if !instance_path.try_exists()? {
+        File::options().write(true).open(path.join("export"))
+            .map_err(|e| IOError::new(e.kind(), format!("{}/export: {}", path.display(), e)))?;
+        return Ok(false);
+    }
+    for attribute in ["period", "duty_cycle", "polarity", "enable"] {
+        let attribute_path = instance_path.join(attribute);
+        File::options().write(true).open(&attribute_path)
+            .map_err(|e| IOError::new(e.kind(), format!("{}: {}", attribute_path.display(), e)))?;
+    }
+    Ok(true)
+}
+
+impl FanOutput for PWMDevice {
+
+    /// lowers a larger duty cycle first, e.g. one left by a previous run at a longer period, which would
+    /// make the kernel reject the period
+    fn set_period(&mut self, period: u32) -> Result<(), IOError> {
+        let shrunk = self.current_duty_cycle()
+            .filter(|duty_cycle| *duty_cycle > period)
+            .map(|duty_cycle| clamp_duty_cycle(duty_cycle, period));
+        // recorded before writing, for a re-initialization
+        self.period = period;
+        if let Some(duty_cycle) = shrunk {
+            self.duty_cycle = Some(duty_cycle);
+            self.write(|pwm| &mut pwm.instance_duty_cycle, duty_cycle)?;
+        }
+        self.write(|pwm| &mut pwm.instance_period, period)
+    }
+
+    fn set_duty_cycle(&mut self, duty_cycle: u32) -> Result<u32, IOError> {
+        let duty_cycle = clamp_duty_cycle(duty_cycle, self.period);
+        self.duty_cycle = Some(duty_cycle);
+        self.write(|pwm| &mut pwm.instance_duty_cycle, duty_cycle)?;
+        Ok(duty_cycle)
+    }
+
+    fn set_polarity(&mut self, polarity: Polarity) -> Result<(), IOError> {
+        self.polarity = Some(polarity.clone());
+        self.write(|pwm| &mut pwm.instance_polarity, polarity)
+    }
+
+    fn set_enable(&mut self, enable: bool) -> Result<(), IOError> {
+        self.enable = Some(enable);
+        self.write(|pwm| &mut pwm.instance_enable, enable as u8)
+    }
+
+    fn get_period(&mut self) -> Result<u32, IOError> {
+        let s = self.instance_period.read_to_string()?;
+        s.parse().map_err(|_e| IOError::new(IOErrorKind::InvalidData, format!("invalid period: {}", s)))
+    }
+
+    fn get_polarity(&mut self) -> Result<Polarity, IOError> {
+        self.instance_polarity.read_to_string()?.parse()
+    }
+
+    fn get_enable(&mut self) -> Result<bool, IOError> {
+        match self.instance_enable.read_to_string()?.as_str() {
+            "0" => Ok(false),
+            "1" => Ok(true),
+            s => Err(IOError::new(IOErrorKind::InvalidData, format!("invalid enable: {}", s))),
+        }
+    }
+
+    /// like the default, but compares against the values last written and skips the unchanged ones,
+    /// reading the instance only for what was never written
+    fn apply(&mut self, period: u32, duty_cycle: u32, polarity: Polarity, enable: bool) -> Result<u32, IOError> {
+        let duty_cycle = clamp_duty_cycle(duty_cycle, period);
+        if let Some(e) = self.backing_off() {
+            // the first failed write would skip recording the others
+            self.period = period;
+            self.duty_cycle = Some(duty_cycle);
+            self.polarity = Some(polarity);
+            self.enable = Some(enable);
+            return Err(e);
+        }
+        if !enable && self.enable != Some(false) {
+            self.set_enable(false)?;
+        }
+        let current_polarity = match self.polarity.clone() {
+            Some(polarity) => Some(polarity),
+            None => self.get_polarity().ok(),
+        };
+        if current_polarity.as_ref() != Some(&polarity) {
+            self.set_polarity(polarity)?;
+        }
+        let previous = if self.period > 0 { self.period } else { self.get_period().unwrap_or(0) };
+        let duty_cycle = if previous == period {
+            self.period = period;
+            if self.duty_cycle == Some(duty_cycle) { duty_cycle } else { self.set_duty_cycle(duty_cycle)? }
+        } else if duty_cycle < previous {
+            let duty_cycle = self.set_duty_cycle(duty_cycle)?;
+            self.set_period(period)?;
+            duty_cycle
         } else {
-            write!(ofile, "0")?;
+            self.set_period(period)?;
+            self.set_duty_cycle(duty_cycle)?
+        };
+        if enable && self.enable != Some(true) {
+            self.set_enable(true)?;
+        }
+        Ok(duty_cycle)
+    }
+}
+
+
+/// logs the intended writes instead of touching sysfs
+#[derive(Debug, Clone)]
+pub struct NullPWMDevice {
+    instance_path: PathBuf,
+    period: u32,
+    polarity: Polarity,
+    enable: bool,
+}
+
+impl NullPWMDevice {
+
+    pub fn new(device: impl AsRef<Path>, instance: u32) -> Self {
+        NullPWMDevice {
+            instance_path: device.as_ref().join(format!("pwm{}", instance)),
+            period: 0,
+            polarity: Polarity::Normal,
+            enable: false,
         }
+    }
+
+    /// logs the writes as attributes of `path`, e.g. a gpio line directory
+    pub fn with_path(path: impl AsRef<Path>) -> Self {
+        NullPWMDevice {
+            instance_path: path.as_ref().to_path_buf(),
+            period: 0,
+            polarity: Polarity::Normal,
+            enable: false,
+        }
+    }
+}
+
+impl FanOutput for NullPWMDevice {
+
+    fn set_period(&mut self, period: u32) -> Result<(), IOError> {
+        log::info!("[null] write {} to {}/period", period, self.instance_path.display());
+        self.period = period;
+        Ok(())
+    }
+
+    fn set_duty_cycle(&mut self, duty_cycle: u32) -> Result<u32, IOError> {
+        let duty_cycle = clamp_duty_cycle(duty_cycle, self.period);
+        log::info!("[null] write {} to {}/duty_cycle", duty_cycle, self.instance_path.display());
+        Ok(duty_cycle)
+    }
+
+    fn set_polarity(&mut self, polarity: Polarity) -> Result<(), IOError> {
+        log::info!("[null] write {} to {}/polarity", polarity, self.instance_path.display());
+        self.polarity = polarity;
         Ok(())
     }
+
+    fn set_enable(&mut self, enable: bool) -> Result<(), IOError> {
+        log::info!("[null] write {} to {}/enable", enable as u8, self.instance_path.display());
+        self.enable = enable;
+        Ok(())
+    }
+
+    fn get_period(&mut self) -> Result<u32, IOError> {
+        Ok(self.period)
+    }
+
+    fn get_polarity(&mut self) -> Result<Polarity, IOError> {
+        Ok(self.polarity.clone())
+    }
+
+    fn get_enable(&mut self) -> Result<bool, IOError> {
+        Ok(self.enable)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use crate::replay::Replay;
+    use crate::replay::TraceLine;
+    use crate::sysfs::MemorySysfs;
+
+    use super::*;
+
+    const CHIP: &str = "/sys/class/pwm/pwmchip0";
+
+    /// an exported pwm0 of a chip with `npwm` channels, all attributes zero and polarity normal
+    fn chip(npwm: u32) -> MemorySysfs {
+        let sysfs = MemorySysfs::new();
+        sysfs.set(format!("{}/export", CHIP), "");
+        sysfs.set(format!("{}/npwm", CHIP), npwm);
+        add_pwm0(&sysfs);
+        sysfs
+    }
+
+    fn add_pwm0(sysfs: &MemorySysfs) {
+        sysfs.set(format!("{}/pwm0/period", CHIP), 0);
+        sysfs.set(format!("{}/pwm0/duty_cycle", CHIP), 0);
+        sysfs.set(format!("{}/pwm0/polarity", CHIP), "normal");
+        sysfs.set(format!("{}/pwm0/enable", CHIP), 0);
+    }
+
+    fn writes(sysfs: &MemorySysfs, attribute: &str) -> Vec<String> {
+        sysfs.take_writes(format!("{}/pwm0/{}", CHIP, attribute))
+    }
+
+    #[test]
+    fn gone_chip_is_reinitialized_with_the_recorded_values() {
+        let sysfs = chip(1);
+        let mut pwm = PWMDevice::with_sysfs(Arc::new(sysfs.clone()), CHIP, 0).unwrap();
+        pwm.apply(10000, 5000, Polarity::Normal, true).unwrap();
+        writes(&sysfs, "duty_cycle");
+
+        // vanishes: the failed re-initialization starts the backoff
+        sysfs.remove(format!("{}/pwm0", CHIP));
+        assert_eq!(pwm.set_duty_cycle(6000).unwrap_err().kind(), IOErrorKind::NotFound);
+        assert_eq!(sysfs.take_writes(format!("{}/export", CHIP)), ["0"]);
+        // writes during the backoff are recorded and reported as such
+        assert_eq!(pwm.set_duty_cycle(7000).unwrap_err().kind(), IOErrorKind::WouldBlock);
+        assert_eq!(pwm.apply(20000, 8000, Polarity::Normal, true).unwrap_err().kind(), IOErrorKind::WouldBlock);
+
+        // reappears: the next write after the backoff re-initializes it with the last values, its own included
+        add_pwm0(&sysfs);
+        pwm.retry_at = Some(Instant::now());
+        assert_eq!(pwm.set_duty_cycle(9000).unwrap(), 9000);
+        assert_eq!(writes(&sysfs, "period"), ["20000"]);
+        assert_eq!(writes(&sysfs, "polarity"), ["normal"]);
+        assert_eq!(writes(&sysfs, "duty_cycle"), ["9000"]);
+        assert_eq!(writes(&sysfs, "enable"), ["1"]);
+        assert!(pwm.retry_at.is_none());
+        assert_eq!(pwm.backoff, PWMDevice::MIN_BACKOFF);
+    }
+    #[test]
+    fn full_duty_is_clamped_below_the_period() {
+        let sysfs = chip(1);
+        let mut pwm = PWMDevice::with_sysfs(Arc::new(sysfs.clone()), CHIP, 0).unwrap();
+        // a duty ratio of 1.0 over a period of 10000
+        assert_eq!(pwm.apply(10000, (1.0 * 10000.0) as u32, Polarity::Normal, true).unwrap(), 9999);
+        assert_eq!(writes(&sysfs, "duty_cycle"), ["9999"]);
+        assert_eq!(pwm.set_duty_cycle(10000).unwrap(), 9999);
+        assert_eq!(pwm.set_duty_cycle(9999).unwrap(), 9999);
+        assert_eq!(writes(&sysfs, "duty_cycle"), ["9999", "9999"]);
+        assert_eq!(clamp_duty_cycle(10000, 0), 10000);
+    }
+    #[test]
+    fn instance_out_of_npwm_is_rejected() {
+        let sysfs = chip(2);
+        let e = PWMDevice::with_sysfs(Arc::new(sysfs.clone()), CHIP, 2).unwrap_err();
+        assert_eq!(e.kind(), IOErrorKind::InvalidInput);
+        assert_eq!(e.to_string(), format!("pwm2 out of range: {} has npwm=2 (pwm0..pwm1 available)", CHIP));
+        // rejected before anything is exported
+        assert!(sysfs.take_writes(format!("{}/export", CHIP)).is_empty());
+        assert!(PWMDevice::with_sysfs(Arc::new(sysfs), CHIP, 0).is_ok());
+    }
+    #[test]
+    fn apply_writes_in_kernel_order() {
+        let replay = Replay::default();
+        let (_sensor, mut pwm) = replay.devices();
+        let expect = |writes: &[&str]| {
+            let line = TraceLine { line_number: 0, temperature: 0.0, writes: writes.iter().map(|write| write.to_string()).collect() };
+            assert_eq!(replay.check(&line), None);
+        };
+        // growing: the period before the duty cycle, enabling last
+        pwm.apply(10000, 5000, Polarity::Normal, true).unwrap();
+        expect(&["period=10000", "duty_cycle=5000", "enable=1"]);
+        // shrinking below the old period: the duty cycle first
+        pwm.apply(4000, 2000, Polarity::Normal, true).unwrap();
+        expect(&["duty_cycle=2000", "period=4000", "enable=1"]);
+        // disabling first, the polarity only when it differs
+        pwm.apply(4000, 1000, Polarity::Inversed, false).unwrap();
+        expect(&["enable=0", "polarity=inversed", "duty_cycle=1000"]);
+    }
 }
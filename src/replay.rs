@@ -0,0 +1,158 @@
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Error as IOError;
+use std::io::ErrorKind as IOErrorKind;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use crate::pwm::clamp_duty_cycle;
+use crate::pwm::FanOutput;
+use crate::pwm::Polarity;
+use crate::sensor::SensorSource;
+
+
+/// one cycle of a recorded trace: `<temperature> [<attribute>=<value> ...]`
+///
+/// ```text
+/// # the first line is consumed by the initialization
+/// 45.0 period=100000 polarity=normal duty_cycle=50000 enable=1
+/// 46.5 duty_cycle=51666
+/// 46.0
+/// ```
+#[derive(Debug)]
+pub struct TraceLine {
+    pub line_number: usize,
+    pub temperature: f32,
+    pub writes: Vec<String>,
+}
+
+pub fn parse_trace(path: impl AsRef<Path>) -> Result<Vec<TraceLine>, IOError> {
+    let path = path.as_ref();
+    let reader = BufReader::new(File::open(path)?);
+    let mut lines = Vec::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut tokens = line.split_whitespace();
+        let temperature = tokens
+            .next()
+            .and_then(|t| t.parse().ok())
+            .ok_or_else(|| IOError::new(IOErrorKind::InvalidData, format!("{}:{}: invalid temperature", path.display(), i + 1)))?;
+        lines.push(TraceLine {
+            line_number: i + 1,
+            temperature,
+            writes: tokens.map(String::from).collect(),
+        });
+    }
+    Ok(lines)
+}
+
+
+#[derive(Debug)]
+pub struct TraceSensor {
+    temperature: Arc<Mutex<f32>>,
+}
+
+impl SensorSource for TraceSensor {
+
+    fn get(&mut self) -> Result<f32, IOError> {
+        Ok(*self.temperature.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+
+/// records every write as `<attribute>=<value>`
+#[derive(Debug)]
+pub struct RecordingPWMDevice {
+    writes: Arc<Mutex<Vec<String>>>,
+    period: u32,
+    polarity: Polarity,
+    enable: bool,
+}
+
+impl FanOutput for RecordingPWMDevice {
+
+    fn set_period(&mut self, period: u32) -> Result<(), IOError> {
+        self.writes.lock().unwrap_or_else(|e| e.into_inner()).push(format!("period={}", period));
+        self.period = period;
+        Ok(())
+    }
+
+    fn set_duty_cycle(&mut self, duty_cycle: u32) -> Result<u32, IOError> {
+        let duty_cycle = clamp_duty_cycle(duty_cycle, self.period);
+        self.writes.lock().unwrap_or_else(|e| e.into_inner()).push(format!("duty_cycle={}", duty_cycle));
+        Ok(duty_cycle)
+    }
+
+    fn set_polarity(&mut self, polarity: Polarity) -> Result<(), IOError> {
+        self.writes.lock().unwrap_or_else(|e| e.into_inner()).push(format!("polarity={}", polarity));
+        self.polarity = polarity;
+        Ok(())
+    }
+
+    fn set_enable(&mut self, enable: bool) -> Result<(), IOError> {
+        self.writes.lock().unwrap_or_else(|e| e.into_inner()).push(format!("enable={}", enable as u8));
+        self.enable = enable;
+        Ok(())
+    }
+
+    fn get_period(&mut self) -> Result<u32, IOError> {
+        Ok(self.period)
+    }
+
+    fn get_polarity(&mut self) -> Result<Polarity, IOError> {
+        Ok(self.polarity.clone())
+    }
+
+    fn get_enable(&mut self) -> Result<bool, IOError> {
+        Ok(self.enable)
+    }
+}
+
+
+/// shared state between the mock devices and the replay loop
+#[derive(Debug, Default)]
+pub struct Replay {
+    temperature: Arc<Mutex<f32>>,
+    writes: Arc<Mutex<Vec<String>>>,
+}
+
+impl Replay {
+
+    pub fn devices(&self) -> (Box<dyn SensorSource>, Box<dyn FanOutput>) {
+        let sensor = TraceSensor {
+            temperature: self.temperature.clone(),
+        };
+        let pwm = RecordingPWMDevice {
+            writes: self.writes.clone(),
+            period: 0,
+            polarity: Polarity::Normal,
+            enable: false,
+        };
+        (Box::new(sensor), Box::new(pwm))
+    }
+
+    pub fn set_temperature(&self, temperature: f32) {
+        *self.temperature.lock().unwrap_or_else(|e| e.into_inner()) = temperature;
+    }
+
+    /// the writes recorded since the last call or check, as `<attribute>=<value>`
+    pub fn take_writes(&self) -> Vec<String> {
+        self.writes.lock().unwrap_or_else(|e| e.into_inner()).drain(..).collect()
+    }
+
+    /// compares the writes recorded since the last check with `line`; returns the diff on mismatch
+    pub fn check(&self, line: &TraceLine) -> Option<String> {
+        let actual = self.take_writes();
+        if actual == line.writes {
+            None
+        } else {
+            Some(format!("line {}: temperature={:.2}°C, expected [{}], actual [{}]", line.line_number, line.temperature, line.writes.join(" "), actual.join(" ")))
+        }
+    }
+}
@@ -1,92 +1,483 @@
-use std::fs::File;
 use std::io::Error as IOError;
 use std::io::ErrorKind as IOErrorKind;
 use std::io::Read;
-use std::path::PathBuf;
+use std::os::unix::process::CommandExt;
 use std::path::Path;
+use std::path::PathBuf;
+use std::process::Child;
+use std::process::Command;
+use std::process::ExitStatus;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::sysfs::Attribute;
+use crate::sysfs::RealSysfs;
+use crate::sysfs::Sysfs;
+
+pub trait SensorSource: Send {
+
+    /// temperature in degrees Celsius
+    fn get(&mut self) -> Result<f32, IOError>;
+
+    /// writes a calibration offset in raw units, subtracted from every later reading; `Unsupported` by default
+    fn set_offset(&mut self, offset: i32) -> Result<(), IOError> {
+        Err(IOError::new(IOErrorKind::Unsupported, format!("sensor has no offset to set to {}", offset)))
+    }
+}
+
 
 #[derive(Debug)]
 pub struct SensorDevice {
-    path_temp: PathBuf,
-    path_offset: Option<PathBuf>,
+    sysfs: Arc<dyn Sysfs>,
+    device: PathBuf,
+    temp: Attribute,
+    offset: Option<Attribute>,
+    /// raw units per degree Celsius
+    scale: f32,
 }
 
 impl SensorDevice {
 
-    const FACTOR: f32 = 1000.0;
+    /// attempts for a read failing with `Interrupted` or `UnexpectedEof`
+    const RETRY_ATTEMPTS: u32 = 3;
 
-    pub fn new(device: impl AsRef<Path>) -> Result<Self, IOError> {
+    const RETRY_DELAY: Duration = Duration::from_millis(10);
+
+    /// longest accepted content: a signed 32-bit millidegree value is 11 bytes plus the newline,
+    /// with room for forms like `4.55e4`
+    const MAX_WIDTH: usize = 24;
+
+    /// `scale` is the raw reading per degree Celsius, 1000 for millidegree thermal zones
+    pub fn new(device: impl AsRef<Path>, scale: f32) -> Result<Self, IOError> {
+        Self::with_sysfs(Arc::new(RealSysfs), device, scale)
+    }
+
+    /// like `new`, through `sysfs`, e.g. a `MemorySysfs` in tests
+    pub fn with_sysfs(sysfs: Arc<dyn Sysfs>, device: impl AsRef<Path>, scale: f32) -> Result<Self, IOError> {
+        if !(scale > 0.0 && scale.is_finite()) {
+            return Err(IOError::new(IOErrorKind::InvalidData, format!("invalid value `{}` for sensor_scale: not a positive number", scale)));
+        }
         let path = device.as_ref();
         let path_temp = path.join("temp");
-        if !path_temp.try_exists()? {
+        if !sysfs.exists(&path_temp)? {
             return Err(IOError::new(IOErrorKind::NotFound, format!("{}", path_temp.display())));
         }
         let path_offset = {
             let path_offset = path.join("offset");
-            match path_offset.try_exists() {
+            match sysfs.exists(&path_offset) {
                 Ok(true) => Some(path_offset),
                 Ok(false) => None,
-                Err(e) => None,
+                Err(_e) => None,
             }
         };
         Ok(
             SensorDevice {
-                path_temp,
-                path_offset,
+                temp: Attribute::new(sysfs.clone(), path_temp, false),
+                offset: path_offset.map(|path| Attribute::new(sysfs.clone(), path, false)),
+                sysfs,
+                device: path.to_path_buf(),
+                scale,
             }
         )
     }
 
-    pub fn get(&self) -> Result<f32, IOError> {
-        let mut buf = [0u8; 8];
-        let temp = {
-            let mut ifile = File::open(&self.path_temp)?;
-            let len = ifile.read(&mut buf)?;
-            if len == 0 {
-                return Err(IOError::new(IOErrorKind::UnexpectedEof, "empty file: temp"));
-            }
-            if len > 8 {
-                return Err(IOError::new(IOErrorKind::InvalidData, "too long file: temp"));
+    /// writes the zone's `offset` in raw units, millidegrees for thermal zones, and reads it back;
+    /// `NotFound` when the zone has no `offset` file
+    pub fn set_offset(&mut self, offset: i32) -> Result<(), IOError> {
+        let path = self.device.join("offset");
+        let attribute = self.offset
+            .as_mut()
+            .ok_or_else(|| IOError::new(IOErrorKind::NotFound, format!("{}: no offset file", path.display())))?;
+        self.sysfs.write(&path, offset.to_string().as_str())?;
+        let actual = Self::read_value(attribute, "offset")?;
+        if actual != offset as f64 {
+            return Err(IOError::new(IOErrorKind::InvalidData, format!("{}: wrote {}, read back {}", path.display(), offset, actual)));
+        }
+        Ok(())
+    }
+
+    /// the temperature of the zone's first `trip_point_N_type` of `kind`, like `critical` or `hot`, in degrees
+    /// Celsius; `None` when the zone has no such trip point
+    pub fn read_trip(&self, kind: &str) -> Result<Option<f32>, IOError> {
+        let mut n = 0;
+        loop {
+            let path_type = self.device.join(format!("trip_point_{}_type", n));
+            if !self.sysfs.exists(&path_type)? {
+                return Ok(None);
             }
-            let (temp, i) = Self::parse(&buf[..len]);
-            if i == 0 {
-                return Err(IOError::new(IOErrorKind::InvalidData, "invalid file: temp"));
+            let trip_type = Attribute::new(self.sysfs.clone(), path_type.clone(), false).read_to_string()
+                .map_err(|e| IOError::new(e.kind(), format!("{}: {}", path_type.display(), e)))?;
+            if trip_type == kind {
+                let path_temp = self.device.join(format!("trip_point_{}_temp", n));
+                let mut temp = Attribute::new(self.sysfs.clone(), path_temp.clone(), false);
+                let temp = Self::read_value(&mut temp, "trip_point_temp")
+                    .map_err(|e| IOError::new(e.kind(), format!("{}: {}", path_temp.display(), e)))?;
+                return Ok(Some(temp as f32 / self.scale));
             }
-            temp
-        };
-        let offset = {
-            if let Some(path_offset) = self.path_offset.as_ref() {
-                let mut ifile = File::open(path_offset)?;
-                let len = ifile.read(&mut buf)?;
-                if len == 0 {
-                    return Err(IOError::new(IOErrorKind::UnexpectedEof, "empty file: offset"));
-                }
-                if len > 8 {
-                    return Err(IOError::new(IOErrorKind::InvalidData, "too long file: offset"));
-                }
-                let (offset, i) = Self::parse(&buf[..len]);
-                if i == 0 {
-                    return Err(IOError::new(IOErrorKind::InvalidData, "invalid file: offset"));
+            n += 1;
+        }
+    }
+
+    /// retries transient failures (an interrupted or empty read); `InvalidData` is returned at once
+    pub fn get(&mut self) -> Result<f32, IOError> {
+        let mut attempt = 1;
+        loop {
+            match self.read() {
+                Err(e) if attempt < Self::RETRY_ATTEMPTS && matches!(e.kind(), IOErrorKind::Interrupted | IOErrorKind::UnexpectedEof) => {
+                    log::debug!("sensor read failed, retrying: attempt={}, error={:?}", attempt, e);
+                    attempt += 1;
+                    thread::sleep(Self::RETRY_DELAY);
                 }
-                offset
-            } else {
-                0
+                result => return result,
             }
+        }
+    }
+
+    fn read(&mut self) -> Result<f32, IOError> {
+        let temp = Self::read_value(&mut self.temp, "temp")?;
+        let offset = match self.offset.as_mut() {
+            Some(offset) => Self::read_value(offset, "offset")?,
+            None => 0.0,
         };
-        Ok( (temp - offset) as f32 / Self::FACTOR )
+        Ok( (temp - offset) as f32 / self.scale )
+    }
+
+    fn read_value(attribute: &mut Attribute, name: &str) -> Result<f64, IOError> {
+        // one spare byte tells a value of exactly `MAX_WIDTH` bytes from a longer one
+        let mut buf = [0u8; Self::MAX_WIDTH + 1];
+        let len = attribute.read(&mut buf)?;
+        if len == 0 {
+            return Err(IOError::new(IOErrorKind::UnexpectedEof, format!("empty file: {}", name)));
+        }
+        if len > Self::MAX_WIDTH {
+            return Err(IOError::new(IOErrorKind::InvalidData, format!("too long file: {}", name)));
+        }
+        Self::parse(&buf[..len]).ok_or_else(|| IOError::new(IOErrorKind::InvalidData, format!("invalid file: {}", name)))
     }
 
-    fn parse(buf: &[u8]) -> (u32, usize) {
-        let mut i = 0;
-        let mut num = 0;
+    /// fast byte-scan for plain integers, after leading whitespace and an optional `+` or `-` as some drivers
+    /// write them (` +42000`); falls back to `f64::from_str` for forms like `4.55e4`
+    fn parse(buf: &[u8]) -> Option<f64> {
+        let mut i = buf.iter().take_while(|c| c.is_ascii_whitespace()).count();
+        let negative = match buf.get(i) {
+            Some(b'+') => { i += 1; false }
+            Some(b'-') => { i += 1; true }
+            _ => false,
+        };
+        let start = i;
+        // `None` once the digits overflow, which leaves the value to the fallback
+        let mut num = Some(0u64);
         while i < buf.len() {
             let c = buf[i];
-            if c < b'0' || c > b'9' {
+            if !c.is_ascii_digit() {
                 break;
             }
-            num = num * 10 + (c - b'0') as u32;
+            num = num.and_then(|num| num.checked_mul(10)).and_then(|num| num.checked_add((c - b'0') as u64));
             i += 1;
         }
-        (num, i)
+        let num = num.filter(|_| i > start).map(|num| if negative { -(num as f64) } else { num as f64 });
+        if num.is_some() && buf[i..].iter().all(|c| c.is_ascii_whitespace()) {
+            return num;
+        }
+        match std::str::from_utf8(buf).ok().and_then(|s| s.trim().parse::<f64>().ok()) {
+            Some(value) if value.is_finite() => Some(value),
+            _ if num.is_some() => num,
+            _ => None,
+        }
+    }
+}
+
+impl SensorSource for SensorDevice {
+
+    fn get(&mut self) -> Result<f32, IOError> {
+        SensorDevice::get(self)
+    }
+
+    fn set_offset(&mut self, offset: i32) -> Result<(), IOError> {
+        SensorDevice::set_offset(self, offset)
+    }
+}
+
+
+/// runs `sh -c COMMAND` on every read and parses its stdout like a `temp` file, e.g. for temperatures only `nvme-cli` can read
+#[derive(Debug)]
+pub struct CommandSensor {
+    command: String,
+    /// raw units per degree Celsius
+    scale: f32,
+    timeout: Duration,
+}
+
+impl CommandSensor {
+
+    const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+    /// `scale` as for `SensorDevice::new`; a command still running after `timeout` is killed with its process group
+    pub fn new(command: impl Into<String>, scale: f32, timeout: Duration) -> Result<Self, IOError> {
+        if !(scale > 0.0 && scale.is_finite()) {
+            return Err(IOError::new(IOErrorKind::InvalidData, format!("invalid value `{}` for sensor_scale: not a positive number", scale)));
+        }
+        let command = command.into();
+        if command.trim().is_empty() {
+            return Err(IOError::new(IOErrorKind::InvalidData, "invalid value `` for sensor_command: empty command"));
+        }
+        Ok(CommandSensor { command, scale, timeout })
+    }
+
+    pub fn get(&mut self) -> Result<f32, IOError> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(self.command.as_str())
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .process_group(0)
+            .spawn()?;
+        let status = match self.wait(&mut child) {
+            Ok(status) => status,
+            Err(e) => {
+                // the whole group, so a pipeline does not leave its children behind
+                unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) };
+                let _ = child.wait();
+                return Err(e);
+            }
+        };
+        if !status.success() {
+            return Err(IOError::new(IOErrorKind::InvalidData, format!("sensor_command failed: {}", status)));
+        }
+        let mut buf = Vec::new();
+        if let Some(stdout) = child.stdout.take() {
+            // one spare byte tells an output of exactly `MAX_WIDTH` bytes from a longer one, as for sysfs
+            stdout.take(SensorDevice::MAX_WIDTH as u64 + 1).read_to_end(&mut buf)?;
+        }
+        if buf.iter().all(|c| c.is_ascii_whitespace()) {
+            return Err(IOError::new(IOErrorKind::UnexpectedEof, "empty output: sensor_command"));
+        }
+        if buf.len() > SensorDevice::MAX_WIDTH {
+            return Err(IOError::new(IOErrorKind::InvalidData, "too long output: sensor_command"));
+        }
+        let value = SensorDevice::parse(&buf).ok_or_else(|| IOError::new(IOErrorKind::InvalidData, "invalid output: sensor_command"))?;
+        Ok( value as f32 / self.scale )
     }
-}
\ No newline at end of file
+
+    /// the output is read after the exit, so it must fit in the pipe buffer; a temperature always does
+    fn wait(&self, child: &mut Child) -> Result<ExitStatus, IOError> {
+        let start = Instant::now();
+        loop {
+            if let Some(status) = child.try_wait()? {
+                return Ok(status);
+            }
+            if start.elapsed() >= self.timeout {
+                return Err(IOError::new(IOErrorKind::TimedOut, format!("sensor_command timed out after {}ms", self.timeout.as_millis())));
+            }
+            thread::sleep(Self::POLL_INTERVAL);
+        }
+    }
+}
+
+impl SensorSource for CommandSensor {
+
+    fn get(&mut self) -> Result<f32, IOError> {
+        CommandSensor::get(self)
+    }
+}
+
+
+/// thermal cooling device, like `/sys/class/thermal/cooling_device0`
+#[derive(Debug)]
+pub struct CoolingDevice {
+    cur_state: Attribute,
+}
+
+impl CoolingDevice {
+
+    pub fn new(device: impl AsRef<Path>) -> Result<Self, IOError> {
+        Self::with_sysfs(Arc::new(RealSysfs), device)
+    }
+
+    /// like `new`, through `sysfs`, e.g. a `MemorySysfs` in tests
+    pub fn with_sysfs(sysfs: Arc<dyn Sysfs>, device: impl AsRef<Path>) -> Result<Self, IOError> {
+        let path_cur_state = device.as_ref().join("cur_state");
+        if !sysfs.exists(&path_cur_state)? {
+            return Err(IOError::new(IOErrorKind::NotFound, format!("{}", path_cur_state.display())));
+        }
+        Ok(CoolingDevice { cur_state: Attribute::new(sysfs, path_cur_state, false) })
+    }
+
+    /// whether the kernel currently engages this device, i.e. `cur_state` is nonzero
+    pub fn is_active(&mut self) -> Result<bool, IOError> {
+        let state: u64 = self.cur_state
+            .read_to_string()?
+            .parse()
+            .map_err(|_e| IOError::new(IOErrorKind::InvalidData, "invalid file: cur_state"))?;
+        Ok(state != 0)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::io::Seek;
+    use std::io::SeekFrom;
+    use std::io::Write;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+
+    use crate::sysfs::MemorySysfs;
+    use crate::sysfs::SysfsFile;
+
+    use super::*;
+
+    /// fails the next `interrupts` reads with EINTR, then reads through, with `trickle` one byte per call
+    #[derive(Debug, Default)]
+    struct FlakySysfs {
+        inner: MemorySysfs,
+        interrupts: Arc<AtomicUsize>,
+        trickle: Arc<AtomicBool>,
+    }
+
+    #[derive(Debug)]
+    struct FlakyFile {
+        inner: Box<dyn SysfsFile>,
+        interrupts: Arc<AtomicUsize>,
+        trickle: Arc<AtomicBool>,
+    }
+
+    impl Sysfs for FlakySysfs {
+
+        fn exists(&self, path: &Path) -> Result<bool, IOError> {
+            self.inner.exists(path)
+        }
+
+        fn open(&self, path: &Path, writable: bool) -> Result<Box<dyn SysfsFile>, IOError> {
+            Ok(Box::new(FlakyFile { inner: self.inner.open(path, writable)?, interrupts: self.interrupts.clone(), trickle: self.trickle.clone() }))
+        }
+
+        fn write(&self, path: &Path, value: &str) -> Result<(), IOError> {
+            self.inner.write(path, value)
+        }
+    }
+
+    impl Read for FlakyFile {
+
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, IOError> {
+            match self.interrupts.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1)) {
+                Ok(_) => Err(IOError::from_raw_os_error(libc::EINTR)),
+                Err(_) if self.trickle.load(Ordering::Relaxed) => {
+                    let len = buf.len().min(1);
+                    self.inner.read(&mut buf[..len])
+                }
+                Err(_) => self.inner.read(buf),
+            }
+        }
+    }
+
+    impl Write for FlakyFile {
+
+        fn write(&mut self, buf: &[u8]) -> Result<usize, IOError> {
+            self.inner.write(buf)
+        }
+
+        fn flush(&mut self) -> Result<(), IOError> {
+            self.inner.flush()
+        }
+    }
+
+    impl Seek for FlakyFile {
+
+        fn seek(&mut self, pos: SeekFrom) -> Result<u64, IOError> {
+            self.inner.seek(pos)
+        }
+    }
+
+    impl SysfsFile for FlakyFile {
+
+        fn set_len(&mut self, len: u64) -> Result<(), IOError> {
+            self.inner.set_len(len)
+        }
+    }
+
+    const ZONE: &str = "/sys/class/thermal/thermal_zone0";
+
+    /// a thermal zone whose `temp` holds `content`
+    fn sensor(content: &str, scale: f32) -> SensorDevice {
+        let sysfs = MemorySysfs::new();
+        sysfs.set(format!("{}/temp", ZONE), content);
+        SensorDevice::with_sysfs(Arc::new(sysfs), ZONE, scale).unwrap()
+    }
+
+    #[test]
+    fn scientific_notation() {
+        assert_eq!(sensor("4.55e4\n", 1000.0).get().unwrap(), 45.5);
+        assert_eq!(SensorDevice::parse(b"4.55e4\n"), Some(45500.0));
+        assert_eq!(SensorDevice::parse(b"4.2E+4"), Some(42000.0));
+    }
+    #[test]
+    fn interrupted_read_is_retried() {
+        let sysfs = Arc::new(FlakySysfs::default());
+        sysfs.inner.set(format!("{}/temp", ZONE), "42000\n");
+        let mut sensor = SensorDevice::with_sysfs(sysfs.clone(), ZONE, 1000.0).unwrap();
+        sysfs.interrupts.store(1, Ordering::Relaxed);
+        assert_eq!(sensor.get().unwrap(), 42.0);
+        sysfs.interrupts.store(SensorDevice::RETRY_ATTEMPTS as usize - 1, Ordering::Relaxed);
+        assert_eq!(sensor.get().unwrap(), 42.0);
+        // the error surfaces once the attempts are used up
+        sysfs.interrupts.store(SensorDevice::RETRY_ATTEMPTS as usize, Ordering::Relaxed);
+        assert_eq!(sensor.get().unwrap_err().kind(), IOErrorKind::Interrupted);
+        assert_eq!(sensor.get().unwrap(), 42.0);
+    }
+    #[test]
+    fn configurable_scale() {
+        assert_eq!(sensor("4212\n", 100.0).get().unwrap(), 42.12);
+        assert_eq!(sensor("42\n", 1.0).get().unwrap(), 42.0);
+        for scale in [0.0, -1000.0, f32::NAN] {
+            let sysfs = MemorySysfs::new();
+            sysfs.set(format!("{}/temp", ZONE), "42000");
+            assert_eq!(SensorDevice::with_sysfs(Arc::new(sysfs), ZONE, scale).unwrap_err().kind(), IOErrorKind::InvalidData);
+        }
+    }
+    #[test]
+    fn one_byte_reads() {
+        let sysfs = Arc::new(FlakySysfs::default());
+        sysfs.trickle.store(true, Ordering::Relaxed);
+        sysfs.inner.set(format!("{}/temp", ZONE), "-12345\n");
+        let mut sensor = SensorDevice::with_sysfs(sysfs.clone(), ZONE, 1000.0).unwrap();
+        assert_eq!(sensor.get().unwrap(), -12.345);
+        sysfs.inner.set(format!("{}/temp", ZONE), "4.55e4\n");
+        assert_eq!(sensor.get().unwrap(), 45.5);
+        // one byte over MAX_WIDTH is still told apart
+        sysfs.inner.set(format!("{}/temp", ZONE), "1".repeat(SensorDevice::MAX_WIDTH + 1));
+        assert_eq!(sensor.get().unwrap_err().kind(), IOErrorKind::InvalidData);
+    }
+    #[test]
+    fn leading_space_and_plus() {
+        assert_eq!(sensor(" 42000\n", 1000.0).get().unwrap(), 42.0);
+        assert_eq!(sensor("+42000\n", 1000.0).get().unwrap(), 42.0);
+        assert_eq!(sensor(" +42000\n", 1000.0).get().unwrap(), 42.0);
+        assert_eq!(sensor("-5000\n", 1000.0).get().unwrap(), -5.0);
+        for invalid in ["\n", " \n", "+\n", "abc\n", "+-1\n"] {
+            assert_eq!(sensor(invalid, 1000.0).get().unwrap_err().kind(), IOErrorKind::InvalidData, "{:?}", invalid);
+        }
+    }
+    #[test]
+    fn long_digit_strings() {
+        assert_eq!(SensorDevice::parse(b"99999999999\n"), Some(99999999999.0));
+        assert_eq!(sensor("99999999999\n", 1000.0).get().unwrap(), 99999999.999);
+        assert_eq!(SensorDevice::parse(b"-18446744073709551615"), Some(-18446744073709551615.0));
+        // past u64 the digits go to the f64 fallback instead of wrapping
+        assert_eq!(SensorDevice::parse(b"123456789012345678901234"), Some(123456789012345678901234.0));
+        assert_eq!(SensorDevice::parse(b"123456789012345678901234x"), None);
+    }
+    #[test]
+    fn command_output_is_capped() {
+        let command = |command: &str| CommandSensor::new(command, 1000.0, Duration::from_secs(5)).unwrap().get();
+        assert_eq!(command("echo 42000").unwrap(), 42.0);
+        assert_eq!(command("echo 99999999999").unwrap(), 99999999.999);
+        assert_eq!(command("printf %s 1234567890123456789012345").unwrap_err().kind(), IOErrorKind::InvalidData);
+        assert_eq!(command("echo; echo").unwrap_err().kind(), IOErrorKind::UnexpectedEof);
+    }
+}
@@ -1,24 +1,34 @@
+use std::fmt;
+use std::io;
 use std::mem;
 use std::ptr;
+use std::str::FromStr;
 use std::sync::PoisonError;
+use std::sync::atomic::AtomicBool;
 use std::sync::atomic::AtomicU64;
 use std::sync::atomic::Ordering;
 use std::sync::Condvar;
 use std::sync::Mutex;
 use std::time::Duration;
+use std::time::Instant;
 
 use libc::c_int;
 use libc::sigaction;
 use libc::sighandler_t;
 
 lazy_static::lazy_static! {
-    pub(self) static ref CVAR: Condvar = Condvar::new();
-    pub(self) static ref MUTEX: Mutex<bool> = Mutex::new(false);
+    static ref CVAR: Condvar = Condvar::new();
+    static ref MUTEX: Mutex<bool> = Mutex::new(false);
 }
-pub(self) static MASK: AtomicU64 = AtomicU64::new(0);
+static MASK: AtomicU64 = AtomicU64::new(0);
+/// set by `wake` until `wait` returns `WAKE`
+static WOKEN: AtomicBool = AtomicBool::new(false);
 
-pub(self) extern "C" fn handler(sig: c_int) {
-    if sig <= 0 || sig >= 64 {
+/// returned by `wait` after `wake`; not a signal number
+pub const WAKE: c_int = -1;
+
+extern "C" fn handler(sig: c_int) {
+    if sig <= 0 || sig >= u64::BITS as c_int {
         return;
     }
     let mask = 0x1u64 << sig;
@@ -27,6 +37,103 @@ pub(self) extern "C" fn handler(sig: c_int) {
 }
 
 
+/// signals by name without the `SIG` prefix
+const NAMES: &[(&str, c_int)] = &[
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("ILL", libc::SIGILL),
+    ("TRAP", libc::SIGTRAP),
+    ("ABRT", libc::SIGABRT),
+    ("BUS", libc::SIGBUS),
+    ("FPE", libc::SIGFPE),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("SEGV", libc::SIGSEGV),
+    ("USR2", libc::SIGUSR2),
+    ("PIPE", libc::SIGPIPE),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("CHLD", libc::SIGCHLD),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+    ("TTIN", libc::SIGTTIN),
+    ("TTOU", libc::SIGTTOU),
+    ("URG", libc::SIGURG),
+    ("WINCH", libc::SIGWINCH),
+    ("PWR", libc::SIGPWR),
+];
+
+/// signals that cannot be caught, or that are raised by faults and job control rather than sent to ask for something
+const RESERVED: &[c_int] = &[
+    libc::SIGKILL,
+    libc::SIGSTOP,
+    libc::SIGILL,
+    libc::SIGTRAP,
+    libc::SIGABRT,
+    libc::SIGBUS,
+    libc::SIGFPE,
+    libc::SIGSEGV,
+    libc::SIGPIPE,
+    libc::SIGCHLD,
+    libc::SIGCONT,
+    libc::SIGTSTP,
+    libc::SIGTTIN,
+    libc::SIGTTOU,
+];
+
+/// a signal that can be mapped to an action: a name like `SIGUSR2` or `USR2`, `SIGRTMIN+N`, or a number
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Signal(pub c_int);
+
+impl FromStr for Signal {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", reason, s));
+        let name = s.strip_prefix("SIG").unwrap_or(s);
+        let signum = if let Ok(signum) = s.parse::<c_int>() {
+            signum
+        } else if let Some(&(_, signum)) = NAMES.iter().find(|(n, _)| *n == name) {
+            signum
+        } else if let Some(offset) = name.strip_prefix("RTMIN") {
+            let offset = match offset.strip_prefix('+') {
+                Some(offset) => offset.parse::<c_int>().map_err(|_e| invalid("invalid signal"))?,
+                None if offset.is_empty() => 0,
+                None => return Err(invalid("invalid signal")),
+            };
+            if offset > libc::SIGRTMAX() - libc::SIGRTMIN() {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("real-time signal out of range: {}", s)));
+            }
+            libc::SIGRTMIN() + offset
+        } else {
+            return Err(invalid("unknown signal"));
+        };
+        // the handler records pending signals in a 64 bit mask
+        if signum <= 0 || signum >= u64::BITS as c_int || signum > libc::SIGRTMAX() {
+            return Err(invalid("unknown signal"));
+        }
+        if RESERVED.contains(&signum) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("reserved signal: {}", s)));
+        }
+        Ok(Signal(signum))
+    }
+}
+
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some((name, _)) = NAMES.iter().find(|(_, signum)| *signum == self.0) {
+            write!(f, "SIG{}", name)
+        } else if self.0 >= libc::SIGRTMIN() {
+            write!(f, "SIGRTMIN+{}", self.0 - libc::SIGRTMIN())
+        } else {
+            write!(f, "{}", self.0)
+        }
+    }
+}
+
+
 pub struct SignalsWaitError {
     
 }
@@ -46,8 +153,12 @@ impl SignalsWaitError {
 }
 
 
+/// installs the handler recording `signals` for `wait`
+///
+/// # Safety
+///
 /// can only be called from main thread
-pub(crate) unsafe fn register(signals: &[c_int]) {
+pub unsafe fn register(signals: &[c_int]) {
     for &signum in signals {
         let mut action: sigaction = mem::zeroed();
         action.sa_sigaction = handler as extern "C" fn(_) as sighandler_t;
@@ -59,36 +170,82 @@ pub(crate) unsafe fn register(signals: &[c_int]) {
 }
 
 
+/// makes `wait` return `WAKE`, from any thread, e.g. when a command is queued for the main loop
+pub fn wake() {
+    let _guard = MUTEX.lock();
+    WOKEN.store(true, Ordering::Relaxed);
+    CVAR.notify_one();
+}
+
+
+/// takes the lowest pending signal recorded by the handler
+fn take_pending() -> Option<c_int> {
+    let mask = MASK.load(Ordering::Relaxed);
+    let offset = mask.trailing_zeros();
+    if offset < u64::BITS {
+        let m = 0x1u64 << offset;
+        MASK.fetch_and(!m, Ordering::Relaxed);
+        return Some(offset as c_int);
+    }
+    None
+}
+
+
+/// returns a pending signal, 0 after `timeout`, or `WAKE`; a spurious wakeup waits again for the rest of `timeout`
+///
+/// # Safety
+///
 /// can only be called from main thread
-pub(crate) unsafe fn wait(timeout: Duration) -> Result<c_int, SignalsWaitError> {
-
-    {
-        let mask = MASK.load(Ordering::Relaxed);
-        let offset = mask.trailing_zeros();
-        if offset < 32 {
-            let m = 0x1u64 << offset;
-            MASK.fetch_and(!m, Ordering::Relaxed);
-            return Ok(offset as c_int);
+pub unsafe fn wait(timeout: Duration) -> Result<c_int, SignalsWaitError> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(signum) = take_pending() {
+            return Ok(signum);
         }
-    }
-    
-    {
         let guard = MUTEX.lock()?;
-        let (guard, result) = CVAR.wait_timeout(guard, timeout)?;
-        if result.timed_out() {
+        if WOKEN.swap(false, Ordering::Relaxed) {
+            return Ok(WAKE);
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
             return Ok(0);
         }
+        let _ = CVAR.wait_timeout(guard, remaining)?;
     }
-    
-    {
-        let mask = MASK.load(Ordering::Relaxed);
-        let offset = mask.trailing_zeros();
-        if offset < 32 {
-            let m = 0x1u64 << offset;
-            MASK.fetch_and(!m, Ordering::Relaxed);
-            return Ok(offset as c_int);
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::thread;
+
+    use super::*;
+
+    /// one test, since the pending mask and the wake flag are global
+    #[test]
+    fn wait_returns_signal_wake_and_timeout() {
+        let rtmin1: Signal = "SIGRTMIN+1".parse().unwrap();
+        assert_eq!(rtmin1, Signal(libc::SIGRTMIN() + 1));
+        assert_eq!(rtmin1.to_string(), "SIGRTMIN+1");
+        unsafe {
+            register(&[rtmin1.0]);
+            assert_eq!(libc::raise(rtmin1.0), 0);
+            assert_eq!(wait(Duration::from_secs(1)).ok(), Some(rtmin1.0));
+
+            wake();
+            assert_eq!(wait(Duration::from_secs(1)).ok(), Some(WAKE));
+
+            // a notification without a signal or a wake is spurious: keep waiting until the timeout
+            let notifier = thread::spawn(|| {
+                thread::sleep(Duration::from_millis(10));
+                let _guard = MUTEX.lock();
+                CVAR.notify_one();
+            });
+            let start = Instant::now();
+            assert_eq!(wait(Duration::from_millis(100)).ok(), Some(0));
+            assert!(start.elapsed() >= Duration::from_millis(100));
+            notifier.join().unwrap();
         }
     }
-
-    Err(SignalsWaitError::unreachable())
 }
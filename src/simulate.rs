@@ -0,0 +1,109 @@
+use std::fmt;
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Error as IOError;
+use std::io::ErrorKind as IOErrorKind;
+use std::path::Path;
+use std::time::Duration;
+
+
+/// one row of a thermal profile: `<seconds>,<temperature>`
+///
+/// ```text
+/// seconds,temperature
+/// 0,42.0
+/// 5,47.5
+/// 10,55.0
+/// ```
+///
+/// a first line that does not start with a number is taken as a header
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    pub seconds: f64,
+    pub temperature: f32,
+}
+
+pub fn parse_profile(path: impl AsRef<Path>) -> Result<Vec<Sample>, IOError> {
+    let path = path.as_ref();
+    let reader = BufReader::new(File::open(path)?);
+    let invalid = |line: usize, reason: &str| IOError::new(IOErrorKind::InvalidData, format!("{}:{}: {}", path.display(), line, reason));
+    let mut samples: Vec<Sample> = Vec::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (seconds, temperature) = line.split_once(',').ok_or_else(|| invalid(i + 1, "expected `seconds,temperature`"))?;
+        let seconds: f64 = match seconds.trim().parse() {
+            Ok(seconds) => seconds,
+            Err(_e) if i == 0 => continue,
+            Err(_e) => return Err(invalid(i + 1, "invalid seconds")),
+        };
+        let temperature = temperature.trim().parse().map_err(|_e| invalid(i + 1, "invalid temperature"))?;
+        if !seconds.is_finite() || samples.last().is_some_and(|last| seconds < last.seconds) {
+            return Err(invalid(i + 1, "seconds must not go backwards"));
+        }
+        samples.push(Sample { seconds, temperature });
+    }
+    if samples.is_empty() {
+        return Err(IOError::new(IOErrorKind::InvalidData, format!("{}: no samples", path.display())));
+    }
+    Ok(samples)
+}
+
+
+/// statistics of a simulated run; each sample's output holds until the next sample
+#[derive(Debug, Default)]
+pub struct Summary {
+    samples: usize,
+    starts: usize,
+    on: bool,
+    duty_cycle: f32,
+    duration: Duration,
+    runtime: Duration,
+    /// integral of the duty cycle over `runtime`, in seconds
+    duty_seconds: f64,
+}
+
+impl Summary {
+
+    /// accounts `dt` to the output of the previous sample, then records the output of this one
+    pub fn add(&mut self, dt: Duration, on: bool, duty_cycle: f32) {
+        if self.samples > 0 {
+            self.duration += dt;
+            if self.on {
+                self.runtime += dt;
+                self.duty_seconds += self.duty_cycle as f64 * dt.as_secs_f64();
+            }
+        }
+        if on && !self.on {
+            self.starts += 1;
+        }
+        self.samples += 1;
+        self.on = on;
+        self.duty_cycle = duty_cycle;
+    }
+
+    /// time-weighted duty cycle while the fan was on
+    pub fn average_duty_cycle(&self) -> f64 {
+        if self.runtime.is_zero() { 0.0 } else { self.duty_seconds / self.runtime.as_secs_f64() }
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let share = if self.duration.is_zero() { 0.0 } else { self.runtime.as_secs_f64() / self.duration.as_secs_f64() };
+        write!(
+            f,
+            "samples={}, duration={:.1}s, starts={}, runtime={:.1}s ({:.1}%), average_duty={:.2}%",
+            self.samples,
+            self.duration.as_secs_f64(),
+            self.starts,
+            self.runtime.as_secs_f64(),
+            share * 100.0,
+            self.average_duty_cycle() * 100.0,
+        )
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::fs::File;
+use std::io::Error as IOError;
+use std::io::ErrorKind as IOErrorKind;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+
+/// content of `state_file`: when it was written and the `Control::save` line of every fan, e.g.
+///
+/// ```text
+/// time = 1700000000123
+/// fan = keep last_temperature=52.5 ...
+/// fan.cpu = off last_temperature=38
+/// ```
+///
+/// `fan` is the unnamed top-level fan, `fan.NAME` a `[fan.NAME]` section
+#[derive(Debug)]
+pub struct StateFile {
+    pub time: SystemTime,
+    pub fans: BTreeMap<String, String>,
+}
+
+impl StateFile {
+
+    pub fn new(fans: BTreeMap<String, String>) -> Self {
+        Self { time: SystemTime::now(), fans }
+    }
+
+    /// `None` when the file does not exist
+    pub fn read(path: &Path) -> Result<Option<Self>, IOError> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == IOErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let invalid = |line: usize, reason: &str| IOError::new(IOErrorKind::InvalidData, format!("{}:{}: {}", path.display(), line, reason));
+        let mut time = None;
+        let mut fans = BTreeMap::new();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line.split_once('=').ok_or_else(|| invalid(i + 1, "expected `key = value`"))?;
+            let (key, value) = (key.trim(), value.trim());
+            if key == "time" {
+                let millis = value.parse().map_err(|_e| invalid(i + 1, "invalid time"))?;
+                time = Some(UNIX_EPOCH + Duration::from_millis(millis));
+            } else if key == "fan" {
+                fans.insert(String::new(), String::from(value));
+            } else if let Some(name) = key.strip_prefix("fan.") {
+                fans.insert(String::from(name), String::from(value));
+            } else {
+                return Err(invalid(i + 1, "unknown key"));
+            }
+        }
+        let time = time.ok_or_else(|| IOError::new(IOErrorKind::InvalidData, format!("{}: missing time", path.display())))?;
+        Ok(Some(Self { time, fans }))
+    }
+
+    /// writes a temporary file next to `path` and renames it over, so a reader never sees half a file
+    pub fn write(&self, path: &Path) -> Result<(), IOError> {
+        let mut temporary = OsString::from(path.as_os_str());
+        temporary.push(".tmp");
+        let millis = self.time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+        let mut file = File::create(&temporary)?;
+        writeln!(file, "time = {}", millis)?;
+        for (name, state) in self.fans.iter() {
+            if name.is_empty() {
+                writeln!(file, "fan = {}", state)?;
+            } else {
+                writeln!(file, "fan.{} = {}", name, state)?;
+            }
+        }
+        file.sync_all()?;
+        fs::rename(&temporary, path)
+    }
+
+    /// time since the file was written; `None` when the clock went backwards
+    pub fn age(&self) -> Option<Duration> {
+        SystemTime::now().duration_since(self.time).ok()
+    }
+}
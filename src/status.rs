@@ -0,0 +1,193 @@
+use std::fmt;
+use std::fs;
+use std::io::Error as IOError;
+use std::io::ErrorKind as IOErrorKind;
+use std::io::Write;
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::unit::Temperature;
+
+
+/// latest snapshot of one fan
+#[derive(Debug, Clone)]
+pub struct Status {
+    /// empty for the unnamed top-level fan
+    pub fan: String,
+    pub temperature: f32,
+    pub duty_cycle: f32,
+    pub on: bool,
+    pub state: &'static str,
+    pub output: &'static str,
+    /// when the last cycle failed and why; cleared by a successful cycle
+    pub last_error: Option<(Instant, String)>,
+}
+
+impl Default for Status {
+    fn default() -> Self {
+        Self {
+            fan: String::new(),
+            temperature: f32::NAN,
+            duty_cycle: 0.0,
+            on: false,
+            state: "off",
+            output: "off",
+            last_error: None,
+        }
+    }
+}
+
+/// `temperature=52.30°C, duty=45.00%, on=true, state=keep, output=keep`, for the log; the JSON stays in degrees Celsius
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f, "temperature={}, duty={:.2}%, on={}, state={}, output={}",
+            Temperature(self.temperature), self.duty_cycle * 100.0, self.on, self.state, self.output
+        )
+    }
+}
+
+impl Status {
+
+    /// one-line JSON; a temperature not read yet is `null`, `last_error` is only there after a failed cycle
+    pub fn to_json(&self) -> String {
+        let temperature = if self.temperature.is_finite() {
+            format!("{:.2}", self.temperature)
+        } else {
+            String::from("null")
+        };
+        let fan = if self.fan.is_empty() {
+            String::new()
+        } else {
+            format!("\"fan\":{},", json_string(&self.fan))
+        };
+        let last_error = match self.last_error.as_ref() {
+            Some((time, message)) => format!(",\"last_error\":{},\"last_error_seconds\":{:.3}", json_string(message), time.elapsed().as_secs_f64()),
+            None => String::new(),
+        };
+        format!(
+            "{{{}\"temperature\":{},\"duty_cycle\":{:.4},\"on\":{},\"state\":\"{}\",\"output\":\"{}\"{}}}",
+            fan, temperature, self.duty_cycle, self.on, self.state, self.output, last_error
+        )
+    }
+}
+
+/// a quoted JSON string, e.g. for an error message with paths and quotes in it, or a fan name from the configuration
+fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+
+/// answers each connection on a unix stream socket with the latest `Status` of each fan, one per line, from a background thread
+#[derive(Debug)]
+pub struct StatusServer {
+    path: PathBuf,
+    status: Arc<Mutex<Vec<Status>>>,
+}
+
+impl StatusServer {
+
+    pub fn bind(path: impl AsRef<Path>) -> Result<Self, IOError> {
+        let path = path.as_ref();
+        match fs::remove_file(path) {
+            Ok(()) => log::warn!("stale status socket removed: path={}", path.display()),
+            Err(e) if e.kind() == IOErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        let listener = UnixListener::bind(path)?;
+        let status = Arc::new(Mutex::new(vec![Status::default()]));
+        let shared = status.clone();
+        thread::Builder::new()
+            .name(String::from("status"))
+            .spawn(move || serve(listener, shared))?;
+        Ok(
+            Self {
+                path: path.to_path_buf(),
+                status,
+            }
+        )
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn update(&self, status: Vec<Status>) {
+        if let Ok(mut guard) = self.status.lock() {
+            *guard = status;
+        }
+    }
+}
+
+impl Drop for StatusServer {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            log::warn!("failed to remove status socket: {:?}", e);
+        }
+    }
+}
+
+fn serve(listener: UnixListener, status: Arc<Mutex<Vec<Status>>>) {
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                log::warn!("failed to accept status connection: {:?}", e);
+                continue;
+            }
+        };
+        let lines: Vec<String> = match status.lock() {
+            Ok(guard) => guard.iter().map(Status::to_json).collect(),
+            Err(_e) => continue,
+        };
+        // a stalled client must not hold the snapshot or the thread for long
+        let _ = stream.set_write_timeout(Some(Duration::from_millis(500)));
+        if let Err(e) = writeln!(stream, "{}", lines.join("\n")) {
+            log::debug!("failed to write status: {:?}", e);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn fan_name_and_error_are_escaped() {
+        let status = Status {
+            fan: String::from("case \"top\"\\1"),
+            temperature: 42.0,
+            last_error: Some((Instant::now(), String::from("invalid value `a\"b`\nat line 2"))),
+            ..Status::default()
+        };
+        let json = status.to_json();
+        assert!(json.starts_with("{\"fan\":\"case \\\"top\\\"\\\\1\",\"temperature\":42.00,"), "{}", json);
+        assert!(json.contains(",\"last_error\":\"invalid value `a\\\"b`\\nat line 2\","), "{}", json);
+    }
+
+    #[test]
+    fn unnamed_fan_has_no_fan_key() {
+        let json = Status::default().to_json();
+        assert_eq!(json, "{\"temperature\":null,\"duty_cycle\":0.0000,\"on\":false,\"state\":\"off\",\"output\":\"off\"}");
+    }
+}
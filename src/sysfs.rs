@@ -0,0 +1,419 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::Error as IOError;
+use std::io::ErrorKind as IOErrorKind;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+
+/// file access of the sensor and pwm devices, so they can run against `MemorySysfs` instead of `/sys`
+pub trait Sysfs: fmt::Debug + Send + Sync {
+
+    fn exists(&self, path: &Path) -> Result<bool, IOError>;
+
+    /// opens an existing file, for reading and, with `writable`, writing
+    fn open(&self, path: &Path, writable: bool) -> Result<Box<dyn SysfsFile>, IOError>;
+
+    /// writes `value` to a write-only file like `export` in a single `write`
+    fn write(&self, path: &Path, value: &str) -> Result<(), IOError>;
+}
+
+pub trait SysfsFile: Read + Write + Seek + fmt::Debug + Send {
+
+    fn set_len(&mut self, len: u64) -> Result<(), IOError>;
+}
+
+
+/// the real filesystem
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealSysfs;
+
+impl Sysfs for RealSysfs {
+
+    fn exists(&self, path: &Path) -> Result<bool, IOError> {
+        path.try_exists()
+    }
+
+    fn open(&self, path: &Path, writable: bool) -> Result<Box<dyn SysfsFile>, IOError> {
+        Ok(Box::new(File::options().read(true).write(writable).open(path)?))
+    }
+
+    fn write(&self, path: &Path, value: &str) -> Result<(), IOError> {
+        File::options().write(true).open(path)?.write_all(value.as_bytes())
+    }
+}
+
+impl SysfsFile for File {
+
+    fn set_len(&mut self, len: u64) -> Result<(), IOError> {
+        File::set_len(self, len)
+    }
+}
+
+
+/// in-memory files for tests, e.g. a thermal zone `temp` set with `set` and the duty cycles a pwm
+/// wrote read back with `take_writes`; clones share the same files
+#[derive(Debug, Clone, Default)]
+pub struct MemorySysfs {
+    files: Arc<Mutex<BTreeMap<PathBuf, MemoryEntry>>>,
+}
+
+#[derive(Debug, Default)]
+struct MemoryEntry {
+    content: Vec<u8>,
+    writes: Vec<String>,
+}
+
+impl MemorySysfs {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// creates or replaces a file, without recording a write
+    pub fn set(&self, path: impl AsRef<Path>, content: impl Display) {
+        let mut files = self.files.lock().unwrap_or_else(|e| e.into_inner());
+        files.entry(path.as_ref().to_path_buf()).or_default().content = content.to_string().into_bytes();
+    }
+
+    /// content of a file, without surrounding whitespace
+    pub fn get(&self, path: impl AsRef<Path>) -> Option<String> {
+        let files = self.files.lock().unwrap_or_else(|e| e.into_inner());
+        files.get(path.as_ref()).map(|entry| String::from_utf8_lossy(&entry.content).trim().to_string())
+    }
+
+    /// values written to a file through `Sysfs::open` since the last call, oldest first
+    pub fn take_writes(&self, path: impl AsRef<Path>) -> Vec<String> {
+        let mut files = self.files.lock().unwrap_or_else(|e| e.into_inner());
+        files.get_mut(path.as_ref()).map(|entry| std::mem::take(&mut entry.writes)).unwrap_or_default()
+    }
+
+    /// removes a file, or a directory with everything below it
+    pub fn remove(&self, path: impl AsRef<Path>) {
+        let mut files = self.files.lock().unwrap_or_else(|e| e.into_inner());
+        files.retain(|file, _| !file.starts_with(path.as_ref()));
+    }
+}
+
+impl Sysfs for MemorySysfs {
+
+    /// a directory exists while any file lies below it
+    fn exists(&self, path: &Path) -> Result<bool, IOError> {
+        let files = self.files.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(files.keys().any(|file| file.starts_with(path)))
+    }
+
+    fn open(&self, path: &Path, writable: bool) -> Result<Box<dyn SysfsFile>, IOError> {
+        let files = self.files.lock().unwrap_or_else(|e| e.into_inner());
+        if !files.contains_key(path) {
+            return Err(IOError::new(IOErrorKind::NotFound, format!("{}", path.display())));
+        }
+        Ok(Box::new(MemoryFile { files: self.files.clone(), path: path.to_path_buf(), writable, position: 0 }))
+    }
+
+    fn write(&self, path: &Path, value: &str) -> Result<(), IOError> {
+        self.open(path, true)?.write_all(value.as_bytes())
+    }
+}
+
+#[derive(Debug)]
+struct MemoryFile {
+    files: Arc<Mutex<BTreeMap<PathBuf, MemoryEntry>>>,
+    path: PathBuf,
+    writable: bool,
+    position: u64,
+}
+
+impl MemoryFile {
+
+    /// the entry, or NotFound once the file was removed, like a sysfs attribute of a gone device
+    fn with_entry<T>(&self, f: impl FnOnce(&mut MemoryEntry) -> T) -> Result<T, IOError> {
+        let mut files = self.files.lock().unwrap_or_else(|e| e.into_inner());
+        match files.get_mut(&self.path) {
+            Some(entry) => Ok(f(entry)),
+            None => Err(IOError::new(IOErrorKind::NotFound, format!("{}", self.path.display()))),
+        }
+    }
+}
+
+impl Read for MemoryFile {
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, IOError> {
+        let position = self.position as usize;
+        let len = self.with_entry(|entry| {
+            let available = entry.content.get(position..).unwrap_or_default();
+            let len = available.len().min(buf.len());
+            buf[..len].copy_from_slice(&available[..len]);
+            len
+        })?;
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl Write for MemoryFile {
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, IOError> {
+        if !self.writable {
+            return Err(IOError::new(IOErrorKind::PermissionDenied, format!("{}: not opened for writing", self.path.display())));
+        }
+        let position = self.position as usize;
+        self.with_entry(|entry| {
+            if entry.content.len() < position + buf.len() {
+                entry.content.resize(position + buf.len(), 0);
+            }
+            entry.content[position..position + buf.len()].copy_from_slice(buf);
+            entry.writes.push(String::from_utf8_lossy(buf).trim().to_string());
+        })?;
+        self.position += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), IOError> {
+        Ok(())
+    }
+}
+
+impl Seek for MemoryFile {
+
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, IOError> {
+        let len = self.with_entry(|entry| entry.content.len() as i64)?;
+        let position = match pos {
+            SeekFrom::Start(offset) => offset as i64,
+            SeekFrom::End(offset) => len + offset,
+            SeekFrom::Current(offset) => self.position as i64 + offset,
+        };
+        if position < 0 {
+            return Err(IOError::new(IOErrorKind::InvalidInput, "seek before the start of the file"));
+        }
+        self.position = position as u64;
+        Ok(self.position)
+    }
+}
+
+impl SysfsFile for MemoryFile {
+
+    fn set_len(&mut self, len: u64) -> Result<(), IOError> {
+        self.with_entry(|entry| entry.content.resize(len as usize, 0))
+    }
+}
+
+
+/// sysfs attribute kept open between accesses, opened on first use; when the handle went stale, e.g. the device
+/// was removed and added again, it is dropped and the access is retried once on a freshly opened file
+#[derive(Debug)]
+pub(crate) struct Attribute {
+    sysfs: Arc<dyn Sysfs>,
+    path: PathBuf,
+    writable: bool,
+    file: Option<Box<dyn SysfsFile>>,
+}
+
+impl Attribute {
+
+    pub fn new(sysfs: Arc<dyn Sysfs>, path: PathBuf, writable: bool) -> Self {
+        Attribute {
+            sysfs,
+            path,
+            writable,
+            file: None,
+        }
+    }
+
+    /// rewrites the attribute from offset 0 in a single `write`, which is what sysfs expects
+    pub fn write(&mut self, value: impl Display) -> Result<(), IOError> {
+        let value = value.to_string();
+        self.access(|file| {
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(value.as_bytes())?;
+            // sysfs ignores the length, but a regular file would keep the tail of a longer value
+            let _ = file.set_len(value.len() as u64);
+            Ok(())
+        })
+    }
+
+    /// reads from offset 0 until EOF or `buf` is full, as a single `read` may return fewer bytes than available;
+    /// sysfs regenerates the content on every read from offset 0
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, IOError> {
+        self.access(|file| {
+            file.seek(SeekFrom::Start(0))?;
+            let mut len = 0;
+            while len < buf.len() {
+                match file.read(&mut buf[len..])? {
+                    0 => break,
+                    n => len += n,
+                }
+            }
+            Ok(len)
+        })
+    }
+
+    pub fn read_to_string(&mut self) -> Result<String, IOError> {
+        let mut s = String::new();
+        self.access(|file| {
+            s.clear();
+            file.seek(SeekFrom::Start(0))?;
+            file.read_to_string(&mut s)
+        })?;
+        Ok(String::from(s.trim()))
+    }
+
+    fn access<T>(&mut self, mut f: impl FnMut(&mut dyn SysfsFile) -> Result<T, IOError>) -> Result<T, IOError> {
+        if let Some(file) = self.file.as_mut() {
+            match f(file.as_mut()) {
+                Ok(value) => return Ok(value),
+                Err(e) if Self::is_stale(&e) => {
+                    log::debug!("reopening {}: {:?}", self.path.display(), e);
+                    self.file = None;
+                }
+                // e.g. EINVAL for a rejected value: the handle is fine, and retrying would write the value twice
+                Err(e) => return Err(e),
+            }
+        }
+        let mut file = self.sysfs.open(&self.path, self.writable)?;
+        let value = f(file.as_mut())?;
+        self.file = Some(file);
+        Ok(value)
+    }
+
+    /// ENOENT, ENODEV, EBADF or ESTALE: the handle no longer refers to the attribute
+    fn is_stale(e: &IOError) -> bool {
+        e.kind() == IOErrorKind::NotFound || matches!(e.raw_os_error(), Some(libc::ENOENT) | Some(libc::ENODEV) | Some(libc::EBADF) | Some(libc::ESTALE))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::sync::atomic::AtomicI32;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+
+    use super::*;
+
+    /// counts `open` calls and, while `errno` is set, fails every write with it
+    #[derive(Debug, Default)]
+    struct CountingSysfs {
+        inner: MemorySysfs,
+        opens: AtomicUsize,
+        errno: Arc<AtomicI32>,
+    }
+
+    #[derive(Debug)]
+    struct FailingFile {
+        inner: Box<dyn SysfsFile>,
+        errno: Arc<AtomicI32>,
+    }
+
+    impl Sysfs for CountingSysfs {
+
+        fn exists(&self, path: &Path) -> Result<bool, IOError> {
+            self.inner.exists(path)
+        }
+
+        fn open(&self, path: &Path, writable: bool) -> Result<Box<dyn SysfsFile>, IOError> {
+            self.opens.fetch_add(1, Ordering::Relaxed);
+            Ok(Box::new(FailingFile { inner: self.inner.open(path, writable)?, errno: self.errno.clone() }))
+        }
+
+        fn write(&self, path: &Path, value: &str) -> Result<(), IOError> {
+            self.inner.write(path, value)
+        }
+    }
+
+    impl Read for FailingFile {
+
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, IOError> {
+            self.inner.read(buf)
+        }
+    }
+
+    impl Write for FailingFile {
+
+        fn write(&mut self, buf: &[u8]) -> Result<usize, IOError> {
+            match self.errno.load(Ordering::Relaxed) {
+                0 => self.inner.write(buf),
+                errno => Err(IOError::from_raw_os_error(errno)),
+            }
+        }
+
+        fn flush(&mut self) -> Result<(), IOError> {
+            self.inner.flush()
+        }
+    }
+
+    impl Seek for FailingFile {
+
+        fn seek(&mut self, pos: SeekFrom) -> Result<u64, IOError> {
+            self.inner.seek(pos)
+        }
+    }
+
+    impl SysfsFile for FailingFile {
+
+        fn set_len(&mut self, len: u64) -> Result<(), IOError> {
+            self.inner.set_len(len)
+        }
+    }
+
+    fn attribute() -> (Arc<CountingSysfs>, Attribute) {
+        let sysfs = Arc::new(CountingSysfs::default());
+        sysfs.inner.set("/pwm0/duty_cycle", 0);
+        let attribute = Attribute::new(sysfs.clone(), PathBuf::from("/pwm0/duty_cycle"), true);
+        (sysfs, attribute)
+    }
+
+    #[test]
+    fn attribute_opens_once() {
+        let (sysfs, mut attribute) = attribute();
+        for duty_cycle in [5000, 5666, 10000, 900] {
+            attribute.write(duty_cycle).unwrap();
+            assert_eq!(attribute.read_to_string().unwrap(), duty_cycle.to_string());
+        }
+        // 8 accesses, 1 open instead of 8
+        assert_eq!(sysfs.opens.load(Ordering::Relaxed), 1);
+        assert_eq!(sysfs.inner.take_writes("/pwm0/duty_cycle"), ["5000", "5666", "10000", "900"]);
+    }
+
+    #[test]
+    fn attribute_keeps_the_handle_on_a_rejected_value() {
+        let (sysfs, mut attribute) = attribute();
+        attribute.write(5000).unwrap();
+        sysfs.errno.store(libc::EINVAL, Ordering::Relaxed);
+        assert_eq!(attribute.write(200000).unwrap_err().raw_os_error(), Some(libc::EINVAL));
+        sysfs.errno.store(0, Ordering::Relaxed);
+        attribute.write(6000).unwrap();
+        assert_eq!(sysfs.opens.load(Ordering::Relaxed), 1);
+        assert_eq!(sysfs.inner.take_writes("/pwm0/duty_cycle"), ["5000", "6000"]);
+    }
+
+    #[test]
+    fn attribute_reopens_a_stale_handle() {
+        let (sysfs, mut attribute) = attribute();
+        attribute.write(5000).unwrap();
+        sysfs.errno.store(libc::ENODEV, Ordering::Relaxed);
+        assert_eq!(attribute.write(6000).unwrap_err().raw_os_error(), Some(libc::ENODEV));
+        // retried once on a fresh handle, which failed the same way
+        assert_eq!(sysfs.opens.load(Ordering::Relaxed), 2);
+        sysfs.errno.store(0, Ordering::Relaxed);
+        attribute.write(6500).unwrap();
+        assert_eq!(sysfs.opens.load(Ordering::Relaxed), 3);
+        // a removed file, like a pwm chip during a driver reload: the handle and the reopen read NotFound
+        sysfs.inner.remove("/pwm0");
+        assert_eq!(attribute.write(7000).unwrap_err().kind(), IOErrorKind::NotFound);
+        assert_eq!(sysfs.opens.load(Ordering::Relaxed), 4);
+        sysfs.inner.set("/pwm0/duty_cycle", 0);
+        attribute.write(7000).unwrap();
+        assert_eq!(sysfs.opens.load(Ordering::Relaxed), 5);
+        assert_eq!(sysfs.inner.take_writes("/pwm0/duty_cycle"), ["7000"]);
+    }
+}
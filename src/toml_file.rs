@@ -0,0 +1,100 @@
+use std::io::Error as IOError;
+use std::io::ErrorKind as IOErrorKind;
+use std::path::Path;
+
+use toml::Table;
+use toml::Value;
+
+use crate::ini;
+use crate::ini::Ini;
+
+
+/// feeds a TOML file through `Ini::callback`, one call per key, so both formats share the same keys and checks;
+/// top-level tables are sections, arrays repeat the key, and curve points are tables of `temperature` and `duty_cycle`:
+///
+/// ```toml
+/// curve = [
+///     { temperature = 40.0, duty_cycle = 0.3 },
+///     { temperature = 70.0, duty_cycle = 0.9 },
+/// ]
+///
+/// [avoid]
+/// band = ["0.45-0.55", { low = 0.6, high = 0.65 }]
+/// ```
+pub fn parse_from_file<I: Ini>(ini: &mut I, path: impl AsRef<Path>) -> Result<(), IOError> {
+    let path = path.as_ref();
+    let content = std::fs::read_to_string(path)?;
+    let table: Table = content
+        .parse()
+        .map_err(|e| IOError::new(IOErrorKind::InvalidData, format!("{}: {}", path.display(), e)))?;
+    feed_table(ini, path, "", &table)
+}
+
+/// keys before nested tables, so sections see the values they inherit; `[fan.soc]` becomes section `fan.soc`
+fn feed_table<I: Ini>(ini: &mut I, path: &Path, section: &str, table: &Table) -> Result<(), IOError> {
+    let (tables, mut keys): (Vec<_>, Vec<_>) = table.iter().partition(|(_, value)| value.is_table());
+    // TOML keys have no order, so the unit comes first and applies to every temperature of the table
+    keys.sort_by_key(|(key, _)| key.as_str() != "temperature_unit");
+    for (key, value) in keys {
+        feed(ini, path, section, key, value)?;
+    }
+    for (key, value) in tables {
+        if let Some(table) = value.as_table() {
+            let section = if section.is_empty() { key.clone() } else { format!("{}.{}", section, key) };
+            feed_table(ini, path, section.as_str(), table)?;
+        }
+    }
+    Ok(())
+}
+
+fn feed<I: Ini>(ini: &mut I, path: &Path, section: &str, key: &str, value: &Value) -> Result<(), IOError> {
+    let values = match value {
+        Value::Array(items) if key == "curve" => {
+            let points = items
+                .iter()
+                .map(|item| point(path, item))
+                .collect::<Result<Vec<_>, _>>()?;
+            vec![points.join(", ")]
+        }
+        Value::Array(items) => items
+            .iter()
+            .map(|item| scalar(path, key, item))
+            .collect::<Result<Vec<_>, _>>()?,
+        value => vec![scalar(path, key, value)?],
+    };
+    for value in values {
+        let value = ini::expand(value.as_str()).map_err(|e| IOError::new(IOErrorKind::InvalidData, format!("{}: {}", path.display(), e)))?;
+        let line = format!("{} = {}", key, value);
+        ini.callback(path, line.as_str(), 0, section, key, Some(&value)).map_err(Into::into)?;
+    }
+    Ok(())
+}
+
+/// `{ temperature, duty_cycle }` to the ini `temperature:duty_cycle` form
+fn point(path: &Path, value: &Value) -> Result<String, IOError> {
+    match value {
+        Value::Table(table) => match (table.get("temperature"), table.get("duty_cycle")) {
+            (Some(t), Some(p)) => Ok(format!("{}:{}", scalar(path, "temperature", t)?, scalar(path, "duty_cycle", p)?)),
+            _ => Err(invalid(path, "curve", "expected a table of `temperature` and `duty_cycle`")),
+        },
+        value => scalar(path, "curve", value),
+    }
+}
+
+fn scalar(path: &Path, key: &str, value: &Value) -> Result<String, IOError> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Integer(i) => Ok(i.to_string()),
+        Value::Float(f) => Ok(f.to_string()),
+        Value::Boolean(b) => Ok(b.to_string()),
+        Value::Table(table) => match (table.get("low"), table.get("high")) {
+            (Some(low), Some(high)) => Ok(format!("{}-{}", scalar(path, key, low)?, scalar(path, key, high)?)),
+            _ => Err(invalid(path, key, "unexpected table")),
+        },
+        _ => Err(invalid(path, key, "unsupported value")),
+    }
+}
+
+fn invalid(path: &Path, key: &str, reason: &str) -> IOError {
+    IOError::new(IOErrorKind::InvalidData, format!("{}: invalid value for {}: {}", path.display(), key, reason))
+}
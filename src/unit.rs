@@ -0,0 +1,100 @@
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+
+/// unit of the temperature values in the configuration; sensors always read millidegrees Celsius
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TemperatureUnit {
+    #[default]
+    Celsius,
+    Fahrenheit,
+}
+
+impl TemperatureUnit {
+
+    /// a temperature in this unit, in degrees Celsius
+    pub fn to_celsius(self, temperature: f32) -> f32 {
+        match self {
+            TemperatureUnit::Celsius => temperature,
+            TemperatureUnit::Fahrenheit => (temperature - 32.0) * 5.0 / 9.0,
+        }
+    }
+
+    /// a difference of temperatures in this unit, like `stop_hysteresis`, in degrees Celsius
+    pub fn delta_to_celsius(self, delta: f32) -> f32 {
+        match self {
+            TemperatureUnit::Celsius => delta,
+            TemperatureUnit::Fahrenheit => delta * 5.0 / 9.0,
+        }
+    }
+}
+
+impl FromStr for TemperatureUnit {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "celsius" => Ok(TemperatureUnit::Celsius),
+            "fahrenheit" => Ok(TemperatureUnit::Fahrenheit),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown temperature unit: {}", s))),
+        }
+    }
+}
+
+
+static LOG_FAHRENHEIT: AtomicBool = AtomicBool::new(false);
+
+/// shows the temperatures in the log in degrees Fahrenheit instead of Celsius
+pub fn set_log_fahrenheit(fahrenheit: bool) {
+    LOG_FAHRENHEIT.store(fahrenheit, Ordering::Relaxed);
+}
+
+/// a temperature in degrees Celsius, shown like `52.30°C`, or `126.14°F` with `log_fahrenheit`
+#[derive(Debug, Clone, Copy)]
+pub struct Temperature(pub f32);
+
+impl fmt::Display for Temperature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if LOG_FAHRENHEIT.load(Ordering::Relaxed) {
+            write!(f, "{:.2}°F", self.0 * 9.0 / 5.0 + 32.0)
+        } else {
+            write!(f, "{:.2}°C", self.0)
+        }
+    }
+}
+
+/// a difference of temperatures in degrees Celsius, shown like `Temperature` but without the offset
+#[derive(Debug, Clone, Copy)]
+pub struct TemperatureDelta(pub f32);
+
+impl fmt::Display for TemperatureDelta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if LOG_FAHRENHEIT.load(Ordering::Relaxed) {
+            write!(f, "{:.2}°F", self.0 * 9.0 / 5.0)
+        } else {
+            write!(f, "{:.2}°C", self.0)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn fahrenheit_to_celsius() {
+        let unit: TemperatureUnit = "fahrenheit".parse().unwrap();
+        assert_eq!(unit.to_celsius(32.0), 0.0);
+        assert_eq!(unit.to_celsius(158.0), 70.0);
+        assert_eq!(unit.to_celsius(-40.0), -40.0);
+        assert_eq!(unit.delta_to_celsius(9.0), 5.0);
+        assert_eq!(TemperatureUnit::Celsius.to_celsius(70.0), 70.0);
+        assert_eq!(TemperatureUnit::Celsius.delta_to_celsius(5.0), 5.0);
+        assert!("kelvin".parse::<TemperatureUnit>().is_err());
+    }
+}
@@ -0,0 +1,94 @@
+use std::io::Error as IOError;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::pwm::FanOutput;
+
+
+/// pwm handle of one fan, opened separately from the one of the control loop
+pub struct WatchdogOutput {
+    pub prefix: String,
+    pub pwm: Box<dyn FanOutput + Send>,
+    /// written to `duty_cycle` when the loop stalls
+    pub duty_cycle: u32,
+}
+
+
+/// forces every output to its `duty_cycle` once the control loop has not called `feed` for `timeout`
+#[derive(Debug)]
+pub struct Watchdog {
+    heartbeat: Arc<AtomicU64>,
+    fired: Arc<AtomicBool>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Watchdog {
+
+    pub fn spawn(timeout: Duration, outputs: Vec<WatchdogOutput>) -> Result<Self, IOError> {
+        let heartbeat = Arc::new(AtomicU64::new(0));
+        let fired = Arc::new(AtomicBool::new(false));
+        let stop = Arc::new(AtomicBool::new(false));
+        let shared = (heartbeat.clone(), fired.clone(), stop.clone());
+        thread::Builder::new()
+            .name(String::from("watchdog"))
+            .spawn(move || watch(timeout, shared.0, shared.1, shared.2, outputs))?;
+        Ok(Self { heartbeat, fired, stop })
+    }
+
+    pub fn feed(&self) {
+        self.heartbeat.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// whether the watchdog forced the outputs since the last call
+    pub fn take_fired(&self) -> bool {
+        self.fired.swap(false, Ordering::Relaxed)
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+fn watch(timeout: Duration, heartbeat: Arc<AtomicU64>, fired: Arc<AtomicBool>, stop: Arc<AtomicBool>, mut outputs: Vec<WatchdogOutput>) {
+    let poll = (timeout / 4).max(Duration::from_millis(10));
+    let mut last_beat = heartbeat.load(Ordering::Relaxed);
+    let mut last_seen = Instant::now();
+    let mut forced = false;
+    loop {
+        thread::sleep(poll);
+        if stop.load(Ordering::Relaxed) {
+            return;
+        }
+        let beat = heartbeat.load(Ordering::Relaxed);
+        if beat != last_beat {
+            last_beat = beat;
+            last_seen = Instant::now();
+            if forced {
+                log::warn!("control loop resumed after the watchdog fired");
+                forced = false;
+            }
+            continue;
+        }
+        if forced || last_seen.elapsed() < timeout {
+            continue;
+        }
+        forced = true;
+        fired.store(true, Ordering::Relaxed);
+        log::error!("control loop stalled for {}ms, watchdog forcing the fans to maximum", last_seen.elapsed().as_millis());
+        for output in outputs.iter_mut() {
+            let result = output.pwm
+                .set_duty_cycle(output.duty_cycle)
+                .and_then(|_| output.pwm.set_enable(true));
+            if let Err(e) = result {
+                log::error!("{}watchdog failed to force the pwm: {}", output.prefix, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use fanctrl::app::Application;
+use fanctrl::app::Args;
+use fanctrl::pwm::PWMDevice;
+use fanctrl::sensor::SensorDevice;
+use fanctrl::sysfs::MemorySysfs;
+
+const THERMAL_ZONE: &str = "/sys/class/thermal/thermal_zone0";
+const PWM_CHIP: &str = "/sys/class/pwm/pwmchip0";
+
+/// a thermal zone at `temperature` millidegrees and an unconfigured pwm0 of a two-channel chip
+fn sysfs(temperature: i32) -> MemorySysfs {
+    let sysfs = MemorySysfs::new();
+    sysfs.set(format!("{}/temp", THERMAL_ZONE), temperature);
+    sysfs.set(format!("{}/npwm", PWM_CHIP), 2);
+    sysfs.set(format!("{}/pwm0/period", PWM_CHIP), 0);
+    sysfs.set(format!("{}/pwm0/duty_cycle", PWM_CHIP), 0);
+    sysfs.set(format!("{}/pwm0/polarity", PWM_CHIP), "normal");
+    sysfs.set(format!("{}/pwm0/enable", PWM_CHIP), 0);
+    sysfs
+}
+
+fn application(sysfs: &MemorySysfs, overrides: &[(&str, &str)]) -> Application {
+    let mut args = Args::default();
+    let overrides: Vec<(String, String)> = overrides.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+    args.apply_overrides(&overrides).unwrap();
+    let shared: Arc<MemorySysfs> = Arc::new(sysfs.clone());
+    let sensor = SensorDevice::with_sysfs(shared.clone(), THERMAL_ZONE, 1000.0).unwrap();
+    let pwm = PWMDevice::with_sysfs(shared, PWM_CHIP, 0).unwrap();
+    Application::with_devices(args, Box::new(sensor), Box::new(pwm)).unwrap()
+}
+
+/// runs one cycle per temperature; returns the duty cycle and enable writes of each
+fn run(app: &mut Application, sysfs: &MemorySysfs, temperatures: &[i32]) -> Vec<(Vec<String>, Vec<String>)> {
+    temperatures.iter().map(|temperature| {
+        sysfs.set(format!("{}/temp", THERMAL_ZONE), temperature);
+        app.run_for(Duration::from_secs(5)).unwrap();
+        (sysfs.take_writes(format!("{}/pwm0/duty_cycle", PWM_CHIP)), sysfs.take_writes(format!("{}/pwm0/enable", PWM_CHIP)))
+    }).collect()
+}
+
+#[test]
+fn control_loop_writes_duty_cycles() {
+    let sysfs = sysfs(35000);
+    let mut app = application(&sysfs, &[]);
+    app.initial().unwrap();
+    assert_eq!(sysfs.take_writes(format!("{}/pwm0/period", PWM_CHIP)), ["100000"]);
+    assert_eq!(sysfs.take_writes(format!("{}/pwm0/duty_cycle", PWM_CHIP)), ["50000"]);
+    assert_eq!(sysfs.take_writes(format!("{}/pwm0/enable", PWM_CHIP)), ["1"]);
+    // rising follows the curve, falling keeps the duty cycle for lag_time_cycle = 8 cycles, then stops
+    let mut temperatures = vec![45000, 70000, 60000];
+    temperatures.resize(13, 25000);
+    let writes = run(&mut app, &sysfs, &temperatures);
+    assert_eq!(writes[0].0, ["56666"]);
+    assert_eq!(writes[1].0, ["90000"]);
+    assert!(writes[2..].iter().all(|(duty_cycles, _)| duty_cycles.is_empty()));
+    let stopped: Vec<usize> = writes.iter().enumerate().filter(|(_, (_, enable))| !enable.is_empty()).map(|(cycle, _)| cycle).collect();
+    assert_eq!(stopped, [11]);
+    assert_eq!(writes[11].1, ["0"]);
+}
@@ -0,0 +1,37 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::process::Output;
+
+const CONFIG: &str = "tests/replay/fanctrl.conf";
+const TRACE: &str = "tests/replay/trace.txt";
+
+fn replay(trace: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_fanctrl"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(["-c", CONFIG, "--replay"])
+        .arg(trace)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn trace_replays_to_its_writes() {
+    let output = replay(Path::new(TRACE));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    assert!(stdout.contains("replayed 14 cycles from tests/replay/trace.txt: 0 mismatches"), "{}", stdout);
+}
+
+#[test]
+fn changed_write_is_a_mismatch() {
+    let trace = fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join(TRACE)).unwrap();
+    let changed = std::env::temp_dir().join(format!("fanctrl-replay-{}.txt", std::process::id()));
+    fs::write(&changed, trace.replace("duty_cycle=79999", "duty_cycle=80000")).unwrap();
+    let output = replay(&changed);
+    fs::remove_file(&changed).unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!output.status.success(), "{}", stdout);
+    assert!(stdout.contains("line 10: temperature=50.00°C, expected [duty_cycle=80000], actual [duty_cycle=79999]"), "{}", stdout);
+    assert!(stdout.contains(": 1 mismatches"), "{}", stdout);
+}